@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table entries add column extra blob;      -- encoded record fields unknown to this client
+alter table workspaces add column extra blob;   -- encoded record fields unknown to this client
@@ -0,0 +1,8 @@
+-- Add migration script here
+create table if not exists quarantine (
+    id integer primary key,
+    kind text not null,                         -- The kind of the remote record, e.g. entry/workspace
+    data text unique not null,                  -- The raw payload as it came from the server
+    error text not null,                        -- Why the record could not be read
+    created_at integer not null
+);
@@ -0,0 +1,22 @@
+-- Add migration script here
+create table if not exists revisions (
+    seq integer primary key autoincrement,  -- order of the operations on this host
+    id text not null,                       -- id of the entry
+    rev integer not null,                   -- revision number of the entry, from 1
+    operation text not null,                -- the operation that replaced this state
+    local integer not null,                 -- whether the operation was made on this host
+    created_at integer not null,            -- when the operation happened
+    value text not null,                    -- the state of the entry before the operation
+    desc text,
+    data text,
+    kind text not null,
+    path text not null,
+    updated_at integer not null,
+    deleted_at integer,
+    version integer not null,
+    workspace_id text,
+    host_id text not null,
+    extra blob,
+
+    unique(id, rev)
+);
@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table revisions add column uid text;      -- id of the revision across the hosts
+create unique index if not exists revisions_uid on revisions(uid);
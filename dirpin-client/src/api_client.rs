@@ -1,99 +1,378 @@
+use crate::settings::Settings;
+use crate::tls;
 use dirpin_common::api::{
-    AddEntryRequest, AddSyncRequest, ErrorMessage, HealthCheckResponse, LoginRequest,
-    LoginResponse, LogoutResponse, RegisterRequest, RegisterResponse, SyncResponse,
-    StatusResponse
+    AddSyncRequest, ChangePasswordRequest, ClaimLinkRequest, ClaimLinkResponse, ConflictMessage,
+    CreateLinkRequest, CreateLinkResponse, DeleteAccountRequest, DigestResponse, ErrorMessage,
+    FetchRecordsRequest, HealthCheckResponse, KeyCheckRequest, KeyStatusResponse, LoginRequest,
+    LoginResponse, LogoutResponse, RegisterRequest, RegisterResponse, RejectedRecord,
+    ResetPasswordRequest, RevokeSessionsRequest, RevokeSessionsResponse, RotateKeyRequest,
+    SendPasswordResetRequest, SessionsResponse, StatusResponse, SyncResponse, VerifyRequest,
+    API_VERSION, API_VERSION_HEADER, VERSION_HEADER,
 };
-use eyre::{bail, Result};
-use reqwest::header::{HeaderMap, AUTHORIZATION};
-use reqwest::{Response, StatusCode};
+use eyre::Result;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::time::Duration;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
 
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Base delay for the exponential backoff between retries.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+/// Upper bound for a single backoff delay.
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ApiError {
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
+    #[error("Server unavailable: {0}")]
+    ServerUnavailable(String),
+
+    #[error("Version mismatch: {0}")]
+    VersionMismatch(String),
+
+    #[error("There was an error with the service: Status {status}. {message}")]
+    Other { status: StatusCode, message: String },
+}
+
+impl ApiError {
+    /// Build the error from the server status code and the `ErrorMessage` body.
+    fn from_response(status: StatusCode, message: String) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized(message),
+            StatusCode::CONFLICT => Self::Conflict(message),
+            StatusCode::UPGRADE_REQUIRED => Self::VersionMismatch(message),
+            StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => Self::ServerUnavailable(message),
+            status => Self::Other { status, message },
+        }
+    }
+
+    /// Whether it makes sense to repeat the same request later.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::ServerUnavailable(_))
+    }
+}
+
+impl From<reqwest::Error> for ApiError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_connect() || err.is_timeout() {
+            Self::ServerUnavailable(err.to_string())
+        } else {
+            Self::Other {
+                status: err.status().unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+                message: err.to_string(),
+            }
+        }
+    }
+}
+
+/// Returns true when the error chain contains an `ApiError::ServerUnavailable`.
+pub fn is_server_unavailable(err: &eyre::Report) -> bool {
+    err.chain().any(|x| {
+        matches!(
+            x.downcast_ref::<ApiError>(),
+            Some(ApiError::ServerUnavailable(_))
+        )
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub connect_timeout: Duration,
+    pub timeout: Duration,
+    /// How many times to repeat an idempotent request when the server is unavailable.
+    pub retries: u32,
+    /// PEM bundle of the CAs to trust instead of the system ones
+    pub tls_ca: Option<String>,
+    /// SHA-256 fingerprint of the only server certificate to trust
+    pub tls_pin: Option<String>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            tls_ca: None,
+            tls_pin: None,
+        }
+    }
+}
+
+impl From<&Settings> for ClientOptions {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(settings.network_connect_timeout),
+            timeout: Duration::from_secs(settings.network_timeout),
+            retries: settings.network_retries,
+            tls_ca: settings.tls_ca.clone(),
+            tls_pin: settings.tls_pin.clone(),
+        }
+    }
+}
+
+fn build_client(options: &ClientOptions, mut headers: HeaderMap) -> Result<reqwest::Client> {
+    headers.insert(VERSION_HEADER, HeaderValue::from_static(VERSION));
+    headers.insert(API_VERSION_HEADER, HeaderValue::from(API_VERSION));
+
+    let mut builder = reqwest::Client::builder()
+        .user_agent(format!("dirpin/{VERSION}"))
+        .default_headers(headers)
+        .connect_timeout(options.connect_timeout)
+        .timeout(options.timeout);
+    if let Some(config) = tls::client_config(options.tls_ca.as_deref(), options.tls_pin.as_deref())?
+    {
+        builder = builder.use_preconfigured_tls(config);
+    }
+
+    Ok(builder.build()?)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(RETRY_MAX_DELAY)
+}
+
+/// Send the request and repeat it with exponential backoff while the server is unavailable.
+/// Only use this for idempotent requests.
+async fn send_with_retry<F>(retries: u32, request: F) -> Result<Response, ApiError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let mut attempt = 0;
+    loop {
+        let res = match request().send().await {
+            Ok(res) => handle_response_error(res).await,
+            Err(err) => Err(ApiError::from(err)),
+        };
+
+        match res {
+            Err(err) if err.is_retryable() && attempt < retries => {
+                tracing::debug!("request failed, retrying: {err}");
+                tokio::time::sleep(backoff(attempt)).await;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+async fn send(request: RequestBuilder) -> Result<Response, ApiError> {
+    let res = request.send().await?;
+    handle_response_error(res).await
+}
+
 pub struct AuthClient<'a> {
     address: &'a str,
     client: reqwest::Client,
+    retries: u32,
 }
 
 impl<'a> AuthClient<'a> {
     pub fn new(address: &'a str, session_token: &str) -> Result<Self> {
+        Self::with_options(address, session_token, &ClientOptions::default())
+    }
+
+    pub fn with_options(
+        address: &'a str,
+        session_token: &str,
+        options: &ClientOptions,
+    ) -> Result<Self> {
         let mut headers = HeaderMap::new();
         headers.insert(AUTHORIZATION, format!("Token {}", session_token).parse()?);
-        // TODO add user agents;
-        // TODO: add version header;
-        // TODO: connection timeout
-        // TODO: timeout
 
         Ok(Self {
             address,
-            client: reqwest::Client::builder()
-                // .user_agent()
-                .default_headers(headers)
-                .build()?,
+            client: build_client(options, headers)?,
+            retries: options.retries,
         })
     }
 
     pub async fn logout(&self) -> Result<LogoutResponse> {
         let url = format!("{}/logout", self.address);
-        let res = self.client.get(url).send().await?;
-        let res = handle_response_error(res).await?;
+        let res = send(self.client.get(url)).await?;
         let res = res.json::<LogoutResponse>().await?;
 
         Ok(res)
     }
 
+    /// Set a new password. Returns the number of other hosts the server logged out.
+    pub async fn change_password(&self, data: &ChangePasswordRequest) -> Result<u64> {
+        let url = format!("{}/account/password", self.address);
+        let res = send(self.client.post(url).json(data)).await?;
+        let res = res.json::<RevokeSessionsResponse>().await?;
+
+        Ok(res.revoked)
+    }
+
+    /// Remove the account and all its data from the server.
+    pub async fn delete_account(&self, data: &DeleteAccountRequest) -> Result<()> {
+        let url = format!("{}/account/delete", self.address);
+        send(self.client.post(url).json(data)).await?;
+
+        Ok(())
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<()> {
+        let url = format!("{}/verify", self.address);
+        send(self.client.post(url).json(&VerifyRequest {
+            token: token.into(),
+        }))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Ask the server to mail a new verification code.
+    pub async fn send_verification(&self) -> Result<()> {
+        let url = format!("{}/verify/send", self.address);
+        send(self.client.post(url)).await?;
+
+        Ok(())
+    }
+
+    pub async fn sessions(&self) -> Result<SessionsResponse> {
+        let url = format!("{}/sessions", self.address);
+        let res = send_with_retry(self.retries, || self.client.get(&url)).await?;
+        let res = res.json::<SessionsResponse>().await?;
+
+        Ok(res)
+    }
+
+    pub async fn revoke_sessions(
+        &self,
+        data: &RevokeSessionsRequest,
+    ) -> Result<RevokeSessionsResponse> {
+        let url = format!("{}/sessions/revoke", self.address);
+        let res = send(self.client.post(url).json(data)).await?;
+        let res = res.json::<RevokeSessionsResponse>().await?;
+
+        Ok(res)
+    }
+
     pub async fn sync(&self, from: OffsetDateTime) -> Result<SyncResponse> {
         let url = format!(
             "{}/sync?last_sync_ts={}",
             self.address,
             urlencoding::encode(from.format(&Rfc3339)?.as_str())
         );
-        let res = self.client.get(url).send().await?;
-        let res = handle_response_error(res).await?;
+        let res = send_with_retry(self.retries, || self.client.get(&url)).await?;
+        let res = res.json::<SyncResponse>().await?;
+
+        Ok(res)
+    }
+
+    pub async fn digest(&self) -> Result<DigestResponse> {
+        let url = format!("{}/sync/digest", self.address);
+        let res = send_with_retry(self.retries, || self.client.get(&url)).await?;
+        let res = res.json::<DigestResponse>().await?;
+
+        Ok(res)
+    }
+
+    pub async fn fetch_records(&self, data: &FetchRecordsRequest) -> Result<SyncResponse> {
+        let url = format!("{}/sync/records", self.address);
+        let res = send_with_retry(self.retries, || self.client.post(&url).json(data)).await?;
         let res = res.json::<SyncResponse>().await?;
 
         Ok(res)
     }
 
-    pub async fn post_entries(&self, data: &AddSyncRequest) -> Result<()> {
+    /// Upload the records. The records that conflict with the server copies are returned together
+    /// with the server copies, the rest is saved.
+    pub async fn post_entries(&self, data: &AddSyncRequest) -> Result<Vec<RejectedRecord>> {
         let url = format!("{}/entries", self.address);
-        let res = self.client.post(url).json(data).send().await?;
+        let res = self
+            .client
+            .post(url)
+            .json(data)
+            .send()
+            .await
+            .map_err(ApiError::from)?;
+
+        if res.status() == StatusCode::CONFLICT {
+            let status = res.status();
+            let message = match res.json::<ConflictMessage>().await {
+                Ok(data) if !data.rejected.is_empty() => return Ok(data.rejected),
+                Ok(data) => data.value,
+                Err(_) => status.canonical_reason().unwrap_or_default().to_string(),
+            };
+            return Err(ApiError::from_response(status, message).into());
+        }
         handle_response_error(res).await?;
 
-        Ok(())
+        Ok(vec![])
     }
 
     pub async fn status(&self) -> Result<StatusResponse> {
         let url = format!("{}/sync/status", self.address);
-        let res = self.client.get(url).send().await?;
-        let res = handle_response_error(res).await?;
+        let res = send_with_retry(self.retries, || self.client.get(&url)).await?;
         let res = res.json::<StatusResponse>().await?;
 
         Ok(res)
+    }
+
+    pub async fn rotate_key(&self, data: &RotateKeyRequest) -> Result<()> {
+        let url = format!("{}/entries/rotate", self.address);
+        send(self.client.post(url).json(data)).await?;
+
+        Ok(())
+    }
+
+    pub async fn create_link(&self, data: &CreateLinkRequest) -> Result<CreateLinkResponse> {
+        let url = format!("{}/link", self.address);
+        let res = send(self.client.post(url).json(data)).await?;
+        let res = res.json::<CreateLinkResponse>().await?;
+
+        Ok(res)
+    }
+
+    pub async fn set_key_check(&self, data: &KeyCheckRequest) -> Result<()> {
+        let url = format!("{}/keys/check", self.address);
+        send(self.client.post(url).json(data)).await?;
+
+        Ok(())
+    }
+
+    pub async fn key_status(&self) -> Result<KeyStatusResponse> {
+        let url = format!("{}/keys", self.address);
+        let res = send_with_retry(self.retries, || self.client.get(&url)).await?;
+        let res = res.json::<KeyStatusResponse>().await?;
 
+        Ok(res)
     }
 }
 
-async fn handle_response_error(res: Response) -> Result<Response> {
+async fn handle_response_error(res: Response) -> Result<Response, ApiError> {
     let status = res.status();
-    if status == StatusCode::SERVICE_UNAVAILABLE {
-        bail!("Service unavailable.");
-    }
 
     if !status.is_success() {
-        println!("{res:?}");
-        let data = res.json::<ErrorMessage>().await?;
-        println!("{data:?}");
-        // TODO: account for all the cases
-        bail!("There was an error with the service: Status {status:?}.");
+        // The body is not guaranteed to be an `ErrorMessage`, e.g. when a proxy in front of the
+        // server fails. Fall back to the status reason in that case.
+        let message = match res.json::<ErrorMessage>().await {
+            Ok(data) => data.value,
+            Err(_) => status.canonical_reason().unwrap_or_default().to_string(),
+        };
+        return Err(ApiError::from_response(status, message));
     }
 
     Ok(res)
 }
 
-pub async fn health_check(address: &str) -> Result<HealthCheckResponse> {
+pub async fn health_check(address: &str, options: &ClientOptions) -> Result<HealthCheckResponse> {
+    let client = build_client(options, HeaderMap::new())?;
     let url = format!("{address}/");
-    let res = reqwest::get(url).await?;
-    let res = handle_response_error(res).await?;
+    let res = send_with_retry(options.retries, || client.get(&url)).await?;
     let res = res.json::<HealthCheckResponse>().await?;
 
     Ok(res)
@@ -101,26 +380,13 @@ pub async fn health_check(address: &str) -> Result<HealthCheckResponse> {
 
 pub async fn register(
     address: &str,
-    username: &str,
-    email: &str,
-    password: &str,
-    host_id: &str,
+    request: &RegisterRequest,
+    options: &ClientOptions,
 ) -> Result<RegisterResponse> {
     // TODO: check if the user already exists
-    // TODO: setup the headers with version
-
+    let client = build_client(options, HeaderMap::new())?;
     let url = format!("{address}/register");
-    let res = reqwest::Client::new()
-        .post(url)
-        .json(&RegisterRequest {
-            username: username.into(),
-            email: email.into(),
-            password: password.into(),
-            host_id: host_id.into(),
-        })
-        .send()
-        .await?;
-    let res = handle_response_error(res).await?;
+    let res = send(client.post(url).json(request)).await?;
     let res = res.json::<RegisterResponse>().await?;
 
     Ok(res)
@@ -132,19 +398,127 @@ pub async fn login(
     username: &str,
     password: &str,
     host_id: &str,
+    options: &ClientOptions,
 ) -> Result<LoginResponse> {
+    let client = build_client(options, HeaderMap::new())?;
     let url = format!("{address}/login");
-    let res = reqwest::Client::new()
-        .post(url)
-        .json(&LoginRequest {
-            username: username.into(),
-            password: password.into(),
-            host_id: host_id.into(),
-        })
-        .send()
-        .await?;
-    let res = handle_response_error(res).await?;
+    let res = send(client.post(url).json(&LoginRequest {
+        username: username.into(),
+        password: password.into(),
+        host_id: host_id.into(),
+    }))
+    .await?;
     let res = res.json::<LoginResponse>().await?;
 
     Ok(res)
 }
+
+/// Ask the server to mail a password reset code to the user.
+pub async fn send_password_reset(
+    address: &str,
+    username: &str,
+    options: &ClientOptions,
+) -> Result<()> {
+    let client = build_client(options, HeaderMap::new())?;
+    let url = format!("{address}/password/reset/send");
+    send(client.post(url).json(&SendPasswordResetRequest {
+        username: username.into(),
+    }))
+    .await?;
+
+    Ok(())
+}
+
+/// Set a new password with the mailed code. The server logs out all the hosts.
+pub async fn reset_password(
+    address: &str,
+    username: &str,
+    token: &str,
+    password: &str,
+    options: &ClientOptions,
+) -> Result<()> {
+    let client = build_client(options, HeaderMap::new())?;
+    let url = format!("{address}/password/reset");
+    send(client.post(url).json(&ResetPasswordRequest {
+        username: username.into(),
+        token: token.into(),
+        password: password.into(),
+    }))
+    .await?;
+
+    Ok(())
+}
+
+pub async fn claim_link(
+    address: &str,
+    code: &str,
+    host_id: &str,
+    options: &ClientOptions,
+) -> Result<ClaimLinkResponse> {
+    let client = build_client(options, HeaderMap::new())?;
+    let url = format!("{address}/link/claim");
+    let res = send(client.post(url).json(&ClaimLinkRequest {
+        code: code.into(),
+        host_id: host_id.into(),
+    }))
+    .await?;
+    let res = res.json::<ClaimLinkResponse>().await?;
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApiError, AuthClient};
+    use dirpin_common::api::ErrorMessage;
+    use time::OffsetDateTime;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn sync_retries_when_server_is_unavailable() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sync"))
+            .respond_with(ResponseTemplate::new(503))
+            .up_to_n_times(2)
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "updated": [],
+                "deleted": [],
+            })))
+            .mount(&mock_server)
+            .await;
+
+        let address = mock_server.uri();
+        let client = AuthClient::new(&address, "session").unwrap();
+        let res = client.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+
+        assert!(res.updated.is_empty());
+        assert!(res.deleted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn error_message_is_turned_into_typed_error() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/sync/status"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(ErrorMessage {
+                value: "session not found".into(),
+            }))
+            .mount(&mock_server)
+            .await;
+
+        let address = mock_server.uri();
+        let client = AuthClient::new(&address, "session").unwrap();
+        let err = client.status().await.unwrap_err();
+
+        assert!(matches!(
+            err.downcast_ref::<ApiError>(),
+            Some(ApiError::Unauthorized(message)) if message == "session not found"
+        ));
+    }
+}
@@ -1,8 +1,11 @@
 use crate::domain::conflict::Conflict;
-use crate::domain::context::Context;
+use crate::domain::context::{normalize_git_remote, Context};
 use crate::domain::entry::{Entry, EntryKind};
 use crate::domain::host::HostId;
+use crate::domain::quarantine::{NewQuarantined, Quarantined};
+use crate::domain::revision::{Revision, RevisionOperation};
 use crate::domain::workspace::{Workspace, WorkspaceId, WorkspacePath};
+use crate::encryption::UnknownFields;
 use dirpin_common::api::RefDelete;
 use dirpin_common::domain::SyncVersion;
 use eyre::Result;
@@ -22,31 +25,57 @@ use uuid::Uuid;
 pub struct DbEntry(pub Entry);
 pub struct DbWorkspace(pub Workspace);
 pub struct DbConflict(pub Conflict);
+pub struct DbQuarantined(pub Quarantined);
+pub struct DbRevision(pub Revision);
+
+/// Report a column value that fails to parse as a decode error instead of panicking, so that one
+/// bad row does not crash the whole program.
+fn column_error<E>(column: &str, err: E) -> sqlx::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    sqlx::Error::ColumnDecode {
+        index: column.into(),
+        source: err.into(),
+    }
+}
+
+fn timestamp_nanos(row: &SqliteRow, column: &str) -> sqlx::Result<OffsetDateTime> {
+    let value: i64 = row.try_get(column)?;
+    OffsetDateTime::from_unix_timestamp_nanos(value as i128).map_err(|e| column_error(column, e))
+}
+
+fn opt_timestamp(row: &SqliteRow, column: &str) -> sqlx::Result<Option<OffsetDateTime>> {
+    let value: Option<i64> = row.try_get(column)?;
+    value
+        .map(OffsetDateTime::from_unix_timestamp)
+        .transpose()
+        .map_err(|e| column_error(column, e))
+}
 
 impl<'r> FromRow<'r, SqliteRow> for DbEntry {
     fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
         Ok(Self(Entry {
             id: row
                 .try_get("id")
-                .map(|x: &str| Uuid::parse_str(x).unwrap())?,
+                .and_then(|x: &str| Uuid::parse_str(x).map_err(|e| column_error("id", e)))?,
             value: row.try_get("value")?,
             desc: row.try_get("desc")?,
             data: row.try_get("data")?,
             kind: row
                 .try_get("kind")
-                .map(|x: &str| EntryKind::from_str(x).unwrap())?,
+                .and_then(|x: &str| EntryKind::from_str(x).map_err(|e| column_error("kind", e)))?,
             path: row.try_get("path")?,
-            updated_at: row
-                .try_get("updated_at")
-                .map(|x: i64| OffsetDateTime::from_unix_timestamp_nanos(x as i128).unwrap())?,
-            deleted_at: row
-                .try_get("deleted_at")
-                .map(|x: Option<i64>| x.map(|y| OffsetDateTime::from_unix_timestamp(y).unwrap()))?,
+            updated_at: timestamp_nanos(row, "updated_at")?,
+            deleted_at: opt_timestamp(row, "deleted_at")?,
             version: row.try_get("version").map(|x: u32| SyncVersion::from(x))?,
             workspace_id: row.try_get("workspace_id").map(|x: &str| x.parse().ok())?,
             host_id: row
                 .try_get("host_id")
-                .map(|x: &str| HostId::from_str(x).unwrap())?,
+                .and_then(|x: &str| HostId::from_str(x).map_err(|e| column_error("host_id", e)))?,
+            extra: row
+                .try_get("extra")
+                .map(|x: Option<Vec<u8>>| UnknownFields::from_bytes(x.unwrap_or_default()))?,
         }))
     }
 }
@@ -54,23 +83,47 @@ impl<'r> FromRow<'r, SqliteRow> for DbEntry {
 impl<'r> FromRow<'r, SqliteRow> for DbWorkspace {
     fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
         Ok(Self(Workspace {
-            id: row.try_get("id").map(|x: &str| x.parse().unwrap())?,
+            id: row
+                .try_get("id")
+                .and_then(|x: &str| x.parse().map_err(|e| column_error("id", e)))?,
             name: row.try_get("name")?,
             git: row.try_get("git")?,
-            paths: row.try_get("paths").map(|x: &str| {
+            paths: row.try_get("paths").and_then(|x: &str| {
                 // TODO: theoretically, there can be a "," in a path that would fail to correctly
-                // deserialize the path from the list.
+                // deserialize the path from the list. Tombstones have no paths.
                 x.split(",")
-                    .map(|y| WorkspacePath::try_from(y).unwrap())
+                    .filter(|y| !y.is_empty())
+                    .map(|y| WorkspacePath::try_from(y).map_err(|e| column_error("paths", e)))
                     .collect()
             })?,
-            updated_at: row
-                .try_get("updated_at")
-                .map(|x: i64| OffsetDateTime::from_unix_timestamp_nanos(x as i128).unwrap())?,
-            deleted_at: row
-                .try_get("deleted_at")
-                .map(|x: Option<i64>| x.map(|y| OffsetDateTime::from_unix_timestamp(y).unwrap()))?,
+            updated_at: timestamp_nanos(row, "updated_at")?,
+            deleted_at: opt_timestamp(row, "deleted_at")?,
             version: row.try_get("version").map(|x: u32| SyncVersion::from(x))?,
+            extra: row
+                .try_get("extra")
+                .map(|x: Option<Vec<u8>>| UnknownFields::from_bytes(x.unwrap_or_default()))?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbRevision {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        let DbEntry(entry) = DbEntry::from_row(row)?;
+
+        Ok(Self(Revision {
+            uid: row.try_get("uid").and_then(|x: Option<&str>| {
+                x.map(Uuid::parse_str)
+                    .transpose()
+                    .map_err(|e| column_error("uid", e))
+            })?,
+            seq: row.try_get("seq")?,
+            rev: row.try_get("rev")?,
+            operation: row.try_get("operation").and_then(|x: &str| {
+                RevisionOperation::from_str(x).map_err(|e| column_error("operation", e))
+            })?,
+            local: row.try_get("local")?,
+            created_at: timestamp_nanos(row, "created_at")?,
+            entry,
         }))
     }
 }
@@ -81,18 +134,37 @@ impl<'r> FromRow<'r, SqliteRow> for DbConflict {
         let data: &str = row.try_get("data")?;
         match kind {
             "entry" => {
-                let value = serde_json::from_str::<Entry>(data).unwrap();
+                let value =
+                    serde_json::from_str::<Entry>(data).map_err(|e| column_error("data", e))?;
                 Ok(Self(Conflict::Entry(value)))
             }
             "workspace" => {
-                let value = serde_json::from_str::<Workspace>(data).unwrap();
+                let value =
+                    serde_json::from_str::<Workspace>(data).map_err(|e| column_error("data", e))?;
                 Ok(Self(Conflict::Workspace(value)))
             }
-            _ => panic!("Found invalid conflict kind"),
+            value => Err(column_error(
+                "ref_kind",
+                format!("Found invalid conflict kind {value}"),
+            )),
         }
     }
 }
 
+impl<'r> FromRow<'r, SqliteRow> for DbQuarantined {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(Quarantined {
+            id: row.try_get("id")?,
+            kind: row.try_get("kind")?,
+            data: row.try_get("data")?,
+            error: row.try_get("error")?,
+            created_at: row.try_get("created_at").and_then(|x: i64| {
+                OffsetDateTime::from_unix_timestamp(x).map_err(|e| column_error("created_at", e))
+            })?,
+        }))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum FilterMode {
     All,
@@ -114,6 +186,18 @@ pub struct Database {
     pub pool: SqlitePool,
 }
 
+/// How the schema of a database file compares to the migrations this client ships with.
+#[derive(Debug, Default)]
+pub struct MigrationState {
+    pub applied: usize,
+    /// Migrations the client knows about but the database has not run yet
+    pub pending: Vec<String>,
+    /// Migrations in the database the client does not know about, e.g. from a newer client
+    pub unknown: Vec<i64>,
+    /// Migrations that started but did not finish
+    pub failed: Vec<i64>,
+}
+
 impl Database {
     pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
@@ -132,6 +216,48 @@ impl Database {
         Ok(Self { pool })
     }
 
+    /// Inspect the schema of the database at the path without creating or migrating it.
+    pub async fn migration_state<P: AsRef<Path>>(path: P) -> Result<MigrationState> {
+        let path = path.as_ref();
+        let options = SqliteConnectOptions::from_str(path.to_str().unwrap())?.read_only(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        let (has_table,): (bool,) = sqlx::query_as(
+            "select count(*) > 0 from sqlite_master where type = 'table' and name = '_sqlx_migrations'",
+        )
+        .fetch_one(&pool)
+        .await?;
+        let applied: Vec<(i64, bool)> = if has_table {
+            sqlx::query_as("select version, success from _sqlx_migrations")
+                .fetch_all(&pool)
+                .await?
+        } else {
+            Vec::new()
+        };
+        pool.close().await;
+
+        let migrator = sqlx::migrate!("./migrations");
+        let mut state = MigrationState::default();
+        for (version, success) in &applied {
+            if !success {
+                state.failed.push(*version);
+            } else if migrator.iter().any(|x| x.version == *version) {
+                state.applied += 1;
+            } else {
+                state.unknown.push(*version);
+            }
+        }
+        for el in migrator.iter() {
+            if !applied.iter().any(|(version, _)| *version == el.version) {
+                state
+                    .pending
+                    .push(format!("{} {}", el.version, el.description));
+            }
+        }
+
+        Ok(state)
+    }
+
     async fn setup_db(pool: &SqlitePool) -> Result<()> {
         debug!("setting up database");
         sqlx::migrate!("./migrations").run(pool).await?;
@@ -143,9 +269,9 @@ impl Database {
         sqlx::query(
             r#"
             insert into entries(
-                id, value, desc, data, kind, path, updated_at, deleted_at, version, workspace_id, host_id
+                id, value, desc, data, kind, path, updated_at, deleted_at, version, workspace_id, host_id, extra
             ) values(
-                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12
             )
             on conflict(id) do update set
                 value = ?2,
@@ -157,7 +283,8 @@ impl Database {
                 deleted_at = ?8,
                 version = ?9,
                 workspace_id = ?10,
-                host_id = ?11
+                host_id = ?11,
+                extra = ?12
             "#,
         )
             .bind(v.id.to_string())
@@ -171,26 +298,70 @@ impl Database {
             .bind(v.version.inner())
             .bind(v.workspace_id.as_ref().map(|x| x.to_string()))
             .bind(v.host_id.to_string())
+            .bind((!v.extra.is_empty()).then_some(v.extra.as_bytes()))
         .execute(&mut **tx)
         .await?;
 
         Ok(())
     }
 
+    /// Keep the current state of the entry in the revision log before it's replaced. Nothing is
+    /// recorded for a new entry, which is returned as false.
+    async fn revision_tx(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        id: &str,
+        operation: RevisionOperation,
+        local: bool,
+    ) -> Result<bool> {
+        let res = sqlx::query(
+            r#"
+            insert into revisions(
+                id, rev, operation, local, created_at, value, desc, data, kind, path, updated_at,
+                deleted_at, version, workspace_id, host_id, extra, uid
+            )
+            select
+                id, coalesce((select max(rev) from revisions where id = ?1), 0) + 1, ?2, ?3, ?4,
+                value, desc, data, kind, path, updated_at, deleted_at, version, workspace_id,
+                host_id, extra, ?5
+            from entries where id = ?1
+            "#,
+        )
+        .bind(id)
+        .bind(operation.as_str())
+        .bind(local)
+        .bind(OffsetDateTime::now_utc().unix_timestamp_nanos() as i64)
+        .bind(Uuid::now_v7().to_string())
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
     pub async fn save(&self, item: &Entry) -> Result<()> {
+        self.save_local(item, RevisionOperation::Save).await
+    }
+
+    async fn save_local(&self, item: &Entry, operation: RevisionOperation) -> Result<()> {
         debug!("Saving entry to database");
         let mut tx = self.pool.begin().await?;
         // TODO: if transaction fails, it does not throw error?
+        let replaced = Self::revision_tx(&mut tx, &item.id.to_string(), operation, true).await?;
         Self::save_tx(&mut tx, item).await?;
+        // A new entry has no prior state, so the added one is recorded for the undo to delete.
+        if !replaced {
+            Self::revision_tx(&mut tx, &item.id.to_string(), RevisionOperation::Add, true).await?;
+        }
         tx.commit().await?;
 
         Ok(())
     }
 
+    /// Save the entries that came with a sync.
     pub async fn save_bulk(&self, items: &[Entry]) -> Result<()> {
         debug!("Saving entries in bulk to database");
         let mut tx = self.pool.begin().await?;
         for el in items {
+            Self::revision_tx(&mut tx, &el.id.to_string(), RevisionOperation::Save, false).await?;
             Self::save_tx(&mut tx, &el).await?;
         }
         tx.commit().await?;
@@ -218,21 +389,53 @@ impl Database {
             None => &mut query,
         };
 
-        match &context.git {
-            Some(git) => query.and_where_eq("git", quote(git)),
-            None => &mut query,
-        };
-
         match workspace_name {
             Some(name) => query.and_where_eq("name", quote(name.to_string())),
             None => &mut query,
         };
 
+        // The remote is compared after the query as older workspaces store it as it came from git.
+        let git = context.git.as_deref().map(normalize_git_remote);
         let query = query.sql().expect("Failed to parse query");
         let res = sqlx::query_as(&query)
+            .fetch(&self.pool)
+            .map_ok(|DbWorkspace(ws)| ws)
+            .try_filter(|ws| {
+                let matches = ws.git.as_deref().map(normalize_git_remote) == git;
+                std::future::ready(git.is_none() || matches)
+            })
+            .try_next()
+            .await?;
+
+        Ok(res)
+    }
+
+    /// The oldest workspace of the git remote, from any host.
+    pub async fn workspace_by_git(&self, git: &str) -> Result<Option<Workspace>> {
+        debug!("Get workspace by git remote from database");
+        let git = normalize_git_remote(git);
+        let res = sqlx::query_as(
+            "select * from workspaces where git is not null and deleted_at is null order by id",
+        )
+        .fetch(&self.pool)
+        .map_ok(|DbWorkspace(ws)| ws)
+        .try_filter(|ws| {
+            let matches = ws.git.as_deref().map(normalize_git_remote).as_ref() == Some(&git);
+            std::future::ready(matches)
+        })
+        .try_next()
+        .await?;
+
+        Ok(res)
+    }
+
+    pub async fn get_workspace(&self, id: &WorkspaceId) -> Result<Option<Workspace>> {
+        debug!("Query workspace from database");
+        let res = sqlx::query_as("select * from workspaces where id = ?1")
+            .bind(id.to_string())
             .fetch_optional(&self.pool)
             .await?
-            .map(|DbWorkspace(ws)| ws);
+            .map(|DbWorkspace(x)| x);
 
         Ok(res)
     }
@@ -243,7 +446,7 @@ impl Database {
         query.field("*");
 
         if !search.is_empty() {
-            query.and_where_like_any("value", search);
+            query.and_where_like_any("name", search);
         }
 
         let query = query.sql().expect("Failed to parse query");
@@ -303,10 +506,10 @@ impl Database {
         sqlx::query(
             r#"
             insert into workspaces(
-                id, name, git, paths, updated_at, deleted_at, version
+                id, name, git, paths, updated_at, deleted_at, version, extra
             )
             values(
-                ?1, ?2, ?3, ?4, ?5, ?6, ?7
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8
             ) 
             on conflict(id) do update set 
                 id = ?1,
@@ -315,7 +518,8 @@ impl Database {
                 paths = ?4,
                 updated_at = ?5,
                 deleted_at = ?6,
-                version = ?7
+                version = ?7,
+                extra = ?8
             "#,
         )
         .bind(v.id.to_string())
@@ -331,6 +535,7 @@ impl Database {
         .bind(v.updated_at.unix_timestamp_nanos() as i64)
         .bind(v.deleted_at.map(|x| x.unix_timestamp()))
         .bind(v.version.inner())
+        .bind((!v.extra.is_empty()).then_some(v.extra.as_bytes()))
         .execute(&mut **tx)
         .await?;
 
@@ -404,6 +609,88 @@ impl Database {
         Ok(res)
     }
 
+    /// Count the live entries that belong to the workspace.
+    pub async fn count_workspace_entries(&self, id: &WorkspaceId) -> Result<i64> {
+        debug!("Count workspace entries in database");
+        let res: (i64,) = sqlx::query_as(
+            "select count(*) from entries where workspace_id = ?1 and deleted_at is null",
+        )
+        .bind(id.to_string())
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(res.0)
+    }
+
+    /// Re-point the live entries of one workspace to another one, or to none. The entries get a
+    /// new version so the change syncs to the other hosts. Returns the number of moved entries.
+    async fn move_entries_tx(
+        tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        from: &WorkspaceId,
+        to: Option<&WorkspaceId>,
+    ) -> Result<u64> {
+        let ids: Vec<(String,)> =
+            sqlx::query_as("select id from entries where workspace_id = ?1 and deleted_at is null")
+                .bind(from.to_string())
+                .fetch_all(&mut **tx)
+                .await?;
+        for (id,) in &ids {
+            Self::revision_tx(tx, id, RevisionOperation::Save, true).await?;
+        }
+
+        let res = sqlx::query(
+            r#"
+            update entries set
+                workspace_id = ?2,
+                version = version + 1,
+                updated_at = ?3
+            where workspace_id = ?1 and deleted_at is null
+            "#,
+        )
+        .bind(from.to_string())
+        .bind(to.map(|x| x.to_string()))
+        .bind(OffsetDateTime::now_utc().unix_timestamp_nanos() as i64)
+        .execute(&mut **tx)
+        .await?;
+
+        Ok(res.rows_affected())
+    }
+
+    fn workspace_tombstone(v: &Workspace) -> RefDelete {
+        RefDelete {
+            client_id: v.id.to_string(),
+            version: v.version.clone(),
+            updated_at: v.updated_at,
+            deleted_at: OffsetDateTime::now_utc(),
+            kind: "workspace".into(),
+        }
+    }
+
+    /// Delete the workspace. Its entries are kept and stay pinned to their own paths. Returns the
+    /// number of detached entries.
+    pub async fn delete_workspace(&self, v: &Workspace) -> Result<u64> {
+        debug!("Deleting workspace in database");
+        let mut tx = self.pool.begin().await?;
+        let moved = Self::move_entries_tx(&mut tx, &v.id, None).await?;
+        Self::delete_workspace_tx(&mut tx, &Self::workspace_tombstone(v)).await?;
+        tx.commit().await?;
+
+        Ok(moved)
+    }
+
+    /// Move the entries of `from` into `into`, save `into` and delete `from`. Returns the number
+    /// of moved entries.
+    pub async fn merge_workspaces(&self, from: &Workspace, into: &Workspace) -> Result<u64> {
+        debug!("Merging workspaces in database");
+        let mut tx = self.pool.begin().await?;
+        Self::save_workspace_tx(&mut tx, into).await?;
+        let moved = Self::move_entries_tx(&mut tx, &from.id, Some(&into.id)).await?;
+        Self::delete_workspace_tx(&mut tx, &Self::workspace_tombstone(from)).await?;
+        tx.commit().await?;
+
+        Ok(moved)
+    }
+
     async fn save_conflict_tx(
         tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
         v: &Conflict,
@@ -441,6 +728,22 @@ impl Database {
         Ok(())
     }
 
+    /// Count the entries that point to a workspace that is not in the database.
+    pub async fn count_orphaned_entries(&self) -> Result<i64> {
+        debug!("Count orphaned entries in database");
+        let res: (i64,) = sqlx::query_as(
+            r#"
+            select count(*) from entries
+            where workspace_id is not null
+            and workspace_id not in (select id from workspaces)
+            "#,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(res.0)
+    }
+
     pub async fn list_conflicts(&self) -> Result<Vec<Conflict>> {
         debug!("Query conflicts datbase");
         let res = sqlx::query_as("select * from conflicts")
@@ -497,6 +800,7 @@ impl Database {
         debug!("Deleting entries in bulk in database");
         let mut tx = self.pool.begin().await?;
         for el in items {
+            Self::revision_tx(&mut tx, &el.client_id, RevisionOperation::Delete, false).await?;
             Self::delete_tx(&mut tx, &el).await?;
         }
         tx.commit().await?;
@@ -508,6 +812,7 @@ impl Database {
         debug!("Deleting entries in bulk in database");
         let mut tx = self.pool.begin().await?;
         for el in items {
+            Self::revision_tx(&mut tx, &el.client_id, RevisionOperation::Delete, false).await?;
             Self::delete_tx(&mut tx, &el).await?;
         }
         tx.commit().await?;
@@ -516,15 +821,166 @@ impl Database {
     }
 
     pub async fn delete(&self, id: Uuid) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        Self::revision_tx(&mut tx, &id.to_string(), RevisionOperation::Delete, true).await?;
         sqlx::query("update entries set deleted_at = ?2 where id = ?1")
             .bind(id.to_string())
             .bind(OffsetDateTime::now_utc().unix_timestamp())
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
         Ok(())
     }
 
+    pub async fn get(&self, id: Uuid) -> Result<Option<Entry>> {
+        debug!("Query entry from database");
+        let res = sqlx::query_as("select * from entries where id = ?1")
+            .bind(id.to_string())
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|DbEntry(x)| x);
+
+        Ok(res)
+    }
+
+    /// All the recorded prior states of the entry, oldest first.
+    pub async fn list_revisions(&self, id: Uuid) -> Result<Vec<Revision>> {
+        debug!("Query entry revisions from database");
+        let res = sqlx::query_as("select * from revisions where id = ?1 order by rev")
+            .bind(id.to_string())
+            .fetch(&self.pool)
+            .map_ok(|DbRevision(x)| x)
+            .try_collect()
+            .await?;
+
+        Ok(res)
+    }
+
+    /// The latest operations across all the entries, newest first.
+    pub async fn list_recent_revisions(&self, limit: u32) -> Result<Vec<Revision>> {
+        debug!("Query recent revisions from database");
+        let res = sqlx::query_as("select * from revisions order by seq desc limit ?1")
+            .bind(limit)
+            .fetch(&self.pool)
+            .map_ok(|DbRevision(x)| x)
+            .try_collect()
+            .await?;
+
+        Ok(res)
+    }
+
+    /// The revisions of the local operations since the timestamp, for the history sync.
+    pub async fn revisions_after(&self, created_at: OffsetDateTime) -> Result<Vec<Revision>> {
+        debug!("Query local revisions after from database");
+        let res = sqlx::query_as(
+            r#"
+            select * from revisions
+            where local and uid not null and created_at >= ?1
+            order by seq
+            "#,
+        )
+        .bind(created_at.unix_timestamp_nanos() as i64)
+        .fetch(&self.pool)
+        .map_ok(|DbRevision(x)| x)
+        .try_collect()
+        .await?;
+
+        Ok(res)
+    }
+
+    /// Keep the revisions that came with the history sync. They get the next revision numbers of
+    /// the entry on this host, and the ones that are already here are skipped.
+    pub async fn save_remote_revisions(&self, items: &[Revision]) -> Result<()> {
+        debug!("Saving remote revisions to database");
+        let mut tx = self.pool.begin().await?;
+        for el in items {
+            let v = &el.entry;
+            sqlx::query(
+                r#"
+                insert or ignore into revisions(
+                    id, rev, operation, local, created_at, value, desc, data, kind, path,
+                    updated_at, deleted_at, version, workspace_id, host_id, extra, uid
+                )
+                values(
+                    ?1, coalesce((select max(rev) from revisions where id = ?1), 0) + 1, ?2, 0,
+                    ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15
+                )
+                "#,
+            )
+            .bind(v.id.to_string())
+            .bind(el.operation.as_str())
+            .bind(el.created_at.unix_timestamp_nanos() as i64)
+            .bind(v.value.as_str())
+            .bind(v.desc.to_owned())
+            .bind(v.data.to_owned())
+            .bind(v.kind.as_str())
+            .bind(v.path.as_str())
+            .bind(v.updated_at.unix_timestamp_nanos() as i64)
+            .bind(v.deleted_at.map(|x| x.unix_timestamp()))
+            .bind(v.version.inner())
+            .bind(v.workspace_id.as_ref().map(|x| x.to_string()))
+            .bind(v.host_id.to_string())
+            .bind((!v.extra.is_empty()).then_some(v.extra.as_bytes()))
+            .bind(el.uid.map(|x| x.to_string()))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn get_revision(&self, id: Uuid, rev: u32) -> Result<Option<Revision>> {
+        debug!("Query entry revision from database");
+        let res = sqlx::query_as("select * from revisions where id = ?1 and rev = ?2")
+            .bind(id.to_string())
+            .bind(rev)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|DbRevision(x)| x);
+
+        Ok(res)
+    }
+
+    /// Put the entry back into the state of the revision. It's saved as a new change so that it
+    /// syncs to the other hosts, and the replaced state becomes a revision itself.
+    pub async fn restore(&self, revision: &Revision) -> Result<Entry> {
+        let mut entry = revision.entry.clone();
+        if let Some(current) = self.get(entry.id).await? {
+            if current.version > entry.version {
+                entry.version = current.version;
+            }
+        }
+        let now = OffsetDateTime::now_utc();
+        entry.version.bump();
+        entry.updated_at = now;
+        // The deletion is made again, as the sync only uploads the ones after the last sync.
+        if entry.deleted_at.is_some() {
+            entry.deleted_at = Some(now);
+        }
+        self.save_local(&entry, RevisionOperation::Restore).await?;
+
+        Ok(entry)
+    }
+
+    /// Revert the last change made on this host. Undoing twice brings the change back.
+    pub async fn undo(&self) -> Result<Option<Entry>> {
+        let revision: Option<DbRevision> =
+            sqlx::query_as("select * from revisions where local order by seq desc limit 1")
+                .fetch_optional(&self.pool)
+                .await?;
+
+        match revision {
+            Some(DbRevision(revision)) if revision.operation == RevisionOperation::Add => {
+                self.delete(revision.entry.id).await?;
+                Ok(self.get(revision.entry.id).await?)
+            }
+            Some(DbRevision(revision)) => Ok(Some(self.restore(&revision).await?)),
+            None => Ok(None),
+        }
+    }
+
     pub async fn deleted_after(&self, deleted_at: OffsetDateTime) -> Result<Vec<Entry>> {
         debug!("Query deleted before from datbase");
         let res = sqlx::query_as("select * from entries where deleted_at >= ?1")
@@ -649,4 +1105,332 @@ impl Database {
 
         Ok(res.0)
     }
+
+    /// Drop the records deleted before the timestamp together with their revisions. A deleted
+    /// workspace stays as long as some entry still points to it. Returns the number of purged
+    /// workspaces and entries.
+    pub async fn purge_tombstones(&self, before: OffsetDateTime) -> Result<(u64, u64)> {
+        debug!("Purging tombstones from database");
+        let mut tx = self.pool.begin().await?;
+        let entries = sqlx::query("delete from entries where deleted_at < ?1")
+            .bind(before.unix_timestamp())
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        let workspaces = sqlx::query(
+            r#"
+            delete from workspaces
+            where deleted_at < ?1
+            and id not in (select workspace_id from entries where workspace_id is not null)
+            "#,
+        )
+        .bind(before.unix_timestamp())
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+        sqlx::query("delete from revisions where id not in (select id from entries)")
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok((workspaces, entries))
+    }
+
+    /// Keep the remote records that failed to parse. The same payload is stored only once.
+    pub async fn save_quarantined_bulk(&self, items: &[NewQuarantined]) -> Result<()> {
+        debug!("Saving quarantined records in bulk to database");
+        let mut tx = self.pool.begin().await?;
+        for el in items {
+            sqlx::query(
+                r#"
+                insert into quarantine(kind, data, error, created_at)
+                values(?1, ?2, ?3, ?4)
+                on conflict(data) do update set
+                    error = ?3
+                "#,
+            )
+            .bind(el.kind.as_str())
+            .bind(el.data.as_str())
+            .bind(el.error.as_str())
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn list_quarantined(&self) -> Result<Vec<Quarantined>> {
+        debug!("Query quarantined records from database");
+        let res = sqlx::query_as("select * from quarantine order by id")
+            .fetch(&self.pool)
+            .map_ok(|DbQuarantined(x)| x)
+            .try_collect()
+            .await?;
+
+        Ok(res)
+    }
+
+    pub async fn delete_quarantined(&self, ids: &[i64]) -> Result<()> {
+        debug!("Deleting quarantined records from database");
+        let mut tx = self.pool.begin().await?;
+        for id in ids {
+            sqlx::query("delete from quarantine where id = ?1")
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Database;
+    use crate::domain::context::Context;
+    use crate::domain::entry::Entry;
+    use crate::domain::host::HostId;
+    use crate::domain::revision::RevisionOperation;
+    use crate::domain::workspace::Workspace;
+    use dirpin_common::api::RefDelete;
+    use time::OffsetDateTime;
+
+    async fn setup_db() -> eyre::Result<Database> {
+        let database = Database::new("sqlite::memory:").await?;
+        sqlx::migrate!("./migrations").run(&database.pool).await?;
+
+        Ok(database)
+    }
+
+    #[tokio::test]
+    async fn revisions_keep_prior_states() {
+        let db = setup_db().await.unwrap();
+        let mut entry = Entry::new(
+            "first".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        );
+        db.save(&entry).await.unwrap();
+        let revisions = db.list_revisions(entry.id).await.unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].rev, 1);
+        assert_eq!(revisions[0].operation, RevisionOperation::Add);
+
+        entry.value = "second".into();
+        entry.version.bump();
+        db.save(&entry).await.unwrap();
+        db.delete(entry.id).await.unwrap();
+
+        let revisions = db.list_revisions(entry.id).await.unwrap();
+        assert_eq!(revisions.len(), 3);
+        assert_eq!(revisions[1].rev, 2);
+        assert_eq!(revisions[1].operation, RevisionOperation::Save);
+        assert_eq!(revisions[1].entry.value, "first");
+        assert_eq!(revisions[2].operation, RevisionOperation::Delete);
+        assert_eq!(revisions[2].entry.value, "second");
+        assert!(revisions[2].entry.deleted_at.is_none());
+
+        let restored = db.restore(&revisions[1]).await.unwrap();
+        assert_eq!(restored.value, "first");
+        assert!(restored.version > entry.version);
+        let current = db.get(entry.id).await.unwrap().unwrap();
+        assert_eq!(current.value, "first");
+        assert!(current.deleted_at.is_none());
+        assert_eq!(db.list_revisions(entry.id).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn undo_reverts_the_last_local_change() {
+        let db = setup_db().await.unwrap();
+        assert!(db.undo().await.unwrap().is_none());
+
+        let entry = Entry::new(
+            "value".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        );
+        db.save(&entry).await.unwrap();
+        db.delete(entry.id).await.unwrap();
+        // The deletion was uploaded with an earlier sync.
+        sqlx::query("update entries set deleted_at = deleted_at - 60 where id = ?1")
+            .bind(entry.id.to_string())
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        let last_sync = OffsetDateTime::now_utc() - time::Duration::seconds(30);
+
+        // A deletion that came with a sync is not a local change.
+        let other = Entry::new(
+            "other".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        );
+        db.save_bulk(std::slice::from_ref(&other)).await.unwrap();
+        db.delete_ref_bulk(&[RefDelete {
+            client_id: other.id.to_string(),
+            version: other.version.clone(),
+            updated_at: other.updated_at,
+            deleted_at: OffsetDateTime::now_utc(),
+            kind: "entry".into(),
+        }])
+        .await
+        .unwrap();
+
+        let restored = db.undo().await.unwrap().unwrap();
+        assert_eq!(restored.id, entry.id);
+        assert!(db
+            .get(entry.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_none());
+        assert!(db
+            .get(other.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_some());
+
+        // Undoing the undo deletes it again, and the deletion is uploaded with the next sync.
+        db.undo().await.unwrap();
+        assert!(db
+            .get(entry.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_some());
+        let deleted = db.deleted_after(last_sync).await.unwrap();
+        assert!(deleted.iter().any(|x| x.id == entry.id));
+    }
+
+    #[tokio::test]
+    async fn undo_deletes_an_added_entry() {
+        let db = setup_db().await.unwrap();
+        let older = Entry::new(
+            "older".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        );
+        db.save(&older).await.unwrap();
+        db.delete(older.id).await.unwrap();
+
+        let entry = Entry::new(
+            "value".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        );
+        db.save(&entry).await.unwrap();
+
+        let undone = db.undo().await.unwrap().unwrap();
+        assert_eq!(undone.id, entry.id);
+        assert!(undone.deleted_at.is_some());
+        assert!(db
+            .get(older.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_some());
+
+        // Undoing the undo brings the entry back.
+        let restored = db.undo().await.unwrap().unwrap();
+        assert_eq!(restored.id, entry.id);
+        assert!(db
+            .get(entry.id)
+            .await
+            .unwrap()
+            .unwrap()
+            .deleted_at
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn merge_and_delete_workspaces_move_entries() {
+        let db = setup_db().await.unwrap();
+        let host_id = HostId::custom("a".into(), "b".into());
+        let context = |path: &str| Context {
+            path: path.into(),
+            host_id: host_id.clone(),
+            git: None,
+            git_path: None,
+        };
+        let first = Workspace::new("first".into(), &context("/first"));
+        let second = Workspace::new("second".into(), &context("/second"));
+        db.save_workspace(&first).await.unwrap();
+        db.save_workspace(&second).await.unwrap();
+        let entry = Entry::new(
+            "value".into(),
+            "/first".into(),
+            Some(first.id.clone()),
+            host_id.clone(),
+        );
+        db.save(&entry).await.unwrap();
+
+        let moved = db.merge_workspaces(&first, &second).await.unwrap();
+        assert_eq!(moved, 1);
+        let merged = db.get(entry.id).await.unwrap().unwrap();
+        assert_eq!(merged.workspace_id, Some(second.id.clone()));
+        assert!(merged.version > entry.version);
+        assert_eq!(db.count_workspace_entries(&first.id).await.unwrap(), 0);
+        assert_eq!(db.count_workspace_entries(&second.id).await.unwrap(), 1);
+        let deleted = db.list_workspace_deleted().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].id, first.id);
+
+        let detached = db.delete_workspace(&second).await.unwrap();
+        assert_eq!(detached, 1);
+        let entry = db.get(entry.id).await.unwrap().unwrap();
+        assert!(entry.workspace_id.is_none());
+        assert!(entry.deleted_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn workspaces_match_by_normalised_git_remote() {
+        let db = setup_db().await.unwrap();
+        let first = Context {
+            path: "/first/repo".into(),
+            host_id: HostId::custom("a".into(), "first".into()),
+            git: Some("git@github.com:org/repo.git".into()),
+            git_path: Some("/first/repo".into()),
+        };
+        let workspace = Workspace::new("repo".into(), &first);
+        db.save_workspace(&workspace).await.unwrap();
+
+        let second = Context {
+            path: "/second/repo".into(),
+            host_id: HostId::custom("a".into(), "second".into()),
+            git: Some("github.com/org/repo".into()),
+            git_path: Some("/second/repo".into()),
+        };
+        assert!(db.workspace(None, None, &second).await.unwrap().is_none());
+        let found = db
+            .workspace_by_git("https://github.com/org/repo")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.id, workspace.id);
+
+        let first = Context {
+            git: Some("https://github.com/org/repo".into()),
+            ..first
+        };
+        let found = db.workspace(None, None, &first).await.unwrap().unwrap();
+        assert_eq!(found.id, workspace.id);
+        assert!(db
+            .workspace_by_git("github.com/org/other")
+            .await
+            .unwrap()
+            .is_none());
+    }
 }
@@ -0,0 +1,631 @@
+use crate::api_client::{self, ApiError, AuthClient, ClientOptions};
+use crate::database::{Database, MigrationState};
+use crate::encryption::{self, key_id};
+use crate::settings::Settings;
+use crypto_secretbox::Key;
+use dirpin_common::api::{Compatibility, HealthCheckResponse, StatusResponse, API_VERSION};
+use std::fs::OpenOptions;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+
+/// Difference between the clocks of the host and the server after which the sync order of the
+/// changes can't be trusted anymore.
+const MAX_CLOCK_SKEW: Duration = Duration::seconds(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Ok,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    /// What the user can do about the problem
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            severity: Severity::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn info(name: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            name,
+            severity: Severity::Info,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn problem(
+        name: &'static str,
+        severity: Severity,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            name,
+            severity,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// The worst severity of all the checks
+    pub fn severity(&self) -> Severity {
+        self.checks
+            .iter()
+            .map(|x| x.severity)
+            .max()
+            .unwrap_or(Severity::Ok)
+    }
+}
+
+/// Run all the checks. Nothing is created or migrated on the way, so it is safe to run on a
+/// broken setup.
+pub async fn diagnose() -> Report {
+    let mut checks = Vec::new();
+
+    let settings = match Settings::new() {
+        Ok(settings) => {
+            checks.push(Check::ok("config", "The config parsed"));
+            settings
+        }
+        Err(err) => {
+            let path = Settings::config_dir().join("config.toml");
+            checks.push(Check::problem(
+                "config",
+                Severity::Error,
+                format!("The config can't be loaded: {err:#}"),
+                format!("Fix the config file at {path:?} or remove it to restore the defaults"),
+            ));
+            return Report { checks };
+        }
+    };
+
+    checks.extend(check_database(&settings).await);
+
+    let key = check_key(&settings, &mut checks);
+
+    let options = ClientOptions {
+        retries: 0,
+        ..ClientOptions::from(&settings)
+    };
+    let reachable = check_server(&settings, &options, &mut checks).await;
+    if reachable {
+        check_session(&settings, &options, key.as_ref(), &mut checks).await;
+    }
+
+    checks.push(check_git());
+
+    Report { checks }
+}
+
+async fn check_database(settings: &Settings) -> Vec<Check> {
+    let path = Path::new(&settings.db_path);
+
+    if !path.exists() {
+        let writable = path
+            .parent()
+            .map(|x| !x.exists() || is_dir_writable(x))
+            .unwrap_or(false);
+        if writable {
+            return vec![Check::info(
+                "database",
+                format!("No database at {path:?} yet. It's created on the first use"),
+            )];
+        }
+        return vec![Check::problem(
+            "database",
+            Severity::Error,
+            format!("The database can't be created at {path:?}"),
+            "Make the directory writable or point `db_path` in the config to another location",
+        )];
+    }
+
+    if let Err(err) = OpenOptions::new().read(true).write(true).open(path) {
+        return vec![Check::problem(
+            "database",
+            Severity::Error,
+            format!("The database at {path:?} is not writable: {err}"),
+            "Fix the permissions of the database file",
+        )];
+    }
+
+    let state = match Database::migration_state(path).await {
+        Ok(state) => state,
+        Err(err) => {
+            return vec![Check::problem(
+                "database",
+                Severity::Error,
+                format!("The database at {path:?} can't be read: {err:#}"),
+                "Restore the database from a backup or remove it and run `dirpin sync`",
+            )]
+        }
+    };
+
+    let mut checks = vec![check_migrations(&state)];
+    // The rest needs the current schema and opening the database would migrate it.
+    if !state.pending.is_empty() || !state.unknown.is_empty() || !state.failed.is_empty() {
+        return checks;
+    }
+
+    match Database::new(path).await {
+        Ok(db) => checks.extend(check_records(&db).await),
+        Err(err) => checks.push(Check::problem(
+            "database",
+            Severity::Error,
+            format!("The database at {path:?} can't be opened: {err:#}"),
+            "Restore the database from a backup or remove it and run `dirpin sync`",
+        )),
+    }
+
+    checks
+}
+
+fn is_dir_writable(path: &Path) -> bool {
+    path.metadata()
+        .map(|x| x.is_dir() && !x.permissions().readonly())
+        .unwrap_or(false)
+}
+
+fn check_migrations(state: &MigrationState) -> Check {
+    if !state.failed.is_empty() {
+        return Check::problem(
+            "migrations",
+            Severity::Error,
+            format!("Migrations {:?} did not finish", state.failed),
+            "Restore the database from a backup or remove it and run `dirpin sync`",
+        );
+    }
+
+    if !state.unknown.is_empty() {
+        return Check::problem(
+            "migrations",
+            Severity::Error,
+            format!(
+                "The database was migrated by a newer dirpin ({:?})",
+                state.unknown
+            ),
+            "Upgrade dirpin on this host",
+        );
+    }
+
+    if !state.pending.is_empty() {
+        return Check::problem(
+            "migrations",
+            Severity::Warning,
+            format!(
+                "{} migrations are pending: {}",
+                state.pending.len(),
+                state.pending.join(", ")
+            ),
+            "Run any command that uses the database, e.g. `dirpin list`, to apply them",
+        );
+    }
+
+    Check::ok(
+        "migrations",
+        format!("{} migrations are applied", state.applied),
+    )
+}
+
+async fn check_records(db: &Database) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    match db.count_orphaned_entries().await {
+        Ok(0) => checks.push(Check::ok("workspaces", "All entries have their workspace")),
+        Ok(count) => checks.push(Check::problem(
+            "workspaces",
+            Severity::Warning,
+            format!("{count} entries point to a workspace that does not exist"),
+            "Run `dirpin sync` to download the missing workspaces",
+        )),
+        Err(err) => checks.push(Check::problem(
+            "workspaces",
+            Severity::Error,
+            format!("The workspaces can't be checked: {err:#}"),
+            "Restore the database from a backup or remove it and run `dirpin sync`",
+        )),
+    }
+
+    match db.list_conflicts().await {
+        Ok(conflicts) if conflicts.is_empty() => {
+            checks.push(Check::ok("conflicts", "No pending conflicts"))
+        }
+        Ok(conflicts) => checks.push(Check::problem(
+            "conflicts",
+            Severity::Warning,
+            format!("{} sync conflicts are pending", conflicts.len()),
+            "Edit the affected records and run `dirpin sync` to upload the version to keep",
+        )),
+        Err(err) => checks.push(Check::problem(
+            "conflicts",
+            Severity::Error,
+            format!("The conflicts can't be read: {err:#}"),
+            "Restore the database from a backup or remove it and run `dirpin sync`",
+        )),
+    }
+
+    match db.list_quarantined().await {
+        Ok(records) if records.is_empty() => {
+            checks.push(Check::ok("quarantine", "No quarantined records"))
+        }
+        Ok(records) => checks.push(Check::problem(
+            "quarantine",
+            Severity::Warning,
+            format!("{} remote records could not be read", records.len()),
+            "Fix the cause and run `dirpin doctor --retry-quarantined`, or drop them with \
+            `dirpin doctor --purge-quarantined`",
+        )),
+        Err(err) => checks.push(Check::problem(
+            "quarantine",
+            Severity::Error,
+            format!("The quarantined records can't be read: {err:#}"),
+            "Restore the database from a backup or remove it and run `dirpin sync`",
+        )),
+    }
+
+    checks
+}
+
+fn check_key(settings: &Settings, checks: &mut Vec<Check>) -> Option<Key> {
+    let path = Path::new(&settings.key_path);
+
+    if !path.exists() {
+        checks.push(Check::problem(
+            "key",
+            Severity::Warning,
+            format!("No key at {path:?}"),
+            "Run `dirpin account login` on a new host, or `dirpin key use` to restore the key of the \
+            account",
+        ));
+        return None;
+    }
+
+    let key = match encryption::read_key(path) {
+        Ok(key) => key,
+        Err(err) => {
+            checks.push(Check::problem(
+                "key",
+                Severity::Error,
+                format!("The key at {path:?} is not valid: {err:#}"),
+                "Restore the key with `dirpin key use`",
+            ));
+            return None;
+        }
+    };
+
+    checks.push(Check::ok("key", format!("Key {}", key_id(&key))));
+
+    match encryption::read_previous_keys(settings) {
+        Ok(keys) if !keys.is_empty() => checks.push(Check::info(
+            "previous keys",
+            format!(
+                "{} previous keys are kept to read old records. Remove them with `dirpin key prune`",
+                keys.len()
+            ),
+        )),
+        Ok(_) => {}
+        Err(err) => checks.push(Check::problem(
+            "previous keys",
+            Severity::Warning,
+            format!("The previous keys can't be read: {err:#}"),
+            "Remove the broken file and restore the keys with `dirpin key use`",
+        )),
+    }
+
+    Some(key)
+}
+
+/// Returns true when the server answered and speaks our protocol.
+async fn check_server(
+    settings: &Settings,
+    options: &ClientOptions,
+    checks: &mut Vec<Check>,
+) -> bool {
+    let address = settings.server_address.as_str();
+    let started_at = OffsetDateTime::now_utc();
+    let res = api_client::health_check(address, options).await;
+    let finished_at = OffsetDateTime::now_utc();
+
+    let res = match res {
+        Ok(res) => res,
+        Err(err) => {
+            checks.push(Check::problem(
+                "server",
+                Severity::Error,
+                format!("The server at {address} is not reachable: {err:#}"),
+                "Check that the server is running and `server_address` in the config points to it",
+            ));
+            return false;
+        }
+    };
+
+    let compatible = check_compatibility(address, &res, checks);
+
+    // Assume the server answered half way through the request.
+    let local = started_at + (finished_at - started_at) / 2;
+    checks.push(check_clock_skew(res.time, local));
+
+    compatible
+}
+
+fn check_compatibility(address: &str, res: &HealthCheckResponse, checks: &mut Vec<Check>) -> bool {
+    if res.api_version == 0 {
+        checks.push(Check::problem(
+            "server",
+            Severity::Warning,
+            format!(
+                "The server at {address} ({}) does not support version negotiation",
+                res.version
+            ),
+            "Upgrade the server",
+        ));
+        return true;
+    }
+
+    let compatibility =
+        Compatibility::check(API_VERSION, res.min_client_version, res.max_client_version);
+    let fix = match compatibility {
+        Compatibility::Compatible => {
+            checks.push(Check::ok(
+                "server",
+                format!("The server at {address} ({}) is compatible", res.version),
+            ));
+            return true;
+        }
+        Compatibility::ClientTooOld => "Upgrade dirpin on this host",
+        Compatibility::ClientTooNew => "Upgrade the server",
+    };
+    checks.push(Check::problem(
+        "server",
+        Severity::Error,
+        format!(
+            "The server at {address} ({}) accepts api versions {} - {}, the {compatibility} with {API_VERSION}",
+            res.version, res.min_client_version, res.max_client_version
+        ),
+        fix,
+    ));
+
+    false
+}
+
+fn check_clock_skew(server: Option<OffsetDateTime>, local: OffsetDateTime) -> Check {
+    let Some(server) = server else {
+        return Check::info("clock", "The server does not report its time");
+    };
+
+    let skew = local - server;
+    if skew.abs() > MAX_CLOCK_SKEW {
+        let direction = if skew.is_positive() {
+            "ahead of"
+        } else {
+            "behind"
+        };
+        return Check::problem(
+            "clock",
+            Severity::Warning,
+            format!(
+                "The clock of this host is {}s {direction} the server",
+                skew.whole_seconds().abs()
+            ),
+            "Enable time synchronisation (NTP) on this host and the server",
+        );
+    }
+
+    Check::ok("clock", "The clock matches the server")
+}
+
+async fn check_session(
+    settings: &Settings,
+    options: &ClientOptions,
+    key: Option<&Key>,
+    checks: &mut Vec<Check>,
+) {
+    let Some(session) = settings.session() else {
+        checks.push(Check::problem(
+            "session",
+            Severity::Warning,
+            "Not logged in, nothing is synced",
+            "Run `dirpin account login` or `dirpin account register`",
+        ));
+        return;
+    };
+
+    let status = match AuthClient::with_options(&settings.server_address, &session, options) {
+        Ok(client) => client.status().await,
+        Err(err) => Err(err),
+    };
+
+    match status {
+        Ok(status) => {
+            checks.push(Check::ok(
+                "session",
+                format!("Logged in as {}", status.username),
+            ));
+            if let Some(key) = key {
+                checks.push(check_account_key(&status, key));
+            }
+        }
+        Err(err) => {
+            let rejected = err.chain().any(|x| {
+                matches!(
+                    x.downcast_ref::<ApiError>(),
+                    Some(ApiError::Unauthorized(_))
+                )
+            });
+            if rejected {
+                checks.push(Check::problem(
+                    "session",
+                    Severity::Error,
+                    "The server rejected the session",
+                    "Log in again with `dirpin account login`",
+                ));
+            } else {
+                checks.push(Check::problem(
+                    "session",
+                    Severity::Warning,
+                    format!("The session can't be verified: {err:#}"),
+                    "Run `dirpin doctor` again once the server is reachable",
+                ));
+            }
+        }
+    }
+}
+
+fn check_account_key(status: &StatusResponse, key: &Key) -> Check {
+    let local = key_id(key);
+    match &status.key_id {
+        Some(remote) if *remote != local => Check::problem(
+            "account key",
+            Severity::Error,
+            format!("The account uses key {remote} but this host has key {local}"),
+            "Get the current key from another host with `dirpin key` and run `dirpin key use`",
+        ),
+        Some(_) => Check::ok("account key", "The key matches the account"),
+        None => Check::info("account key", "Nothing is uploaded to the account yet"),
+    }
+}
+
+fn check_git() -> Check {
+    let output = std::process::Command::new("git").arg("--version").output();
+
+    match output {
+        Ok(output) if output.status.success() => Check::ok(
+            "git",
+            String::from_utf8_lossy(&output.stdout).trim().to_string(),
+        ),
+        Ok(output) => Check::problem(
+            "git",
+            Severity::Warning,
+            format!(
+                "git failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            "Repair the git installation",
+        ),
+        Err(err) => Check::problem(
+            "git",
+            Severity::Warning,
+            format!("git can't be run ({err}), workspaces are not matched by the git remote"),
+            "Install git and make sure it is on the PATH",
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_clock_skew, check_key, check_migrations, check_session, Severity, MAX_CLOCK_SKEW,
+    };
+    use crate::api_client::ClientOptions;
+    use crate::database::MigrationState;
+    use crate::settings::Settings;
+    use time::{Duration, OffsetDateTime};
+
+    #[test]
+    fn clock_skew_over_the_limit_is_a_warning() {
+        let now = OffsetDateTime::now_utc();
+
+        let check = check_clock_skew(Some(now), now + Duration::seconds(1));
+        assert_eq!(check.severity, Severity::Ok);
+
+        let check = check_clock_skew(Some(now), now - MAX_CLOCK_SKEW - Duration::seconds(1));
+        assert_eq!(check.severity, Severity::Warning);
+        assert!(check.message.contains("behind"));
+
+        let check = check_clock_skew(None, now);
+        assert_eq!(check.severity, Severity::Info);
+    }
+
+    #[test]
+    fn migrations_from_a_newer_client_are_an_error() {
+        let check = check_migrations(&MigrationState {
+            applied: 5,
+            ..Default::default()
+        });
+        assert_eq!(check.severity, Severity::Ok);
+
+        let check = check_migrations(&MigrationState {
+            applied: 4,
+            pending: vec!["20241205100000 quarantine-table".into()],
+            ..Default::default()
+        });
+        assert_eq!(check.severity, Severity::Warning);
+
+        let check = check_migrations(&MigrationState {
+            applied: 5,
+            unknown: vec![20991231000000],
+            ..Default::default()
+        });
+        assert_eq!(check.severity, Severity::Error);
+    }
+
+    #[tokio::test]
+    async fn a_new_host_is_pointed_to_the_account_commands() {
+        // Nothing is written, the files only must not exist.
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let settings = Settings {
+            db_path: path("dirpin.db"),
+            key_path: path("key"),
+            session_path: path("session"),
+            server_address: "http://127.0.0.1:1".into(),
+            network_connect_timeout: 1,
+            network_timeout: 1,
+            network_retries: 0,
+            tls_ca: None,
+            tls_pin: None,
+            tombstone_retention: 30,
+            sync_history: false,
+        };
+
+        let mut checks = vec![];
+        assert!(check_key(&settings, &mut checks).is_none());
+        check_session(&settings, &ClientOptions::default(), None, &mut checks).await;
+
+        let fixes = checks.iter().map(|x| x.fix.as_deref()).collect::<Vec<_>>();
+        assert_eq!(
+            fixes,
+            [
+                Some(
+                    "Run `dirpin account login` on a new host, or `dirpin key use` to restore \
+                    the key of the account"
+                ),
+                Some("Run `dirpin account login` or `dirpin account register`"),
+            ]
+        );
+    }
+}
@@ -2,4 +2,6 @@ pub mod conflict;
 pub mod context;
 pub mod entry;
 pub mod host;
+pub mod quarantine;
+pub mod revision;
 pub mod workspace;
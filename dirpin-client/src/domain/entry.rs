@@ -1,10 +1,11 @@
 use crate::domain::host::HostId;
 use crate::domain::workspace::WorkspaceId;
-use crate::encryption::{rmp_error_report, MsgPackSerializable};
+use crate::encryption::{
+    rmp_error_report, MsgPackSerializable, RecordReader, RecordWriter, UnknownFields,
+};
 use dirpin_common::domain::SyncVersion;
-use eyre::{bail, Result};
+use eyre::{bail, eyre, Result};
 use rmp::decode::{self, Bytes, DecodeStringError};
-use rmp::encode;
 use rmp::Marker;
 use std::str::FromStr;
 use time::format_description::well_known::Rfc3339;
@@ -17,6 +18,8 @@ pub enum EntryKind {
     Note,
     Cmd,
     Todo,
+    /// A kind from a newer client. It's kept as it is so that it syncs back unchanged.
+    Other(String),
 }
 
 impl FromStr for EntryKind {
@@ -27,17 +30,19 @@ impl FromStr for EntryKind {
             "note" => Ok(Self::Note),
             "cmd" => Ok(Self::Cmd),
             "todo" => Ok(Self::Todo),
-            _ => Ok(Self::Note),
+            "" => Err("Entry kind is empty".into()),
+            _ => Ok(Self::Other(s.to_string())),
         }
     }
 }
 
 impl EntryKind {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             EntryKind::Note => "note",
             EntryKind::Cmd => "cmd",
             EntryKind::Todo => "todo",
+            EntryKind::Other(kind) => kind,
         }
     }
 }
@@ -61,9 +66,13 @@ pub struct Entry {
     pub path: String,
     pub workspace_id: Option<WorkspaceId>,
     pub host_id: HostId,
+    /// Fields from newer clients that this client does not know about
+    #[serde(default)]
+    pub extra: UnknownFields,
 }
 
 impl Entry {
+    /// Number of fields in the version 0 record format
     const FIELD_LEN: u32 = 11;
 
     pub fn new(
@@ -86,6 +95,7 @@ impl Entry {
             path,
             workspace_id,
             host_id,
+            extra: UnknownFields::default(),
         }
     }
 
@@ -95,56 +105,58 @@ impl Entry {
     }
 }
 
-// TODO: I did it withouth serde for the learning process with message pack.
-// Maybe we should move to serde deserialize and serialize as this seems rather
-// error prone and ugly :D
 impl MsgPackSerializable for Entry {
     fn encode_msgpack(&self) -> Result<Vec<u8>> {
-        let mut count = 0;
-        let mut output = Vec::new();
-        encode::write_array_len(&mut output, Self::FIELD_LEN)?;
-
-        encode::write_str(&mut output, &self.id.to_string())?;
-        count += 1;
-        encode::write_str(&mut output, &self.value)?;
-        count += 1;
-        match &self.desc {
-            Some(v) => encode::write_str(&mut output, &v)?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        match &self.data {
-            Some(v) => encode::write_str(&mut output, &v)?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        encode::write_str(&mut output, &self.path)?;
-        count += 1;
-        encode::write_str(&mut output, &self.kind.to_string())?;
-        count += 1;
-        encode::write_str(&mut output, &self.updated_at.format(&Rfc3339)?)?;
-        count += 1;
-        match self.deleted_at {
-            Some(v) => encode::write_str(&mut output, &v.format(&Rfc3339)?)?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        encode::write_u32(&mut output, self.version.inner())?;
-        count += 1;
-        match &self.workspace_id {
-            Some(v) => encode::write_str(&mut output, &v.to_string())?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        encode::write_str(&mut output, &self.host_id.to_string())?;
-        count += 1;
+        RecordWriter::new()
+            .str("id", &self.id.to_string())
+            .str("value", &self.value)
+            .opt_str("desc", self.desc.as_deref())
+            .opt_str("data", self.data.as_deref())
+            .str("path", &self.path)
+            .str("kind", self.kind.as_str())
+            .str("updated_at", &self.updated_at.format(&Rfc3339)?)
+            .opt_str(
+                "deleted_at",
+                self.deleted_at
+                    .map(|x| x.format(&Rfc3339))
+                    .transpose()?
+                    .as_deref(),
+            )
+            .u32("version", self.version.inner())
+            .opt_str(
+                "workspace_id",
+                self.workspace_id.as_ref().map(|x| x.to_string()).as_deref(),
+            )
+            .str("host_id", self.host_id.as_ref())
+            .finish(&self.extra)
+    }
 
-        assert_eq!(count, Self::FIELD_LEN);
+    fn decode_msgpack(input: &[u8]) -> Result<Entry> {
+        let mut record = RecordReader::new(input)?;
 
-        Ok(output)
+        Ok(Entry {
+            id: Uuid::parse_str(&record.str("id")?)?,
+            value: record.str("value")?,
+            desc: record.opt_str("desc")?,
+            data: record.opt_str("data")?,
+            path: record.str("path")?,
+            kind: EntryKind::from_str(&record.str("kind")?).map_err(|e| eyre!(e))?,
+            updated_at: OffsetDateTime::parse(&record.str("updated_at")?, &Rfc3339)?,
+            deleted_at: record
+                .opt_str("deleted_at")?
+                .map(|x| OffsetDateTime::parse(&x, &Rfc3339))
+                .transpose()?,
+            version: SyncVersion::from(record.u32("version")?),
+            workspace_id: record.opt_str("workspace_id")?.and_then(|x| x.parse().ok()),
+            host_id: HostId::from_str(&record.str("host_id")?).map_err(|e| eyre!(e))?,
+            extra: record.into_unknown()?,
+        })
     }
 
-    fn decode_msgpack(input: &[u8]) -> Result<Entry> {
+    // TODO: I did it withouth serde for the learning process with message pack.
+    // Maybe we should move to serde deserialize and serialize as this seems rather
+    // error prone and ugly :D
+    fn decode_msgpack_v0(input: &[u8]) -> Result<Entry> {
         let mut count = 0;
         let mut bytes = Bytes::new(input);
         let len = decode::read_array_len(&mut bytes).map_err(rmp_error_report)?;
@@ -224,7 +236,7 @@ impl MsgPackSerializable for Entry {
             value: value.to_owned(),
             desc: desc.map(|x| x.to_owned()),
             data: data.map(|x| x.to_owned()),
-            kind: EntryKind::from_str(kind).unwrap(),
+            kind: EntryKind::from_str(kind).map_err(|e| eyre!(e))?,
             path: path.to_owned(),
             updated_at: OffsetDateTime::parse(updated_at, &Rfc3339)?,
             deleted_at: deleted_at
@@ -232,7 +244,8 @@ impl MsgPackSerializable for Entry {
                 .transpose()?,
             version: SyncVersion::from(version),
             workspace_id: workspace_id.map(|x| x.parse().ok()).flatten(),
-            host_id: HostId::from_str(host_id).unwrap(),
+            host_id: HostId::from_str(host_id).map_err(|e| eyre!(e))?,
+            extra: UnknownFields::default(),
         })
     }
 }
@@ -0,0 +1,19 @@
+use time::OffsetDateTime;
+
+/// Remote record that failed to decode or decrypt. It's kept aside with the raw payload so that
+/// the sync can go on with the healthy records.
+#[derive(Debug, Clone)]
+pub struct Quarantined {
+    pub id: i64,
+    pub kind: String,
+    pub data: String,
+    pub error: String,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewQuarantined {
+    pub kind: String,
+    pub data: String,
+    pub error: String,
+}
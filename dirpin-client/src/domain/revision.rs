@@ -0,0 +1,103 @@
+use crate::domain::entry::Entry;
+use crate::encryption::{MsgPackSerializable, RecordReader, RecordWriter, UnknownFields};
+use eyre::{bail, eyre, Result};
+use std::str::FromStr;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum RevisionOperation {
+    Add,
+    Save,
+    Delete,
+    Restore,
+}
+
+impl RevisionOperation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Save => "save",
+            Self::Delete => "delete",
+            Self::Restore => "restore",
+        }
+    }
+}
+
+impl FromStr for RevisionOperation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "add" => Ok(Self::Add),
+            "save" => Ok(Self::Save),
+            "delete" => Ok(Self::Delete),
+            "restore" => Ok(Self::Restore),
+            _ => Err("Failed to parse RevisionOperation from string".into()),
+        }
+    }
+}
+
+impl std::fmt::Display for RevisionOperation {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A prior state of an entry. It's recorded every time the entry is overwritten or deleted, so
+/// that the change can be undone. An entry added on this host starts with an add of its first
+/// state. Revisions stay on the host unless `sync_history` is turned on.
+#[derive(Debug, Clone)]
+pub struct Revision {
+    /// Id of the revision across the hosts. The revisions from before the history sync don't
+    /// have it.
+    pub uid: Option<Uuid>,
+    /// Order of the operations on this host
+    pub seq: i64,
+    /// Revision number of the entry on this host, from 1
+    pub rev: u32,
+    /// The operation that replaced this state
+    pub operation: RevisionOperation,
+    /// Whether the operation was made on this host or came with a sync
+    pub local: bool,
+    pub created_at: OffsetDateTime,
+    /// The state of the entry before the operation, or the added state of an add
+    pub entry: Entry,
+}
+
+/// Only the fields that mean the same on every host are synced. The order and the revision
+/// number are given again by the host that receives it.
+impl MsgPackSerializable for Revision {
+    fn encode_msgpack(&self) -> Result<Vec<u8>> {
+        let uid = self
+            .uid
+            .ok_or_else(|| eyre!("Revision {} of {} has no uid", self.rev, self.entry.id))?;
+
+        RecordWriter::new()
+            .str("uid", &uid.to_string())
+            .str("operation", self.operation.as_str())
+            .str("created_at", &self.created_at.format(&Rfc3339)?)
+            .bin("entry", &self.entry.encode_msgpack()?)
+            .finish(&UnknownFields::default())
+    }
+
+    fn decode_msgpack(input: &[u8]) -> Result<Revision> {
+        let mut record = RecordReader::new(input)?;
+
+        Ok(Revision {
+            uid: Some(Uuid::parse_str(&record.str("uid")?)?),
+            seq: 0,
+            rev: 0,
+            operation: RevisionOperation::from_str(&record.str("operation")?)
+                .map_err(|e| eyre!(e))?,
+            local: false,
+            created_at: OffsetDateTime::parse(&record.str("created_at")?, &Rfc3339)?,
+            entry: Entry::decode_msgpack(&record.bin("entry")?)?,
+        })
+    }
+
+    fn decode_msgpack_v0(_input: &[u8]) -> Result<Revision> {
+        bail!("Revisions are synced only in the map format")
+    }
+}
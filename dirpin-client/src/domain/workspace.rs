@@ -1,8 +1,10 @@
 use crate::domain::context::Context;
 use crate::domain::host::HostId;
-use crate::encryption::{rmp_error_report, MsgPackSerializable};
+use crate::encryption::{
+    rmp_error_report, MsgPackSerializable, RecordReader, RecordWriter, UnknownFields,
+};
 use dirpin_common::domain::SyncVersion;
-use eyre::{bail, Result};
+use eyre::{bail, eyre, Result};
 use std::str::FromStr;
 use time::format_description::well_known::Rfc3339;
 use time::OffsetDateTime;
@@ -75,9 +77,13 @@ pub struct Workspace {
     pub updated_at: OffsetDateTime,
     pub deleted_at: Option<OffsetDateTime>,
     pub version: SyncVersion,
+    /// Fields from newer clients that this client does not know about
+    #[serde(default)]
+    pub extra: UnknownFields,
 }
 
 impl Workspace {
+    /// Number of fields in the version 0 record format
     const FIELD_LEN: u32 = 7;
 
     pub fn new(name: String, context: &Context) -> Self {
@@ -93,56 +99,64 @@ impl Workspace {
             updated_at: OffsetDateTime::now_utc(),
             deleted_at: None,
             version: SyncVersion::new(),
+            extra: UnknownFields::default(),
         }
     }
 }
 
-// TODO: I did it withouth serde for the learning process with message pack.
-// Maybe we should move to serde deserialize and serialize as this seems rather
-// error prone and ugly :D
 impl MsgPackSerializable for Workspace {
     fn encode_msgpack(&self) -> Result<Vec<u8>> {
-        use rmp::encode;
-
-        let mut count = 0;
-        let mut output = Vec::new();
-        encode::write_array_len(&mut output, Self::FIELD_LEN)?;
-
-        encode::write_str(&mut output, &self.id.to_string())?;
-        count += 1;
-        encode::write_str(&mut output, &self.name)?;
-        count += 1;
-        match &self.git {
-            Some(v) => encode::write_str(&mut output, &v)?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        encode::write_str(
-            &mut output,
-            &self
-                .paths
-                .iter()
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join(","),
-        )?;
-        count += 1;
-        encode::write_str(&mut output, &self.updated_at.format(&Rfc3339)?)?;
-        count += 1;
-        match self.deleted_at {
-            Some(v) => encode::write_str(&mut output, &v.format(&Rfc3339)?)?,
-            None => encode::write_nil(&mut output)?,
-        }
-        count += 1;
-        encode::write_u32(&mut output, self.version.inner())?;
-        count += 1;
+        RecordWriter::new()
+            .str("id", &self.id.to_string())
+            .str("name", &self.name)
+            .opt_str("git", self.git.as_deref())
+            .str(
+                "paths",
+                &self
+                    .paths
+                    .iter()
+                    .map(|x| x.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )
+            .str("updated_at", &self.updated_at.format(&Rfc3339)?)
+            .opt_str(
+                "deleted_at",
+                self.deleted_at
+                    .map(|x| x.format(&Rfc3339))
+                    .transpose()?
+                    .as_deref(),
+            )
+            .u32("version", self.version.inner())
+            .finish(&self.extra)
+    }
 
-        assert_eq!(count, Self::FIELD_LEN);
+    fn decode_msgpack(input: &[u8]) -> Result<Workspace> {
+        let mut record = RecordReader::new(input)?;
 
-        Ok(output)
+        Ok(Workspace {
+            id: WorkspaceId::from_str(&record.str("id")?)?,
+            name: record.str("name")?,
+            git: record.opt_str("git")?,
+            paths: record
+                .str("paths")?
+                .split(",")
+                .map(|x| WorkspacePath::try_from(x).map_err(|e| eyre!(e)))
+                .collect::<Result<_>>()?,
+            updated_at: OffsetDateTime::parse(&record.str("updated_at")?, &Rfc3339)?,
+            deleted_at: record
+                .opt_str("deleted_at")?
+                .map(|x| OffsetDateTime::parse(&x, &Rfc3339))
+                .transpose()?,
+            version: SyncVersion::from(record.u32("version")?),
+            extra: record.into_unknown()?,
+        })
     }
 
-    fn decode_msgpack(input: &[u8]) -> Result<Workspace> {
+    // TODO: I did it withouth serde for the learning process with message pack.
+    // Maybe we should move to serde deserialize and serialize as this seems rather
+    // error prone and ugly :D
+    fn decode_msgpack_v0(input: &[u8]) -> Result<Workspace> {
         use rmp::decode::{self, Bytes, DecodeStringError};
         use rmp::Marker;
 
@@ -202,13 +216,14 @@ impl MsgPackSerializable for Workspace {
             git: git.map(|x| x.to_owned()),
             paths: paths
                 .split(",")
-                .map(|x| WorkspacePath::try_from(x).expect("failed to parse workspace path"))
-                .collect(),
+                .map(|x| WorkspacePath::try_from(x).map_err(|e| eyre!(e)))
+                .collect::<Result<_>>()?,
             updated_at: OffsetDateTime::parse(updated_at, &Rfc3339)?,
             deleted_at: deleted_at
                 .map(|x| OffsetDateTime::parse(x, &Rfc3339))
                 .transpose()?,
             version: SyncVersion::from(version),
+            extra: UnknownFields::default(),
         })
     }
 }
@@ -1,16 +1,35 @@
 use crate::settings::Settings;
+use argon2::{Algorithm, Argon2, Params, Version};
 use base64::prelude::{Engine, BASE64_STANDARD};
+use bip39::{Language, Mnemonic};
 use crypto_secretbox::aead::{AeadCore, AeadInPlace, Nonce, OsRng};
-use crypto_secretbox::{Key, KeyInit, XSalsa20Poly1305};
+use crypto_secretbox::{KeyInit, XSalsa20Poly1305};
 use eyre::{bail, ensure, eyre, Context, Report, Result};
 use fs_err as fs;
+use rmpv::Value;
+use sha2::{Digest, Sha256};
 use std::convert::TryInto;
 use std::io::Write;
 use std::path::Path;
 use std::path::PathBuf;
 
+pub use crypto_secretbox::Key;
+
+/// The oldest sync protocol version that is able to decode the records this client encrypts.
+pub const MIN_READER_API_VERSION: u32 = 2;
+
+/// Format of the decrypted record payload.
+/// 0 - message pack array with a fixed number of fields. Only read for backwards compatibility.
+/// 1 - message pack map keyed by the field name. Keys unknown to the reader are kept as they are.
+///
+/// Every format from 1 onwards has to stay a map so that the older readers can still decode it.
+pub const RECORD_FORMAT_VERSION: u32 = 1;
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct EncryptedItem {
+    pub version: u32,
+    /// Id of the key the record is encrypted with. See `key_id`.
+    pub key_id: Option<String>,
     pub ciphertext: Vec<u8>,
     pub key: Vec<u8>,
     pub key_nonce: Nonce<XSalsa20Poly1305>,
@@ -19,6 +38,12 @@ pub struct EncryptedItem {
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct EncryptedItemEncoded {
+    /// Records from before the versioned format don't have the version.
+    #[serde(default)]
+    pub version: u32,
+    /// Records from before the key rotation don't have the key id.
+    #[serde(default)]
+    pub key_id: Option<String>,
     pub ciphertext: String,
     pub key: String,
     pub key_nonce: String,
@@ -28,6 +53,8 @@ pub struct EncryptedItemEncoded {
 impl From<EncryptedItem> for EncryptedItemEncoded {
     fn from(item: EncryptedItem) -> Self {
         Self {
+            version: item.version,
+            key_id: item.key_id,
             ciphertext: BASE64_STANDARD.encode(item.ciphertext),
             key: BASE64_STANDARD.encode(item.key),
             key_nonce: BASE64_STANDARD.encode(item.key_nonce),
@@ -52,6 +79,8 @@ impl TryFrom<EncryptedItemEncoded> for EncryptedItem {
         let nonce = Nonce::<XSalsa20Poly1305>::clone_from_slice(&nonce);
 
         let value = Self {
+            version: item.version,
+            key_id: item.key_id,
             ciphertext: BASE64_STANDARD.decode(item.ciphertext)?,
             key: BASE64_STANDARD.decode(item.key)?,
             key_nonce,
@@ -116,6 +145,164 @@ pub fn load_key(settings: &Settings) -> Result<Key> {
     Ok(key)
 }
 
+/// Argon2id cost of the passphrase derived key. Changing any of these changes the derived key, so
+/// they are fixed instead of following the defaults of the crate.
+const KEY_DERIVATION_MEMORY_KIB: u32 = 19 * 1024;
+const KEY_DERIVATION_ITERATIONS: u32 = 2;
+const KEY_DERIVATION_PARALLELISM: u32 = 1;
+const KEY_SALT_LEN: usize = 16;
+
+/// Random salt for a passphrase derived key. It's stored on the server with the account so that
+/// a new host only needs the passphrase.
+pub fn generate_key_salt() -> String {
+    let salt: [u8; KEY_SALT_LEN] = rand::random();
+    BASE64_STANDARD.encode(salt)
+}
+
+/// Derive the key from the passphrase with Argon2id.
+pub fn derive_key(passphrase: &str, salt: &str) -> Result<Key> {
+    ensure!(!passphrase.is_empty(), "Passphrase can not be empty");
+    let salt = BASE64_STANDARD
+        .decode(salt)
+        .context("Failed to decode key salt from base64")?;
+
+    let params = Params::new(
+        KEY_DERIVATION_MEMORY_KIB,
+        KEY_DERIVATION_ITERATIONS,
+        KEY_DERIVATION_PARALLELISM,
+        Some(32),
+    )
+    .map_err(|err| eyre!("Invalid key derivation params: {err}"))?;
+
+    let mut key = Key::default();
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|err| eyre!("Failed to derive key from passphrase: {err}"))?;
+
+    Ok(key)
+}
+
+/// Short public identifier of the key. It's safe to share it with the server as it's derived
+/// with a one way hash.
+pub fn key_id(key: &Key) -> String {
+    let hash = Sha256::digest(key.as_slice());
+    hash[..8].iter().map(|x| format!("{x:02x}")).collect()
+}
+
+/// The keys that were replaced by a rotation are kept next to the key file, one per line, until
+/// all the hosts switch to the new key.
+pub fn previous_keys_path(settings: &Settings) -> PathBuf {
+    PathBuf::from(format!("{}.previous", settings.key_path))
+}
+
+pub fn read_previous_keys(settings: &Settings) -> Result<Vec<Key>> {
+    let path = previous_keys_path(settings);
+
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    fs::read_to_string(path)
+        .wrap_err("Failed to read previous keys file")?
+        .lines()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| decode_key(x.to_string()))
+        .collect()
+}
+
+fn write_previous_keys(settings: &Settings, keys: &[Key]) -> Result<()> {
+    let path = previous_keys_path(settings);
+
+    if keys.is_empty() {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+
+    let mut buf = String::new();
+    for key in keys {
+        buf.push_str(&encode_key(key)?);
+        buf.push('\n');
+    }
+    fs::write(path, buf).wrap_err("Failed to write previous keys file")?;
+
+    Ok(())
+}
+
+pub fn add_previous_key(settings: &Settings, key: &Key) -> Result<()> {
+    let mut keys = read_previous_keys(settings)?;
+    if keys.iter().all(|x| key_id(x) != key_id(key)) {
+        keys.push(*key);
+    }
+    write_previous_keys(settings, &keys)
+}
+
+pub fn remove_previous_key(settings: &Settings, id: &str) -> Result<()> {
+    let mut keys = read_previous_keys(settings)?;
+    keys.retain(|x| key_id(x) != id);
+    write_previous_keys(settings, &keys)
+}
+
+/// Forget all the previous keys. Only safe once every host uses the current key.
+pub fn prune_previous_keys(settings: &Settings) -> Result<usize> {
+    let keys = read_previous_keys(settings)?;
+    write_previous_keys(settings, &[])?;
+    Ok(keys.len())
+}
+
+/// Replace the current key. The replaced key is kept as a previous key so that the records that
+/// are still encrypted with it can be read.
+pub fn use_key(settings: &Settings, key: &Key) -> Result<()> {
+    let path = PathBuf::from(settings.key_path.as_str());
+
+    if path.exists() {
+        let current = read_key(&path)?;
+        if key_id(&current) == key_id(key) {
+            return Ok(());
+        }
+        add_previous_key(settings, &current)?;
+    }
+
+    remove_previous_key(settings, &key_id(key))?;
+    fs::write(path, encode_key(key)?).wrap_err("Failed to write key file")?;
+
+    Ok(())
+}
+
+/// The current key with the keys replaced by rotations.
+pub struct Keyring {
+    pub current: Key,
+    pub previous: Vec<Key>,
+}
+
+impl Keyring {
+    pub fn new(current: Key) -> Self {
+        Self {
+            current,
+            previous: vec![],
+        }
+    }
+
+    /// Find the key for the record. Records without the key id are from before the rotation was
+    /// possible and use the current key.
+    pub fn get(&self, id: Option<&str>) -> Option<&Key> {
+        match id {
+            None => Some(&self.current),
+            Some(id) => std::iter::once(&self.current)
+                .chain(self.previous.iter())
+                .find(|x| key_id(x) == id),
+        }
+    }
+}
+
+pub fn load_keyring(settings: &Settings) -> Result<Keyring> {
+    Ok(Keyring {
+        current: load_key(settings)?,
+        previous: read_previous_keys(settings)?,
+    })
+}
+
 pub fn encode_key(key: &Key) -> Result<String> {
     let mut buf = vec![];
     rmp::encode::write_array_len(&mut buf, key.len() as u32)
@@ -146,18 +333,296 @@ pub fn decode_key(key: String) -> Result<Key> {
     Ok(key)
 }
 
+/// Encrypt a small value with the key. The nonce is prepended to the ciphertext.
+fn seal(value: &[u8], key: &Key) -> Result<String> {
+    let nonce = XSalsa20Poly1305::generate_nonce(&mut OsRng);
+    let mut buf = value.to_vec();
+    XSalsa20Poly1305::new(key)
+        .encrypt_in_place(&nonce, &[], &mut buf)
+        .map_err(|_| eyre!("Failed to encrypt data"))?;
+
+    let mut data = nonce.to_vec();
+    data.extend(buf);
+    Ok(BASE64_STANDARD.encode(data))
+}
+
+fn open(data: &str, key: &Key) -> Result<Vec<u8>> {
+    let data = BASE64_STANDARD
+        .decode(data)
+        .context("Failed to decode sealed data from base64")?;
+    ensure!(data.len() > 24, "Sealed data is too short");
+
+    let (nonce, ciphertext) = data.split_at(24);
+    let mut buf = ciphertext.to_vec();
+    XSalsa20Poly1305::new(key)
+        .decrypt_in_place(Nonce::<XSalsa20Poly1305>::from_slice(nonce), &[], &mut buf)
+        .map_err(|_| eyre!("Failed to decrypt data"))?;
+
+    Ok(buf)
+}
+
+/// Encrypt the key with another key, e.g. a one time secret to move the key between hosts.
+pub fn wrap_key(key: &Key, secret: &Key) -> Result<String> {
+    seal(key.as_slice(), secret)
+}
+
+pub fn unwrap_key(data: &str, secret: &Key) -> Result<Key> {
+    let buf = open(data, secret).map_err(|_| eyre!("Failed to decrypt key"))?;
+    ensure!(buf.len() == 32, "encryption key is not the correct size");
+
+    Ok(Key::clone_from_slice(&buf))
+}
+
+/// Plaintext of the key check. Decrypting it proves the key is the account key without
+/// touching any of the records.
+const KEY_CHECK_PLAINTEXT: &[u8] = b"dirpin key check";
+
+/// Record stored with the account to verify the key of every host.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct KeyCheck {
+    key_id: String,
+    data: String,
+}
+
+pub fn create_key_check(key: &Key) -> Result<String> {
+    let check = KeyCheck {
+        key_id: key_id(key),
+        data: seal(KEY_CHECK_PLAINTEXT, key)?,
+    };
+    serde_json::to_string(&check).wrap_err("Failed to serialize key check")
+}
+
+/// Make sure the key is the one the account data is encrypted with.
+pub fn verify_key_check(value: &str, key: &Key) -> Result<()> {
+    let check: KeyCheck = serde_json::from_str(value).wrap_err("Failed to parse key check")?;
+    let local = key_id(key);
+
+    ensure!(
+        check.key_id == local,
+        "The key {local} is not the key {} of the account",
+        check.key_id
+    );
+    match open(&check.data, key) {
+        Ok(plaintext) if plaintext == KEY_CHECK_PLAINTEXT => Ok(()),
+        _ => bail!("The key {local} failed to decrypt the key check of the account"),
+    }
+}
+
+/// Number of words of the key mnemonic. 256 bits of the key with 8 bits of checksum.
+const KEY_MNEMONIC_WORDS: usize = 24;
+
+/// Encode the key as a BIP39 english word list. The last word carries the checksum.
+pub fn encode_key_mnemonic(key: &Key) -> Result<String> {
+    let mnemonic = Mnemonic::from_entropy_in(Language::English, key.as_slice())
+        .map_err(|err| eyre!("Failed to encode key to mnemonic: {err}"))?;
+    Ok(mnemonic.to_string())
+}
+
+pub fn decode_key_mnemonic(value: &str) -> Result<Key> {
+    let value = value.to_lowercase();
+    let words = value.split_whitespace().collect::<Vec<_>>();
+    ensure!(
+        words.len() == KEY_MNEMONIC_WORDS,
+        "The key mnemonic has {} words but it must have {KEY_MNEMONIC_WORDS}",
+        words.len()
+    );
+
+    let mnemonic = Mnemonic::parse_in(Language::English, words.join(" ")).map_err(|err| match err {
+        bip39::Error::UnknownWord(i) => eyre!(
+            "The word {} \"{}\" of the key mnemonic is not in the word list",
+            i + 1,
+            words[i]
+        ),
+        bip39::Error::InvalidChecksum => {
+            eyre!("The checksum of the key mnemonic is wrong. Check the order and the spelling of the words")
+        }
+        err => eyre!("Invalid key mnemonic: {err}"),
+    })?;
+
+    Ok(Key::clone_from_slice(&mnemonic.to_entropy()))
+}
+
+/// Parse the key in either of the forms the user can have it. The base64 form is a single word.
+pub fn parse_key(value: &str) -> Result<Key> {
+    let value = value.trim();
+
+    if value.split_whitespace().count() > 1 {
+        decode_key_mnemonic(value)
+    } else {
+        decode_key(value.to_string()).map_err(|_| eyre!("Provided key seems to be invalid"))
+    }
+}
+
 pub trait MsgPackSerializable: Sized {
+    /// Encode in the current `RECORD_FORMAT_VERSION`
     fn encode_msgpack(&self) -> Result<Vec<u8>>;
+    /// Decode the map keyed format from version 1 onwards
     fn decode_msgpack(input: &[u8]) -> Result<Self>;
+    /// Decode the fixed array format of version 0
+    fn decode_msgpack_v0(input: &[u8]) -> Result<Self>;
 }
 
 pub fn rmp_error_report<E: std::fmt::Debug>(err: E) -> eyre::Report {
     eyre!("{err:?}")
 }
 
+/// Record fields that this client does not know about. They come from the records written by
+/// newer clients and are kept as an encoded message pack map so that they are written back when
+/// this client updates the record.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct UnknownFields(Vec<u8>);
+
+impl UnknownFields {
+    pub fn from_bytes(value: Vec<u8>) -> Self {
+        Self(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    fn from_entries(entries: Vec<(Value, Value)>) -> Result<Self> {
+        if entries.is_empty() {
+            return Ok(Self::default());
+        }
+
+        let mut buf = vec![];
+        rmpv::encode::write_value(&mut buf, &Value::Map(entries)).map_err(rmp_error_report)?;
+        Ok(Self(buf))
+    }
+
+    fn entries(&self) -> Result<Vec<(Value, Value)>> {
+        if self.0.is_empty() {
+            return Ok(vec![]);
+        }
+
+        match rmpv::decode::read_value(&mut self.0.as_slice()).map_err(rmp_error_report)? {
+            Value::Map(entries) => Ok(entries),
+            _ => bail!("unknown record fields are not a map"),
+        }
+    }
+}
+
+/// Builder for the map keyed record payload.
+#[derive(Default)]
+pub struct RecordWriter {
+    fields: Vec<(Value, Value)>,
+}
+
+impl RecordWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn str(mut self, key: &str, value: &str) -> Self {
+        self.fields.push((Value::from(key), Value::from(value)));
+        self
+    }
+
+    pub fn opt_str(mut self, key: &str, value: Option<&str>) -> Self {
+        let value = value.map(Value::from).unwrap_or(Value::Nil);
+        self.fields.push((Value::from(key), value));
+        self
+    }
+
+    pub fn u32(mut self, key: &str, value: u32) -> Self {
+        self.fields.push((Value::from(key), Value::from(value)));
+        self
+    }
+
+    pub fn bin(mut self, key: &str, value: &[u8]) -> Self {
+        self.fields.push((Value::from(key), Value::from(value)));
+        self
+    }
+
+    /// Encode the record together with the fields that this client does not know about.
+    pub fn finish(mut self, unknown: &UnknownFields) -> Result<Vec<u8>> {
+        self.fields.extend(unknown.entries()?);
+
+        let mut buf = vec![];
+        rmpv::encode::write_value(&mut buf, &Value::Map(self.fields)).map_err(rmp_error_report)?;
+        Ok(buf)
+    }
+}
+
+/// Reader for the map keyed record payload. Take out all the known fields and keep the rest
+/// with `into_unknown`.
+pub struct RecordReader {
+    fields: Vec<(Value, Value)>,
+}
+
+impl RecordReader {
+    pub fn new(input: &[u8]) -> Result<Self> {
+        let mut bytes = input;
+        let value = rmpv::decode::read_value(&mut bytes).map_err(rmp_error_report)?;
+
+        if !bytes.is_empty() {
+            bail!("found more bytes than expected. malformed");
+        }
+
+        match value {
+            Value::Map(fields) => Ok(Self { fields }),
+            _ => bail!("incorrectly formed decrypted record. expected a map"),
+        }
+    }
+
+    fn take(&mut self, key: &str) -> Option<Value> {
+        let index = self
+            .fields
+            .iter()
+            .position(|(k, _)| k.as_str() == Some(key))?;
+        Some(self.fields.remove(index).1)
+    }
+
+    pub fn str(&mut self, key: &str) -> Result<String> {
+        self.opt_str(key)?
+            .ok_or_else(|| eyre!("missing field '{key}' in record"))
+    }
+
+    pub fn opt_str(&mut self, key: &str) -> Result<Option<String>> {
+        match self.take(key) {
+            None | Some(Value::Nil) => Ok(None),
+            Some(Value::String(value)) => value
+                .into_str()
+                .map(Some)
+                .ok_or_else(|| eyre!("field '{key}' is not valid utf-8")),
+            Some(_) => bail!("field '{key}' is not a string"),
+        }
+    }
+
+    pub fn u32(&mut self, key: &str) -> Result<u32> {
+        match self.take(key) {
+            Some(Value::Integer(value)) => value
+                .as_u64()
+                .and_then(|x| u32::try_from(x).ok())
+                .ok_or_else(|| eyre!("field '{key}' is out of range")),
+            Some(_) => bail!("field '{key}' is not an integer"),
+            None => bail!("missing field '{key}' in record"),
+        }
+    }
+
+    pub fn bin(&mut self, key: &str) -> Result<Vec<u8>> {
+        match self.take(key) {
+            Some(Value::Binary(value)) => Ok(value),
+            Some(_) => bail!("field '{key}' is not binary"),
+            None => bail!("missing field '{key}' in record"),
+        }
+    }
+
+    pub fn into_unknown(self) -> Result<UnknownFields> {
+        UnknownFields::from_entries(self.fields)
+    }
+}
+
 pub fn encrypt<T: MsgPackSerializable>(entry: &T, key: &Key) -> Result<EncryptedItem> {
-    let mut entry_buf = entry.encode_msgpack()?;
+    encrypt_payload(entry.encode_msgpack()?, RECORD_FORMAT_VERSION, key)
+}
 
+fn encrypt_payload(mut entry_buf: Vec<u8>, version: u32, key: &Key) -> Result<EncryptedItem> {
     let one_time_key = XSalsa20Poly1305::generate_key(&mut OsRng);
     let one_time_key_nonce = XSalsa20Poly1305::generate_nonce(&mut OsRng);
     XSalsa20Poly1305::new(&one_time_key)
@@ -171,6 +636,8 @@ pub fn encrypt<T: MsgPackSerializable>(entry: &T, key: &Key) -> Result<Encrypted
         .map_err(|_| eyre!("Failed to encrypt key"))?;
 
     Ok(EncryptedItem {
+        version,
+        key_id: Some(key_id(key)),
         ciphertext: entry_buf,
         key: encrypted_key,
         key_nonce: one_time_key_nonce,
@@ -179,18 +646,205 @@ pub fn encrypt<T: MsgPackSerializable>(entry: &T, key: &Key) -> Result<Encrypted
 }
 
 pub fn decrypt<T: MsgPackSerializable>(encrypted_data: EncryptedItem, key: &Key) -> Result<T> {
+    if let Some(id) = &encrypted_data.key_id {
+        let expected = key_id(key);
+        ensure!(
+            *id == expected,
+            "Record is encrypted with the key {id} but the key {expected} was used. Mixed keys \
+            detected, run `dirpin key status`."
+        );
+    }
+
     let mut one_time_key = encrypted_data.key;
-    XSalsa20Poly1305::new(&key)
+    XSalsa20Poly1305::new(key)
         .decrypt_in_place(&encrypted_data.nonce, &[], &mut one_time_key)
         .map_err(|_| eyre!("Failed to decrypt data"))?;
     let one_time_key = Key::from_slice(&one_time_key);
 
     let mut entry = encrypted_data.ciphertext;
-    XSalsa20Poly1305::new(&one_time_key)
+    XSalsa20Poly1305::new(one_time_key)
         .decrypt_in_place(&encrypted_data.key_nonce, &[], &mut entry)
         .map_err(|_| eyre!("Failed to decrypt data"))?;
 
-    let entry = T::decode_msgpack(&entry)?;
+    let entry = match encrypted_data.version {
+        0 => T::decode_msgpack_v0(&entry)?,
+        _ => T::decode_msgpack(&entry)?,
+    };
 
     Ok(entry)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        create_key_check, decode_key_mnemonic, decrypt, derive_key, encode_key_mnemonic, encrypt,
+        encrypt_payload, generate_encoded_key, generate_key_salt, key_id, parse_key,
+        verify_key_check, Keyring, MsgPackSerializable, RecordReader, RECORD_FORMAT_VERSION,
+    };
+    use crate::domain::context::Context;
+    use crate::domain::entry::{Entry, EntryKind};
+    use crate::domain::host::HostId;
+    use crate::domain::workspace::Workspace;
+    use crypto_secretbox::Key;
+    use rmp::encode;
+    use rmpv::Value;
+    use time::format_description::well_known::Rfc3339;
+
+    #[test]
+    fn decrypts_version_0_records() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let workspace = Workspace::new("global".into(), &Context::global());
+
+        let mut buf = vec![];
+        encode::write_array_len(&mut buf, 7).unwrap();
+        encode::write_str(&mut buf, &workspace.id.to_string()).unwrap();
+        encode::write_str(&mut buf, &workspace.name).unwrap();
+        encode::write_nil(&mut buf).unwrap();
+        encode::write_str(&mut buf, &workspace.paths[0].to_string()).unwrap();
+        encode::write_str(&mut buf, &workspace.updated_at.format(&Rfc3339).unwrap()).unwrap();
+        encode::write_nil(&mut buf).unwrap();
+        encode::write_u32(&mut buf, workspace.version.inner()).unwrap();
+
+        let encrypted = encrypt_payload(buf, 0, &key).unwrap();
+        let encoded = encrypted.to_json_base64().unwrap();
+        let encrypted = super::EncryptedItem::from_json_base64(&encoded).unwrap();
+        let decrypted: Workspace = decrypt(encrypted, &key).unwrap();
+
+        assert_eq!(decrypted, workspace);
+    }
+
+    #[test]
+    fn keeps_unknown_fields_of_newer_records() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let workspace = Workspace::new("global".into(), &Context::global());
+
+        // A newer client added a field to the workspace
+        let mut newer =
+            match rmpv::decode::read_value(&mut workspace.encode_msgpack().unwrap().as_slice())
+                .unwrap()
+            {
+                Value::Map(fields) => fields,
+                _ => unreachable!(),
+            };
+        newer.push((Value::from("color"), Value::from("red")));
+        let mut buf = vec![];
+        rmpv::encode::write_value(&mut buf, &Value::Map(newer)).unwrap();
+
+        let encrypted = encrypt_payload(buf, RECORD_FORMAT_VERSION + 1, &key).unwrap();
+        let mut decrypted: Workspace = decrypt(encrypted, &key).unwrap();
+        assert_eq!(decrypted.name, workspace.name);
+        assert!(!decrypted.extra.is_empty());
+
+        // Update it on this client and make sure the unknown field survives
+        decrypted.name = "renamed".into();
+        let encrypted = encrypt(&decrypted, &key).unwrap();
+        assert_eq!(encrypted.version, RECORD_FORMAT_VERSION);
+        let updated: Workspace = decrypt(encrypted, &key).unwrap();
+        assert_eq!(updated, decrypted);
+
+        let mut record = RecordReader::new(&updated.encode_msgpack().unwrap()).unwrap();
+        assert_eq!(record.str("name").unwrap(), "renamed");
+        assert_eq!(record.str("color").unwrap(), "red");
+    }
+
+    #[test]
+    fn keeps_unknown_entry_kinds() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let entry = Entry::new(
+            "value".into(),
+            "/".into(),
+            None,
+            HostId::custom("a".into(), "b".into()),
+        )
+        .kind(EntryKind::Other("snippet".into()));
+
+        let decrypted: Entry = decrypt(encrypt(&entry, &key).unwrap(), &key).unwrap();
+        assert_eq!(decrypted, entry);
+        assert_eq!(decrypted.kind.as_str(), "snippet");
+    }
+
+    #[test]
+    fn detects_records_encrypted_with_another_key() {
+        let (old_key, _) = generate_encoded_key().unwrap();
+        let (new_key, _) = generate_encoded_key().unwrap();
+        let workspace = Workspace::new("global".into(), &Context::global());
+
+        let encrypted = encrypt(&workspace, &old_key).unwrap();
+        assert_eq!(encrypted.key_id, Some(key_id(&old_key)));
+
+        let err = decrypt::<Workspace>(encrypted, &new_key).unwrap_err();
+        assert!(err.to_string().contains(&key_id(&old_key)));
+
+        let keyring = Keyring {
+            current: new_key,
+            previous: vec![old_key],
+        };
+        let encrypted = encrypt(&workspace, &old_key).unwrap();
+        let key = keyring.get(encrypted.key_id.as_deref()).unwrap();
+        let decrypted: Workspace = decrypt(encrypted, key).unwrap();
+        assert_eq!(decrypted, workspace);
+    }
+
+    #[test]
+    fn derives_the_same_key_from_the_same_passphrase() {
+        let salt = generate_key_salt();
+        let key = derive_key("correct horse battery staple", &salt).unwrap();
+
+        assert_eq!(
+            key,
+            derive_key("correct horse battery staple", &salt).unwrap()
+        );
+        assert_ne!(key, derive_key("correct horse battery", &salt).unwrap());
+        assert_ne!(
+            key,
+            derive_key("correct horse battery staple", &generate_key_salt()).unwrap()
+        );
+    }
+
+    #[test]
+    fn key_mnemonic_roundtrip() {
+        let (key, encoded) = generate_encoded_key().unwrap();
+        let mnemonic = encode_key_mnemonic(&key).unwrap();
+
+        assert_eq!(mnemonic.split_whitespace().count(), 24);
+        assert_eq!(decode_key_mnemonic(&mnemonic).unwrap(), key);
+        assert_eq!(parse_key(&mnemonic.to_uppercase()).unwrap(), key);
+        assert_eq!(parse_key(&encoded).unwrap(), key);
+    }
+
+    #[test]
+    fn key_mnemonic_reports_wrong_words() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let mnemonic = encode_key_mnemonic(&key).unwrap();
+        let mut words = mnemonic.split_whitespace().collect::<Vec<_>>();
+
+        words[2] = "dirpin";
+        let err = decode_key_mnemonic(&words.join(" ")).unwrap_err();
+        assert!(err.to_string().contains("word 3 \"dirpin\""));
+
+        // The all zero key is "abandon" 23 times followed by the checksum word "art"
+        let mut words = vec!["abandon"; 24];
+        assert!(decode_key_mnemonic(&words.join(" ")).is_err());
+        words[23] = "art";
+        assert_eq!(
+            decode_key_mnemonic(&words.join(" ")).unwrap(),
+            Key::default()
+        );
+        words[23] = "abandon";
+        let err = decode_key_mnemonic(&words.join(" ")).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+
+        let err = decode_key_mnemonic(&words[..12].join(" ")).unwrap_err();
+        assert!(err.to_string().contains("must have 24"));
+    }
+
+    #[test]
+    fn key_check_only_passes_with_the_same_key() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let (other, _) = generate_encoded_key().unwrap();
+        let check = create_key_check(&key).unwrap();
+
+        assert!(verify_key_check(&check, &key).is_ok());
+        assert!(verify_key_check(&check, &other).is_err());
+    }
+}
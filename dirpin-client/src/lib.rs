@@ -1,7 +1,10 @@
 pub mod api_client;
 pub mod database;
+pub mod doctor;
 pub mod domain;
 pub mod encryption;
+pub mod link;
 pub mod settings;
 pub mod sync;
+pub mod tls;
 pub mod utils;
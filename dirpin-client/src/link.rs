@@ -0,0 +1,124 @@
+use crate::api_client::{self, AuthClient, ClientOptions};
+use crate::encryption::{load_key, unwrap_key, wrap_key};
+use crate::settings::Settings;
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use crypto_secretbox::aead::OsRng;
+use crypto_secretbox::{Key, KeyInit, XSalsa20Poly1305};
+use dirpin_common::api::CreateLinkRequest;
+use eyre::{eyre, Context, Result};
+use std::fmt;
+use std::str::FromStr;
+use time::OffsetDateTime;
+
+/// Single string to enrol a new host. The code identifies the encrypted account key on the
+/// server and the secret to decrypt it never leaves the hosts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkToken {
+    pub code: String,
+    pub secret: Key,
+}
+
+impl fmt::Display for LinkToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}",
+            self.code,
+            BASE64_URL_SAFE_NO_PAD.encode(self.secret)
+        )
+    }
+}
+
+impl FromStr for LinkToken {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (code, secret) = s
+            .trim()
+            .split_once('.')
+            .ok_or_else(|| eyre!("Invalid link token"))?;
+        let secret = BASE64_URL_SAFE_NO_PAD
+            .decode(secret)
+            .wrap_err("Invalid link token")?;
+
+        if code.is_empty() || secret.len() != 32 {
+            return Err(eyre!("Invalid link token"));
+        }
+
+        Ok(Self {
+            code: code.into(),
+            secret: Key::clone_from_slice(&secret),
+        })
+    }
+}
+
+/// Upload the key of this host encrypted with a fresh secret and build the token for the new
+/// host from the code the server returns.
+pub async fn create_link(settings: &Settings) -> Result<(LinkToken, OffsetDateTime)> {
+    let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+
+    let key = load_key(settings)?;
+    let secret = XSalsa20Poly1305::generate_key(&mut OsRng);
+    let res = client
+        .create_link(&CreateLinkRequest {
+            data: wrap_key(&key, &secret)?,
+        })
+        .await?;
+
+    Ok((
+        LinkToken {
+            code: res.code,
+            secret,
+        },
+        res.expires_at,
+    ))
+}
+
+/// Claim the link on the new host. Returns the session and the account key.
+pub async fn claim_link(
+    address: &str,
+    token: &LinkToken,
+    host_id: &str,
+    options: &ClientOptions,
+) -> Result<(String, Key)> {
+    let res = api_client::claim_link(address, &token.code, host_id, options).await?;
+    let key = unwrap_key(&res.data, &token.secret)
+        .wrap_err("Failed to decrypt the key from the link. Is the token complete?")?;
+
+    Ok((res.session, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkToken;
+    use crate::encryption::{generate_encoded_key, unwrap_key, wrap_key};
+
+    #[test]
+    fn link_token_roundtrip() {
+        let (secret, _) = generate_encoded_key().unwrap();
+        let token = LinkToken {
+            code: "abc_-123".into(),
+            secret,
+        };
+
+        assert_eq!(token.to_string().parse::<LinkToken>().unwrap(), token);
+        assert!("abc".parse::<LinkToken>().is_err());
+        assert!("abc.c2hvcnQ".parse::<LinkToken>().is_err());
+    }
+
+    #[test]
+    fn wrapped_key_needs_the_secret() {
+        let (key, _) = generate_encoded_key().unwrap();
+        let (secret, _) = generate_encoded_key().unwrap();
+        let (other, _) = generate_encoded_key().unwrap();
+
+        let data = wrap_key(&key, &secret).unwrap();
+        assert_eq!(unwrap_key(&data, &secret).unwrap(), key);
+        assert!(unwrap_key(&data, &other).is_err());
+    }
+}
@@ -22,6 +22,21 @@ pub struct Settings {
     pub key_path: String,
     pub session_path: String,
     pub server_address: String,
+    /// Seconds to wait for the connection to the server to establish
+    pub network_connect_timeout: u64,
+    /// Seconds to wait for the whole request to the server to finish
+    pub network_timeout: u64,
+    /// How many times to retry idempotent requests when the server is unavailable
+    pub network_retries: u32,
+    /// PEM bundle of the CAs to trust for the server instead of the system ones, e.g. the CA of a
+    /// self hosted server
+    pub tls_ca: Option<String>,
+    /// SHA-256 fingerprint of the server certificate. Only this certificate is trusted then
+    pub tls_pin: Option<String>,
+    /// Days to keep the synced tombstones of deleted records before purging them
+    pub tombstone_retention: u64,
+    /// Upload the revision history of the entries so that the other hosts can see it
+    pub sync_history: bool,
 }
 
 impl Settings {
@@ -44,11 +59,10 @@ impl Settings {
         Ok(())
     }
 
-    pub fn save_last_sync() -> Result<()> {
-        Settings::save_to_data_dir(
-            LAST_SYNC_FILENAME,
-            OffsetDateTime::now_utc().format(&Rfc3339)?.as_str(),
-        )?;
+    /// Store the timestamp of the last successful sync. This should be the time the sync
+    /// started at, so that the changes made during the sync are picked up by the next one.
+    pub fn save_last_sync(timestamp: OffsetDateTime) -> Result<()> {
+        Settings::save_to_data_dir(LAST_SYNC_FILENAME, timestamp.format(&Rfc3339)?.as_str())?;
         Ok(())
     }
 
@@ -104,6 +118,11 @@ impl Settings {
             .set_default("key_path", key_path.to_str())?
             .set_default("session_path", session_path.to_str())?
             .set_default("server_address", "http://127.0.0.1:8090")?
+            .set_default("network_connect_timeout", 5)?
+            .set_default("network_timeout", 30)?
+            .set_default("network_retries", 3)?
+            .set_default("tombstone_retention", 90)?
+            .set_default("sync_history", false)?
             .add_source(
                 Environment::with_prefix("dirpin")
                     .prefix_separator("_")
@@ -151,6 +170,7 @@ impl Settings {
         settings.db_path = expand_shell(&settings.db_path)?;
         settings.key_path = expand_shell(&settings.key_path)?;
         settings.session_path = expand_shell(&settings.session_path)?;
+        settings.tls_ca = settings.tls_ca.as_deref().map(expand_shell).transpose()?;
 
         Ok(settings)
     }
@@ -1,14 +1,23 @@
-use crate::api_client::AuthClient;
+use crate::api_client::{is_server_unavailable, AuthClient, ClientOptions};
 use crate::database::Database;
 use crate::domain::conflict::{Conflict, HasId};
 use crate::domain::entry::Entry;
+use crate::domain::quarantine::NewQuarantined;
+use crate::domain::revision::Revision;
 use crate::domain::workspace::{Workspace, WorkspaceId};
-use crate::encryption::{decrypt, encrypt, load_key, EncryptedItem};
+use crate::encryption::{
+    add_previous_key, create_key_check, decrypt, derive_key, encrypt, generate_encoded_key,
+    generate_key_salt, key_id, load_keyring, remove_previous_key, use_key, verify_key_check,
+    EncryptedItem, Keyring, MIN_READER_API_VERSION,
+};
 use crate::settings::Settings;
 use crypto_secretbox::Key;
-use dirpin_common::api::{AddEntryRequest, AddSyncRequest, RefDelete, RefItem};
+use dirpin_common::api::{
+    AddEntryRequest, AddSyncRequest, FetchRecordsRequest, KeyCheckRequest, RecordDigest, RefDelete,
+    RefItem, RejectedRecord, RotateKeyRequest, StatusResponse,
+};
 use dirpin_common::domain::SyncVersion;
-use eyre::{bail, Result};
+use eyre::{bail, eyre, Result};
 use std::collections::HashMap;
 use std::str::FromStr;
 use time::OffsetDateTime;
@@ -99,34 +108,66 @@ impl HasId for Workspace {
     }
 }
 
-fn parse_remote_updates(
-    items: Vec<RefItem>,
-    key: &Key,
-) -> Result<(HashMap<WorkspaceId, Workspace>, HashMap<Uuid, Entry>)> {
-    let mut workspaces: HashMap<WorkspaceId, Workspace> = HashMap::new();
-    let mut entries: HashMap<Uuid, Entry> = HashMap::new();
+/// The remote records that were parsed and the ones that failed and go to the quarantine.
+#[derive(Default)]
+struct RemoteUpdates {
+    workspaces: HashMap<WorkspaceId, Workspace>,
+    entries: HashMap<Uuid, Entry>,
+    /// The history of the hosts with `sync_history` turned on
+    revisions: Vec<Revision>,
+    quarantined: Vec<NewQuarantined>,
+}
 
-    let decrypted = items.iter().map(|x| {
-        let decoded = EncryptedItem::from_json_base64(&x.data).expect("failed deserialize");
-        (&x.kind, decoded)
-    });
+enum RemoteRecord {
+    Entry(Entry),
+    Workspace(Workspace),
+    Revision(Revision),
+}
 
-    for (kind, data) in decrypted {
-        match kind.as_str() {
-            "entry" => {
-                let entry: Entry = decrypt(data, key).expect("failed to decrypt entry. check key!");
-                entries.insert(entry.id, entry);
+fn parse_remote_item(item: &RefItem, keyring: &Keyring) -> Result<RemoteRecord> {
+    let data = EncryptedItem::from_json_base64(&item.data)?;
+    let key = keyring.get(data.key_id.as_deref()).ok_or_else(|| {
+        eyre!(
+            "Remote record is encrypted with an unknown key {}. Run `dirpin key use <key>` \
+            with the key from the host that rotated it.",
+            data.key_id.as_deref().unwrap_or_default()
+        )
+    })?;
+
+    match item.kind.as_str() {
+        "entry" => Ok(RemoteRecord::Entry(decrypt(data, key)?)),
+        "workspace" => Ok(RemoteRecord::Workspace(decrypt(data, key)?)),
+        "revision" => Ok(RemoteRecord::Revision(decrypt(data, key)?)),
+        value => bail!("Failed to recoghnize {value} remote entry"),
+    }
+}
+
+/// Parse every remote record on its own. The ones that fail don't stop the sync, they are
+/// quarantined with the raw payload for `dirpin doctor` to retry or purge.
+fn parse_remote_updates(items: Vec<RefItem>, keyring: &Keyring) -> RemoteUpdates {
+    let mut res = RemoteUpdates::default();
+
+    for item in items {
+        match parse_remote_item(&item, keyring) {
+            Ok(RemoteRecord::Entry(entry)) => {
+                res.entries.insert(entry.id, entry);
             }
-            "workspace" => {
-                let workspace: Workspace =
-                    decrypt(data, key).expect("failed to decrypt worksapce. check key!");
-                workspaces.insert(workspace.id.clone(), workspace);
+            Ok(RemoteRecord::Workspace(workspace)) => {
+                res.workspaces.insert(workspace.id.clone(), workspace);
+            }
+            Ok(RemoteRecord::Revision(revision)) => res.revisions.push(revision),
+            Err(err) => {
+                tracing::warn!("Quarantined remote {} record: {err:#}", item.kind);
+                res.quarantined.push(NewQuarantined {
+                    kind: item.kind,
+                    data: item.data,
+                    error: format!("{err:#}"),
+                });
             }
-            value => bail!("Failed to recoghnize {value} remote entry"),
         }
     }
 
-    Ok((workspaces, entries))
+    res
 }
 
 fn parse_remote_delets(
@@ -237,6 +278,7 @@ struct DownloadStatus {
     entry_delets: usize,
     entry_updates: usize,
     conflicts: usize,
+    quarantined: usize,
 }
 
 /// Get the list of the updates (full data)
@@ -244,19 +286,20 @@ struct DownloadStatus {
 /// Compare new updates with local db and buffer updates and conflicts
 /// Compare new delets with local db and buffer updates and conflicts
 async fn sync_download(
-    server_address: &str,
+    client: &AuthClient<'_>,
     db: &Database,
-    session: &str,
-    key: &Key,
+    keyring: &Keyring,
     from: OffsetDateTime,
 ) -> Result<DownloadStatus> {
-    let res = AuthClient::new(server_address, session)?.sync(from).await?;
+    let res = client.sync(from).await?;
+
+    let remote = parse_remote_updates(res.updated, keyring);
+    if !remote.quarantined.is_empty() {
+        db.save_quarantined_bulk(&remote.quarantined).await?;
+    }
 
-    let (remote_workspace_ups, remote_entry_ups) = parse_remote_updates(res.updated, key)?;
     let (remote_workspace_dels, remote_entry_dels, unknown_dels) =
         parse_remote_delets(res.deleted)?;
-    let (local_workspace_ups, local_entry_ups) = get_local_updates(db, &from).await?;
-    let (local_workspace_dels, local_entry_dels) = get_local_delets(db, &from).await?;
 
     if !unknown_dels.is_empty() {
         bail!(
@@ -265,6 +308,34 @@ async fn sync_download(
         );
     }
 
+    let mut status = apply_remote_changes(
+        db,
+        from,
+        remote.workspaces,
+        remote.entries,
+        remote_workspace_dels,
+        remote_entry_dels,
+    )
+    .await?;
+    db.save_remote_revisions(&remote.revisions).await?;
+    status.quarantined = remote.quarantined.len();
+
+    Ok(status)
+}
+
+/// Compare the remote changes with the local changes since `from`. Save the remote changes that
+/// are newer and buffer the rest as conflicts.
+async fn apply_remote_changes(
+    db: &Database,
+    from: OffsetDateTime,
+    remote_workspace_ups: HashMap<WorkspaceId, Workspace>,
+    remote_entry_ups: HashMap<Uuid, Entry>,
+    remote_workspace_dels: HashMap<WorkspaceId, RefDelete>,
+    remote_entry_dels: HashMap<Uuid, RefDelete>,
+) -> Result<DownloadStatus> {
+    let (local_workspace_ups, local_entry_ups) = get_local_updates(db, &from).await?;
+    let (local_workspace_dels, local_entry_dels) = get_local_delets(db, &from).await?;
+
     let mut conflicts: Vec<Conflict> = vec![];
 
     let mut update_workspaces: Vec<Workspace> = vec![];
@@ -348,6 +419,7 @@ async fn sync_download(
         entry_updates: update_entries.len(),
         entry_delets: delete_entries.len(),
         conflicts: conflicts.len(),
+        quarantined: 0,
     })
 }
 
@@ -355,6 +427,7 @@ async fn sync_download(
 struct UploadStatus {
     entries: usize,
     workspaces: usize,
+    conflicts: usize,
 }
 
 /// The assumptoin for the logic of this function is that this function always runs after the
@@ -364,13 +437,13 @@ struct UploadStatus {
 /// there is a new update on the server, we will first check it before we upload.
 /// Meaining, even if new values are in remote, we still download them first.
 /// This is not buletproof, as there is a time in-between that can create new values from a
-/// different host. However, we'll use a periodic doctor to spot this.
+/// different host. However, `verify` spots this.
 async fn sync_upload(
-    server_address: &str,
+    client: &AuthClient<'_>,
     db: &Database,
-    session: &str,
-    key: &Key,
+    keyring: &Keyring,
     from: OffsetDateTime,
+    history: bool,
 ) -> Result<UploadStatus> {
     // TODO: Split this into pages so that we don't have massive payload.
     let mut workspaces = db.after_workspaces(from).await?;
@@ -379,9 +452,96 @@ async fn sync_upload(
     let mut entries = db.after(from).await?;
     entries.extend(db.deleted_after(from).await?);
 
+    let revisions = match history {
+        true => db.revisions_after(from).await?,
+        false => vec![],
+    };
+
+    let rejected = client
+        .post_entries(&AddSyncRequest {
+            items: encode_upload_items(&entries, &workspaces, &revisions, &keyring.current)?,
+            last_sync_ts: from,
+            key_id: Some(key_id(&keyring.current)),
+        })
+        .await?;
+    let conflicts = save_rejected(db, keyring, rejected, &entries, &workspaces).await?;
+
+    Ok(UploadStatus {
+        entries: entries.len(),
+        workspaces: workspaces.len(),
+        conflicts,
+    })
+}
+
+/// Save the uploaded records the server refused as conflicts, the same way as the conflicts of a
+/// download. A live server copy is the conflict itself. A deleted one is the local record with the
+/// server deletion time. A live copy that can't be decrypted goes to the quarantine and counts as
+/// a conflict too, so that the last sync stays until it's recovered. Returns the number of saved
+/// conflicts.
+async fn save_rejected(
+    db: &Database,
+    keyring: &Keyring,
+    rejected: Vec<RejectedRecord>,
+    entries: &[Entry],
+    workspaces: &[Workspace],
+) -> Result<usize> {
+    let mut conflicts = vec![];
+    let mut live = vec![];
+
+    for r in rejected {
+        // The server already has the revision. It never changes, so there is nothing to merge.
+        if r.kind == "revision" {
+            continue;
+        }
+        let Some(deleted_at) = r.deleted_at else {
+            live.push(RefItem {
+                data: r.data,
+                kind: r.kind,
+            });
+            continue;
+        };
+        match r.kind.as_str() {
+            "entry" => {
+                if let Some(item) = entries.iter().find(|x| x.id.to_string() == r.client_id) {
+                    let mut item = item.clone();
+                    item.set_deleted_at(deleted_at);
+                    conflicts.push(Conflict::Entry(item));
+                }
+            }
+            "workspace" => {
+                if let Some(item) = workspaces.iter().find(|x| x.id.to_string() == r.client_id) {
+                    let mut item = item.clone();
+                    item.set_deleted_at(deleted_at);
+                    conflicts.push(Conflict::Workspace(item));
+                }
+            }
+            kind => tracing::warn!("Rejected record of unknown kind {kind}"),
+        }
+    }
+
+    let remote = parse_remote_updates(live, keyring);
+    if !remote.quarantined.is_empty() {
+        db.save_quarantined_bulk(&remote.quarantined).await?;
+    }
+    conflicts.extend(remote.workspaces.into_values().map(Conflict::Workspace));
+    conflicts.extend(remote.entries.into_values().map(Conflict::Entry));
+
+    if !conflicts.is_empty() {
+        db.save_conflicts_bulk(&conflicts).await?;
+    }
+
+    Ok(conflicts.len() + remote.quarantined.len())
+}
+
+fn encode_upload_items(
+    entries: &[Entry],
+    workspaces: &[Workspace],
+    revisions: &[Revision],
+    key: &Key,
+) -> Result<Vec<AddEntryRequest>> {
     let mut buffer = vec![];
 
-    for entry in &entries {
+    for entry in entries {
         buffer.push(AddEntryRequest {
             id: entry.id.to_string(),
             data: encrypt(entry, key)?.to_json_base64()?,
@@ -392,7 +552,7 @@ async fn sync_upload(
         });
     }
 
-    for ws in &workspaces {
+    for ws in workspaces {
         buffer.push(AddEntryRequest {
             id: ws.id.to_string(),
             data: encrypt(ws, key)?.to_json_base64()?,
@@ -403,16 +563,26 @@ async fn sync_upload(
         });
     }
 
-    AuthClient::new(server_address, session)?
-        .post_entries(&AddSyncRequest {
-            items: buffer,
-            last_sync_ts: from,
-        })
-        .await?;
+    // A revision never changes, so it's uploaded only once with the first version.
+    for revision in revisions {
+        buffer.push(revision_upload_item(revision, key)?);
+    }
 
-    Ok(UploadStatus {
-        entries: entries.len(),
-        workspaces: workspaces.len(),
+    Ok(buffer)
+}
+
+fn revision_upload_item(revision: &Revision, key: &Key) -> Result<AddEntryRequest> {
+    let uid = revision
+        .uid
+        .ok_or_else(|| eyre!("Revision {} has no uid", revision.rev))?;
+
+    Ok(AddEntryRequest {
+        id: uid.to_string(),
+        data: encrypt(revision, key)?.to_json_base64()?,
+        kind: "revision".into(),
+        version: 1,
+        updated_at: revision.created_at,
+        deleted_at: None,
     })
 }
 
@@ -422,9 +592,11 @@ async fn sync_upload(
 /// 4. Update last_sync_timestamp on successful sync.
 ///
 /// This does not guarantee that all the changes from the remote will show up in the local as there
-/// can be another update during this process that is missed. Due to this, we will rely on
-/// periodical full local/remote comparison of versions to make sure we are all up to date
-/// eventually.
+/// can be another update during this process that is missed. The full local/remote comparison of
+/// versions in `verify` picks these up.
+///
+/// When the server can not be reached, the local changes stay queued as the last_sync_timestamp
+/// is not moved and they get uploaded on the next successful sync.
 pub async fn sync(settings: &Settings, db: &Database, force: bool) -> Result<()> {
     let session = settings.session();
 
@@ -434,9 +606,14 @@ pub async fn sync(settings: &Settings, db: &Database, force: bool) -> Result<()>
     }
 
     let from = Settings::last_sync()?;
-    let key = load_key(settings)?;
+    let keyring = load_keyring(settings)?;
     let session = session.unwrap();
-    let server_address = &settings.server_address;
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+    let started_at = OffsetDateTime::now_utc();
 
     let from = if force {
         OffsetDateTime::UNIX_EPOCH
@@ -444,7 +621,24 @@ pub async fn sync(settings: &Settings, db: &Database, force: bool) -> Result<()>
         from
     };
 
-    let down_status = sync_download(server_address, db, &session, &key, from.clone()).await?;
+    let down_status = match sync_download(&client, db, &keyring, from).await {
+        Ok(status) => status,
+        Err(err) if is_server_unavailable(&err) => {
+            let pending = count_pending(db, &from).await?;
+            println!(
+                "Server is unreachable. {pending} local changes are queued for the next sync."
+            );
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
+    if down_status.quarantined > 0 {
+        println!(
+            "{} remote records could not be read and were quarantined. Run `dirpin doctor` to \
+            inspect them.",
+            down_status.quarantined
+        );
+    }
     if down_status.conflicts > 0 {
         println!(
             "{} conflicts. Resolve in app before resyncing",
@@ -453,7 +647,24 @@ pub async fn sync(settings: &Settings, db: &Database, force: bool) -> Result<()>
         return Ok(());
     }
 
-    let up_status = sync_upload(server_address, db, &session, &key, from).await?;
+    let upload = async {
+        let status = client.status().await?;
+        ensure_readable_by_peers(&status)?;
+        ensure_current_key(&status, &keyring.current)?;
+        check_account_key(&client, &status, &keyring.current).await?;
+        sync_upload(&client, db, &keyring, from, settings.sync_history).await
+    };
+    let up_status = match upload.await {
+        Ok(status) => status,
+        Err(err) if is_server_unavailable(&err) => {
+            let pending = count_pending(db, &from).await?;
+            println!(
+                "Server is unreachable. {pending} local changes are queued for the next sync."
+            );
+            return Ok(());
+        }
+        Err(err) => return Err(err),
+    };
 
     println!(
         "Workspaces: {} Uploaded / {} Deleted / {} Downloaded",
@@ -463,26 +674,543 @@ pub async fn sync(settings: &Settings, db: &Database, force: bool) -> Result<()>
         "Entries: {} Uploaded / {} Deleted / {} Downloaded",
         up_status.entries, down_status.entry_delets, down_status.entry_updates
     );
-    Settings::save_last_sync()?;
+    if up_status.conflicts > 0 {
+        // The last sync stays so the refused records are compared again on the next sync.
+        println!(
+            "{} records changed on the server in the meantime and were saved as conflicts or \
+            quarantined. Resolve in app or run `dirpin doctor` before resyncing",
+            up_status.conflicts
+        );
+        return Ok(());
+    }
+    Settings::save_last_sync(started_at)?;
+
+    let (workspaces, entries) = purge_tombstones(settings, db).await?;
+    if workspaces + entries > 0 {
+        println!("Purged {workspaces} workspace and {entries} entry tombstones");
+    }
 
     Ok(())
 }
 
+/// Drop the local tombstones that are older than the retention window. Only the ones deleted
+/// before the last sync go, the rest still has to be uploaded. Returns the number of purged
+/// workspaces and entries.
+pub async fn purge_tombstones(settings: &Settings, db: &Database) -> Result<(u64, u64)> {
+    let retention = time::Duration::days(settings.tombstone_retention as i64);
+    let before = (OffsetDateTime::now_utc() - retention).min(Settings::last_sync()?);
+
+    db.purge_tombstones(before).await
+}
+
+/// Make sure that every client the server lets in is able to decode the records we upload.
+/// Otherwise older hosts would fail on every record this host touches.
+fn ensure_readable_by_peers(status: &StatusResponse) -> Result<()> {
+    if status.min_client_version < MIN_READER_API_VERSION {
+        bail!(
+            "Refusing to upload. The server accepts clients with api version {} but the records of \
+            this client can only be decoded by api version {} and newer. Raise the \
+            'min_client_version' of the server once all your hosts are upgraded.",
+            status.min_client_version,
+            MIN_READER_API_VERSION
+        );
+    }
+
+    Ok(())
+}
+
+/// All the records of the account have to be encrypted with the same key. A different key on the
+/// server means another host rotated it.
+fn ensure_current_key(status: &StatusResponse, key: &Key) -> Result<()> {
+    let local = key_id(key);
+
+    match &status.key_id {
+        Some(remote) if *remote != local => bail!(
+            "Refusing to upload. The records on the server are encrypted with the key {remote} \
+            but this host uses the key {local}. Run `dirpin key use <key>` with the key from the \
+            host that rotated it."
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Verify the key against the key check of the account so that a host with a wrong key never
+/// uploads records the other hosts can not decrypt. The first host to check stores the key check.
+pub async fn check_account_key(
+    client: &AuthClient<'_>,
+    status: &StatusResponse,
+    key: &Key,
+) -> Result<()> {
+    match &status.key_check {
+        Some(check) => verify_key_check(check, key),
+        None => {
+            client
+                .set_key_check(&KeyCheckRequest {
+                    key_check: create_key_check(key)?,
+                })
+                .await
+        }
+    }
+}
+
+/// Replace the encryption key of the account.
+///
+/// 1. Sync so that the server and this host have the same records.
+/// 2. Download every live record and re-encrypt it with a new key.
+/// 3. Replace all the records on the server in a single batch. The server rejects the batch when
+///    any record changed in the meantime.
+/// 4. Switch to the new key locally. The old key is kept until every host switches.
+///
+/// With a passphrase the new key is derived from it with a fresh salt, otherwise it's random.
+/// Returns the id of the new key.
+pub async fn rotate_key(
+    settings: &Settings,
+    db: &Database,
+    passphrase: Option<&str>,
+) -> Result<String> {
+    let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+    let keyring = load_keyring(settings)?;
+
+    let status = client.status().await?;
+    ensure_readable_by_peers(&status)?;
+    ensure_current_key(&status, &keyring.current)?;
+    check_account_key(&client, &status, &keyring.current).await?;
+
+    let from = Settings::last_sync()?;
+    let started_at = OffsetDateTime::now_utc();
+    let down_status = sync_download(&client, db, &keyring, OffsetDateTime::UNIX_EPOCH).await?;
+    if down_status.conflicts > 0 {
+        bail!(
+            "{} conflicts. Resolve in app before rotating the key",
+            down_status.conflicts
+        );
+    }
+    let up_status = sync_upload(&client, db, &keyring, from, settings.sync_history).await?;
+    if up_status.conflicts > 0 {
+        bail!(
+            "{} conflicts. Resolve in app before rotating the key",
+            up_status.conflicts
+        );
+    }
+    Settings::save_last_sync(started_at)?;
+
+    let (new_key, key_salt) = match passphrase {
+        Some(passphrase) => {
+            let salt = generate_key_salt();
+            (derive_key(passphrase, &salt)?, Some(salt))
+        }
+        None => (generate_encoded_key()?.0, None),
+    };
+    let new_key_id = key_id(&new_key);
+
+    let res = client.sync(OffsetDateTime::UNIX_EPOCH).await?;
+    let RemoteUpdates {
+        workspaces,
+        entries,
+        revisions,
+        quarantined,
+    } = parse_remote_updates(res.updated, &keyring);
+    if !quarantined.is_empty() {
+        db.save_quarantined_bulk(&quarantined).await?;
+        bail!(
+            "{} remote records could not be read. Run `dirpin doctor` before rotating the key",
+            quarantined.len()
+        );
+    }
+
+    let mut items = vec![];
+    for ws in workspaces.values() {
+        items.push(AddEntryRequest {
+            id: ws.id.to_string(),
+            data: encrypt(ws, &new_key)?.to_json_base64()?,
+            kind: "workspace".into(),
+            version: ws.version.inner(),
+            updated_at: ws.updated_at,
+            deleted_at: None,
+        });
+    }
+    for entry in entries.values() {
+        items.push(AddEntryRequest {
+            id: entry.id.to_string(),
+            data: encrypt(entry, &new_key)?.to_json_base64()?,
+            kind: "entry".into(),
+            version: entry.version.inner(),
+            updated_at: entry.updated_at,
+            deleted_at: None,
+        });
+    }
+    for revision in &revisions {
+        items.push(revision_upload_item(revision, &new_key)?);
+    }
+
+    // Keep the new key on disk before the server switches to it. Otherwise a failure after the
+    // server commits the batch would leave the records unreadable.
+    add_previous_key(settings, &new_key)?;
+    let rotated = client
+        .rotate_key(&RotateKeyRequest {
+            key_id: new_key_id.clone(),
+            key_salt,
+            key_check: Some(create_key_check(&new_key)?),
+            items,
+        })
+        .await;
+    if let Err(err) = rotated {
+        remove_previous_key(settings, &new_key_id)?;
+        return Err(err);
+    }
+    use_key(settings, &new_key)?;
+
+    Ok(new_key_id)
+}
+
+/// Parse the quarantined records again, e.g. after switching to the right key, and apply the
+/// ones that succeed like a regular download. Returns the number of recovered records.
+pub async fn retry_quarantined(settings: &Settings, db: &Database) -> Result<usize> {
+    let keyring = load_keyring(settings)?;
+    recover_quarantined(db, &keyring, Settings::last_sync()?).await
+}
+
+/// The recovered records that the local copy has moved past since are dropped.
+async fn recover_quarantined(
+    db: &Database,
+    keyring: &Keyring,
+    last_sync: OffsetDateTime,
+) -> Result<usize> {
+    let records = db.list_quarantined().await?;
+    let ids = records
+        .iter()
+        .map(|x| (x.data.clone(), x.id))
+        .collect::<HashMap<_, _>>();
+
+    let remote = parse_remote_updates(
+        records
+            .into_iter()
+            .map(|x| RefItem {
+                data: x.data,
+                kind: x.kind,
+            })
+            .collect(),
+        keyring,
+    );
+
+    // Keep the latest error of the records that still fail
+    db.save_quarantined_bulk(&remote.quarantined).await?;
+    let recovered = ids
+        .iter()
+        .filter(|(data, _)| remote.quarantined.iter().all(|x| x.data != **data))
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+
+    // The records could sit in the quarantine for long, so they are compared with the local
+    // copies as they are now and not only with the changes since the last sync.
+    let mut conflicts = vec![];
+    let mut workspaces = HashMap::new();
+    for (id, r) in remote.workspaces {
+        let latest = match db.get_workspace(&id).await? {
+            Some(l) => compare_versions(&r, &l),
+            None => LatestOrigin::Remote,
+        };
+        match latest {
+            LatestOrigin::Remote => {
+                workspaces.insert(id, r);
+            }
+            LatestOrigin::Local => continue,
+            LatestOrigin::Conflict => conflicts.push(Conflict::Workspace(r)),
+        }
+    }
+    let mut entries = HashMap::new();
+    for (id, r) in remote.entries {
+        let latest = match db.get(id).await? {
+            Some(l) => compare_versions(&r, &l),
+            None => LatestOrigin::Remote,
+        };
+        match latest {
+            LatestOrigin::Remote => {
+                entries.insert(id, r);
+            }
+            LatestOrigin::Local => continue,
+            LatestOrigin::Conflict => conflicts.push(Conflict::Entry(r)),
+        }
+    }
+    if !conflicts.is_empty() {
+        db.save_conflicts_bulk(&conflicts).await?;
+    }
+
+    let status = apply_remote_changes(
+        db,
+        last_sync,
+        workspaces,
+        entries,
+        HashMap::new(),
+        HashMap::new(),
+    )
+    .await?;
+    db.save_remote_revisions(&remote.revisions).await?;
+    let conflicts = status.conflicts + conflicts.len();
+    if conflicts > 0 {
+        println!("{conflicts} conflicts. Resolve in app before resyncing");
+    }
+    db.delete_quarantined(&recovered).await?;
+
+    Ok(recovered.len())
+}
+
+/// Which side has to move for a record to be in sync.
+#[derive(Debug, PartialEq, Eq)]
+enum Drift {
+    InSync,
+    Download,
+    Upload,
+    Conflict,
+}
+
+/// Compare the sync properties of both copies of a record. A deletion does not bump the version,
+/// so with equal versions the deleted copy is the newer one.
+fn compare_digests(remote: &RecordDigest, local: &RecordDigest) -> Drift {
+    if remote.version == local.version && remote.updated_at == local.updated_at {
+        return match (remote.deleted_at.is_some(), local.deleted_at.is_some()) {
+            (true, false) => Drift::Download,
+            (false, true) => Drift::Upload,
+            _ => Drift::InSync,
+        };
+    }
+
+    if remote.version >= local.version && remote.updated_at >= local.updated_at {
+        Drift::Download
+    } else if remote.deleted_at.is_some() && local.deleted_at.is_none() {
+        // The server does not let a live record replace a deleted one.
+        Drift::Conflict
+    } else if local.version >= remote.version && local.updated_at >= remote.updated_at {
+        Drift::Upload
+    } else {
+        Drift::Conflict
+    }
+}
+
+fn entry_digest(entry: &Entry) -> RecordDigest {
+    RecordDigest {
+        client_id: entry.id.to_string(),
+        kind: "entry".into(),
+        version: entry.version.inner(),
+        updated_at: entry.updated_at,
+        deleted_at: entry.deleted_at,
+    }
+}
+
+fn workspace_digest(ws: &Workspace) -> RecordDigest {
+    RecordDigest {
+        client_id: ws.id.to_string(),
+        kind: "workspace".into(),
+        version: ws.version.inner(),
+        updated_at: ws.updated_at,
+        deleted_at: ws.deleted_at,
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    /// Number of records on either side
+    pub checked: usize,
+    pub in_sync: usize,
+    pub downloaded: usize,
+    pub uploaded: usize,
+    pub conflicts: usize,
+    pub quarantined: usize,
+}
+
+/// Compare every record of the server with the local copy instead of only the changes since the
+/// last sync. This picks up the updates a regular sync missed.
+///
+/// 1. Download the digests (id, kind, version, timestamps) of all the remote records.
+/// 2. Compare them with all the local records, including the deleted ones.
+/// 3. Download the records that are missing locally or newer on the server.
+/// 4. Upload the records that are missing on the server or newer locally.
+///
+/// Records that changed on both sides are saved as conflicts and left alone. Only a local deletion
+/// of a record that changed on the server gives way to the server copy.
+pub async fn verify(settings: &Settings, db: &Database) -> Result<VerifyReport> {
+    let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+    let keyring = load_keyring(settings)?;
+
+    let status = client.status().await?;
+    ensure_readable_by_peers(&status)?;
+    ensure_current_key(&status, &keyring.current)?;
+    check_account_key(&client, &status, &keyring.current).await?;
+
+    let mut remote: HashMap<(String, String), RecordDigest> = client
+        .digest()
+        .await?
+        .items
+        .into_iter()
+        // The history is append only and has nothing to repair.
+        .filter(|x| x.kind != "revision")
+        .map(|x| ((x.kind.clone(), x.client_id.clone()), x))
+        .collect();
+
+    let mut local_entries = db.after(OffsetDateTime::UNIX_EPOCH).await?;
+    local_entries.extend(db.deleted_after(OffsetDateTime::UNIX_EPOCH).await?);
+    let mut local_workspaces = db.after_workspaces(OffsetDateTime::UNIX_EPOCH).await?;
+    local_workspaces.extend(
+        db.deleted_after_workspaces(OffsetDateTime::UNIX_EPOCH)
+            .await?,
+    );
+
+    let mut report = VerifyReport {
+        checked: remote.len(),
+        ..Default::default()
+    };
+    let mut download = vec![];
+    let mut conflicts = vec![];
+    let mut upload_entries = vec![];
+    let mut upload_workspaces = vec![];
+
+    for entry in local_entries {
+        let local = entry_digest(&entry);
+        let Some(r) = remote.remove(&(local.kind.clone(), local.client_id.clone())) else {
+            report.checked += 1;
+            match local.deleted_at {
+                // The server already purged the tombstone.
+                Some(_) => report.in_sync += 1,
+                None => upload_entries.push(entry),
+            }
+            continue;
+        };
+        match compare_digests(&r, &local) {
+            Drift::InSync => report.in_sync += 1,
+            Drift::Upload => upload_entries.push(entry),
+            Drift::Download => download.push(r.client_id),
+            Drift::Conflict => match r.deleted_at {
+                Some(deleted_at) => {
+                    let mut item = entry;
+                    item.set_deleted_at(deleted_at);
+                    conflicts.push(Conflict::Entry(item));
+                }
+                None => download.push(r.client_id),
+            },
+        }
+    }
+
+    for ws in local_workspaces {
+        let local = workspace_digest(&ws);
+        let Some(r) = remote.remove(&(local.kind.clone(), local.client_id.clone())) else {
+            report.checked += 1;
+            match local.deleted_at {
+                // The server already purged the tombstone.
+                Some(_) => report.in_sync += 1,
+                None => upload_workspaces.push(ws),
+            }
+            continue;
+        };
+        match compare_digests(&r, &local) {
+            Drift::InSync => report.in_sync += 1,
+            Drift::Upload => upload_workspaces.push(ws),
+            Drift::Download => download.push(r.client_id),
+            Drift::Conflict => match r.deleted_at {
+                Some(deleted_at) => {
+                    let mut item = ws;
+                    item.set_deleted_at(deleted_at);
+                    conflicts.push(Conflict::Workspace(item));
+                }
+                None => download.push(r.client_id),
+            },
+        }
+    }
+
+    // What is left only exists on the server.
+    download.extend(remote.into_values().map(|x| x.client_id));
+
+    if !download.is_empty() {
+        let res = client
+            .fetch_records(&FetchRecordsRequest { ids: download })
+            .await?;
+        let updates = parse_remote_updates(res.updated, &keyring);
+        if !updates.quarantined.is_empty() {
+            db.save_quarantined_bulk(&updates.quarantined).await?;
+        }
+        let (workspace_dels, entry_dels, _) = parse_remote_delets(res.deleted)?;
+
+        // Compare with every local record, not only the recent changes.
+        let status = apply_remote_changes(
+            db,
+            OffsetDateTime::UNIX_EPOCH,
+            updates.workspaces,
+            updates.entries,
+            workspace_dels,
+            entry_dels,
+        )
+        .await?;
+        report.downloaded = status.workspace_updates
+            + status.workspace_delets
+            + status.entry_updates
+            + status.entry_delets;
+        report.conflicts += status.conflicts;
+        report.quarantined = updates.quarantined.len();
+    }
+
+    if !conflicts.is_empty() {
+        db.save_conflicts_bulk(&conflicts).await?;
+        report.conflicts += conflicts.len();
+    }
+
+    if !upload_entries.is_empty() || !upload_workspaces.is_empty() {
+        let rejected = client
+            .post_entries(&AddSyncRequest {
+                items: encode_upload_items(
+                    &upload_entries,
+                    &upload_workspaces,
+                    &[],
+                    &keyring.current,
+                )?,
+                last_sync_ts: OffsetDateTime::UNIX_EPOCH,
+                key_id: Some(key_id(&keyring.current)),
+            })
+            .await?;
+        report.uploaded = upload_entries.len() + upload_workspaces.len() - rejected.len();
+        report.conflicts +=
+            save_rejected(db, &keyring, rejected, &upload_entries, &upload_workspaces).await?;
+    }
+
+    Ok(report)
+}
+
+/// Number of local changes that are waiting to be uploaded.
+async fn count_pending(db: &Database, from: &OffsetDateTime) -> Result<usize> {
+    let (workspace_ups, entry_ups) = get_local_updates(db, from).await?;
+    let (workspace_dels, entry_dels) = get_local_delets(db, from).await?;
+
+    Ok(workspace_ups.len() + entry_ups.len() + workspace_dels.len() + entry_dels.len())
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::api_client::AuthClient;
     use crate::database::Database;
     use crate::domain::conflict::Conflict;
     use crate::domain::context::Context;
     use crate::domain::entry::Entry;
     use crate::domain::host::HostId;
+    use crate::domain::quarantine::NewQuarantined;
+    use crate::domain::revision::Revision;
     use crate::domain::workspace::Workspace;
     use crate::encryption;
-    use crate::encryption::encrypt;
+    use crate::encryption::{encrypt, Keyring, MsgPackSerializable, RecordWriter, UnknownFields};
     use crypto_secretbox::Key;
-    use dirpin_common::api::{RefDelete, RefItem};
+    use dirpin_common::api::{ConflictMessage, RefDelete, RefItem, RejectedRecord};
     use fake::faker::lorem::en::Word;
     use fake::Fake;
+    use time::format_description::well_known::Rfc3339;
     use time::OffsetDateTime;
+    use uuid::Uuid;
     use wiremock::matchers::{method, path};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
@@ -499,7 +1227,24 @@ mod tests {
         Ok(database)
     }
 
-    async fn setup_upload_test() -> eyre::Result<(MockServer, String, Database, Key)> {
+    /// A record with the payload as it's given, to send what the client can't decode.
+    struct RawRecord(Vec<u8>);
+
+    impl MsgPackSerializable for RawRecord {
+        fn encode_msgpack(&self) -> eyre::Result<Vec<u8>> {
+            Ok(self.0.clone())
+        }
+
+        fn decode_msgpack(input: &[u8]) -> eyre::Result<Self> {
+            Ok(Self(input.to_vec()))
+        }
+
+        fn decode_msgpack_v0(input: &[u8]) -> eyre::Result<Self> {
+            Ok(Self(input.to_vec()))
+        }
+    }
+
+    async fn setup_upload_test() -> eyre::Result<(MockServer, String, Database, Keyring)> {
         let key = setup_key()?;
         let database = setup_db().await?;
         let mock_server = MockServer::start().await;
@@ -509,19 +1254,21 @@ mod tests {
             .mount(&mock_server)
             .await;
 
-        Ok((mock_server, "session".into(), database, key))
+        Ok((mock_server, "session".into(), database, Keyring::new(key)))
     }
 
     #[tokio::test]
     async fn sync_upload_empty_data() {
-        let (server, session, database, key) = setup_upload_test().await.unwrap();
+        let (server, session, database, keyring) = setup_upload_test().await.unwrap();
 
+        let address = server.uri();
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_upload(
-            &server.uri(),
+            &client,
             &database,
-            &session,
-            &key,
+            &keyring,
             OffsetDateTime::UNIX_EPOCH,
+            false,
         )
         .await
         .unwrap();
@@ -532,18 +1279,20 @@ mod tests {
 
     #[tokio::test]
     async fn sync_upload_with_entry() {
-        let (server, session, database, key) = setup_upload_test().await.unwrap();
+        let (server, session, database, keyring) = setup_upload_test().await.unwrap();
         let host_id = HostId::custom(Word().fake(), Word().fake());
 
         let entry = Entry::new(Word().fake(), "/".into(), None, host_id);
         database.save(&entry).await.unwrap();
 
+        let address = server.uri();
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_upload(
-            &server.uri(),
+            &client,
             &database,
-            &session,
-            &key,
+            &keyring,
             OffsetDateTime::UNIX_EPOCH,
+            false,
         )
         .await
         .unwrap();
@@ -557,7 +1306,7 @@ mod tests {
         use crate::domain::context::Context;
         use crate::domain::workspace::Workspace;
 
-        let (server, session, database, key) = setup_upload_test().await.unwrap();
+        let (server, session, database, keyring) = setup_upload_test().await.unwrap();
         let host_id = HostId::custom(Word().fake(), Word().fake());
 
         let workspace = Workspace::new("global".into(), &Context::global());
@@ -579,12 +1328,14 @@ mod tests {
         ];
         database.save_bulk(&entries).await.unwrap();
 
+        let address = server.uri();
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_upload(
-            &server.uri(),
+            &client,
             &database,
-            &session,
-            &key,
+            &keyring,
             OffsetDateTime::UNIX_EPOCH,
+            false,
         )
         .await
         .unwrap();
@@ -593,6 +1344,189 @@ mod tests {
         assert_eq!(res.workspaces, 1);
     }
 
+    #[tokio::test]
+    async fn sync_upload_with_history() {
+        use dirpin_common::api::AddSyncRequest;
+
+        let (server, session, database, keyring) = setup_upload_test().await.unwrap();
+        let host_id = HostId::custom(Word().fake(), Word().fake());
+
+        let mut entry = Entry::new("first".into(), "/".into(), None, host_id);
+        database.save(&entry).await.unwrap();
+        entry.value = "second".into();
+        database.save(&entry).await.unwrap();
+
+        let address = server.uri();
+        let client = AuthClient::new(&address, &session).unwrap();
+        for history in [false, true] {
+            super::sync_upload(
+                &client,
+                &database,
+                &keyring,
+                OffsetDateTime::UNIX_EPOCH,
+                history,
+            )
+            .await
+            .unwrap();
+        }
+
+        let requests = server.received_requests().await.unwrap();
+        let uploads = requests
+            .iter()
+            .map(|x| serde_json::from_slice::<AddSyncRequest>(&x.body).unwrap())
+            .collect::<Vec<_>>();
+        assert!(uploads[0].items.iter().all(|x| x.kind != "revision"));
+
+        let revisions = uploads[1]
+            .items
+            .iter()
+            .filter(|x| x.kind == "revision")
+            .map(|x| {
+                let data = encryption::EncryptedItem::from_json_base64(&x.data).unwrap();
+                encryption::decrypt::<Revision>(data, &keyring.current).unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(revisions.len(), 2);
+
+        // The other host keeps them apart from its own history, so the undo never picks them.
+        let other = setup_db().await.unwrap();
+        other.save_remote_revisions(&revisions).await.unwrap();
+        other.save_remote_revisions(&revisions).await.unwrap();
+        let saved = other.list_revisions(entry.id).await.unwrap();
+        assert_eq!(saved.len(), 2);
+        assert!(saved.iter().all(|x| !x.local));
+        assert_eq!(saved[1].entry.value, "first");
+    }
+
+    #[tokio::test]
+    async fn sync_upload_counts_unreadable_rejected_records() {
+        let key = setup_key().unwrap();
+        let database = setup_db().await.unwrap();
+        let host_id = HostId::custom(Word().fake(), Word().fake());
+
+        let changed = Entry::new(Word().fake(), "/".into(), None, host_id);
+        database.save(&changed).await.unwrap();
+
+        // The server copy is encrypted with a key this host doesn't have.
+        let mut server_copy = changed.clone();
+        server_copy.version.bump();
+        let rejected = vec![RejectedRecord {
+            client_id: changed.id.to_string(),
+            kind: "entry".into(),
+            version: server_copy.version.inner(),
+            updated_at: server_copy.updated_at,
+            deleted_at: None,
+            data: encrypt(&server_copy, &setup_key().unwrap())
+                .unwrap()
+                .to_json_base64()
+                .unwrap(),
+        }];
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/entries"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(ConflictMessage {
+                value: "1 record conflicts with the server copy".into(),
+                rejected,
+            }))
+            .mount(&mock_server)
+            .await;
+
+        let address = mock_server.uri();
+        let client = AuthClient::new(&address, "session").unwrap();
+        let res = super::sync_upload(
+            &client,
+            &database,
+            &Keyring::new(key),
+            OffsetDateTime::UNIX_EPOCH,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.conflicts, 1);
+        assert!(database.list_conflicts().await.unwrap().is_empty());
+        assert_eq!(database.list_quarantined().await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sync_upload_saves_rejected_as_conflicts() {
+        let key = setup_key().unwrap();
+        let database = setup_db().await.unwrap();
+        let host_id = HostId::custom(Word().fake(), Word().fake());
+
+        let changed = Entry::new(Word().fake(), "/".into(), None, host_id.clone());
+        let deleted = Entry::new(Word().fake(), "/".into(), None, host_id.clone());
+        let accepted = Entry::new(Word().fake(), "/".into(), None, host_id.clone());
+        database
+            .save_bulk(&[changed.clone(), deleted.clone(), accepted])
+            .await
+            .unwrap();
+
+        let mut server_copy = changed.clone();
+        server_copy.value = Word().fake();
+        server_copy.version.bump();
+        let rejected = vec![
+            RejectedRecord {
+                client_id: changed.id.to_string(),
+                kind: "entry".into(),
+                version: server_copy.version.inner(),
+                updated_at: server_copy.updated_at,
+                deleted_at: None,
+                data: encrypt(&server_copy, &key)
+                    .unwrap()
+                    .to_json_base64()
+                    .unwrap(),
+            },
+            RejectedRecord {
+                client_id: deleted.id.to_string(),
+                kind: "entry".into(),
+                version: deleted.version.inner(),
+                updated_at: deleted.updated_at,
+                deleted_at: Some(OffsetDateTime::now_utc()),
+                data: "".into(),
+            },
+        ];
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/entries"))
+            .respond_with(ResponseTemplate::new(409).set_body_json(ConflictMessage {
+                value: "2 records conflict with the server copies".into(),
+                rejected,
+            }))
+            .mount(&mock_server)
+            .await;
+
+        let address = mock_server.uri();
+        let client = AuthClient::new(&address, "session").unwrap();
+        let res = super::sync_upload(
+            &client,
+            &database,
+            &Keyring::new(key),
+            OffsetDateTime::UNIX_EPOCH,
+            false,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.entries, 3);
+        assert_eq!(res.conflicts, 2);
+
+        let conflicts = database.list_conflicts().await.unwrap();
+        assert_eq!(conflicts.len(), 2);
+        for conflict in conflicts {
+            match conflict {
+                Conflict::Entry(x) if x.id == changed.id => {
+                    assert_eq!(x.value, server_copy.value);
+                    assert!(x.deleted_at.is_none());
+                }
+                Conflict::Entry(x) if x.id == deleted.id => assert!(x.deleted_at.is_some()),
+                x => panic!("unexpected conflict {x:?}"),
+            }
+        }
+    }
+
     #[tokio::test]
     async fn sync_download_status() {
         let key = setup_key().unwrap();
@@ -661,11 +1595,11 @@ mod tests {
         let address = mock_server.uri();
         let session = "session".to_string();
 
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_download(
-            &address,
+            &client,
             &database,
-            &session,
-            &key,
+            &Keyring::new(key),
             OffsetDateTime::UNIX_EPOCH,
         )
         .await
@@ -722,11 +1656,11 @@ mod tests {
             .await;
         let address = mock_server.uri();
 
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_download(
-            &address,
+            &client,
             &database,
-            &session,
-            &key,
+            &Keyring::new(key),
             OffsetDateTime::UNIX_EPOCH,
         )
         .await
@@ -792,11 +1726,11 @@ mod tests {
 
         let address = mock_server.uri();
 
+        let client = AuthClient::new(&address, &session).unwrap();
         let res = super::sync_download(
-            &address,
+            &client,
             &database,
-            &session,
-            &key,
+            &Keyring::new(key),
             OffsetDateTime::UNIX_EPOCH,
         )
         .await
@@ -814,4 +1748,177 @@ mod tests {
         assert!(matches!(conflicts[0], Conflict::Entry(_)));
         assert_eq!(conflicts[0].id(), e1.id.to_string());
     }
+
+    #[tokio::test]
+    async fn sync_download_quarantines_bad_records() {
+        let key = setup_key().unwrap();
+        let other_key = setup_key().unwrap();
+        let database = setup_db().await.unwrap();
+        let mock_server = MockServer::start().await;
+        let session = "session".to_string();
+
+        let host_id = HostId::custom(Word().fake(), Word().fake());
+        let e1 = Entry::new(Word().fake(), "/".into(), None, host_id.clone());
+        let e2 = Entry::new(Word().fake(), "/".into(), None, host_id.clone());
+        let foreign = encrypt(&e2, &other_key).unwrap().to_json_base64().unwrap();
+        let bad_kind = RecordWriter::new()
+            .str("id", &Uuid::new_v4().to_string())
+            .str("value", "value")
+            .str("path", "/")
+            .u32("kind", 1)
+            .str(
+                "updated_at",
+                &OffsetDateTime::now_utc().format(&Rfc3339).unwrap(),
+            )
+            .u32("version", 1)
+            .str("host_id", host_id.as_ref())
+            .finish(&UnknownFields::default())
+            .unwrap();
+        let bad_kind = encrypt(&RawRecord(bad_kind), &key)
+            .unwrap()
+            .to_json_base64()
+            .unwrap();
+
+        Mock::given(method("GET"))
+            .and(path("/sync"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "updated": vec![
+                    RefItem {
+                        data: encrypt(&e1, &key).unwrap().to_json_base64().unwrap(),
+                        kind: "entry".into(),
+                    },
+                    RefItem {
+                        data: "not a record".into(),
+                        kind: "entry".into(),
+                    },
+                    RefItem {
+                        data: foreign.clone(),
+                        kind: "entry".into(),
+                    },
+                    RefItem {
+                        data: bad_kind.clone(),
+                        kind: "entry".into(),
+                    },
+                ],
+                "deleted": Vec::<RefDelete>::new(),
+            })))
+            .mount(&mock_server)
+            .await;
+        let address = mock_server.uri();
+
+        let client = AuthClient::new(&address, &session).unwrap();
+        let res = super::sync_download(
+            &client,
+            &database,
+            &Keyring::new(key),
+            OffsetDateTime::UNIX_EPOCH,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(res.entry_updates, 1);
+        assert_eq!(res.quarantined, 3);
+
+        let quarantined = database.list_quarantined().await.unwrap();
+        assert_eq!(quarantined.len(), 3);
+        assert!(quarantined.iter().any(|x| x.data == foreign));
+        assert!(quarantined.iter().any(|x| x.data == bad_kind));
+        assert!(quarantined.iter().all(|x| !x.error.is_empty()));
+
+        // The same records are not quarantined twice
+        super::sync_download(
+            &client,
+            &database,
+            &Keyring::new(key),
+            OffsetDateTime::UNIX_EPOCH,
+        )
+        .await
+        .unwrap();
+        assert_eq!(database.list_quarantined().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn recover_quarantined_drops_stale_records() {
+        let key = setup_key().unwrap();
+        let database = setup_db().await.unwrap();
+        let host_id = HostId::custom(Word().fake(), Word().fake());
+
+        let quarantined = Entry::new("old".into(), "/".into(), None, host_id.clone());
+        let mut local = quarantined.clone();
+        local.value = "edited".into();
+        local.version.bump();
+        local.updated_at = OffsetDateTime::now_utc();
+        database.save(&local).await.unwrap();
+
+        let new = Entry::new("new".into(), "/".into(), None, host_id);
+        database
+            .save_quarantined_bulk(&[&quarantined, &new].map(|x| NewQuarantined {
+                kind: "entry".into(),
+                data: encrypt(x, &key).unwrap().to_json_base64().unwrap(),
+                error: "unknown key".into(),
+            }))
+            .await
+            .unwrap();
+
+        let recovered =
+            super::recover_quarantined(&database, &Keyring::new(key), OffsetDateTime::now_utc())
+                .await
+                .unwrap();
+
+        assert_eq!(recovered, 2);
+        assert!(database.list_quarantined().await.unwrap().is_empty());
+        assert!(database.list_conflicts().await.unwrap().is_empty());
+        let current = database.get(local.id).await.unwrap().unwrap();
+        assert_eq!(current.value, "edited");
+        assert_eq!(database.get(new.id).await.unwrap().unwrap().value, "new");
+    }
+
+    #[test]
+    fn compare_digests_picks_the_newer_side() {
+        use super::{compare_digests, Drift};
+        use dirpin_common::api::RecordDigest;
+        use time::Duration;
+
+        let now = OffsetDateTime::now_utc();
+        let digest = |version, updated_at, deleted_at| RecordDigest {
+            client_id: "id".into(),
+            kind: "entry".into(),
+            version,
+            updated_at,
+            deleted_at,
+        };
+        let later = now + Duration::seconds(1);
+
+        assert_eq!(
+            compare_digests(&digest(1, now, None), &digest(1, now, None)),
+            Drift::InSync
+        );
+        assert_eq!(
+            compare_digests(&digest(2, later, None), &digest(1, now, None)),
+            Drift::Download
+        );
+        assert_eq!(
+            compare_digests(&digest(1, now, None), &digest(2, later, None)),
+            Drift::Upload
+        );
+        // A deletion does not bump the version.
+        assert_eq!(
+            compare_digests(&digest(1, now, Some(later)), &digest(1, now, None)),
+            Drift::Download
+        );
+        assert_eq!(
+            compare_digests(&digest(1, now, None), &digest(1, now, Some(later))),
+            Drift::Upload
+        );
+        // Changed on both sides.
+        assert_eq!(
+            compare_digests(&digest(2, now, None), &digest(1, later, None)),
+            Drift::Conflict
+        );
+        // The server does not take a live record over a deleted one.
+        assert_eq!(
+            compare_digests(&digest(1, now, Some(now)), &digest(2, later, None)),
+            Drift::Conflict
+        );
+    }
 }
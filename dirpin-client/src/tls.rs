@@ -0,0 +1,140 @@
+use eyre::{eyre, Context, Result};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// The tls config of the connections to the server when the settings trust a custom CA bundle or
+/// pin the certificate of the server. None keeps the system defaults.
+///
+/// A pinned certificate is trusted on its own, so the CA bundle is not used along with a pin.
+pub fn client_config(ca_path: Option<&str>, pin: Option<&str>) -> Result<Option<ClientConfig>> {
+    let provider = Arc::new(ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let config = match (pin, ca_path) {
+        (Some(pin), _) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier {
+                fingerprint: parse_fingerprint(pin)?,
+                provider,
+            }))
+            .with_no_client_auth(),
+        (None, Some(ca_path)) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_path)? {
+                roots
+                    .add(cert)
+                    .wrap_err_with(|| format!("Invalid CA certificate in {ca_path}"))?;
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        (None, None) => return Ok(None),
+    };
+
+    Ok(Some(config))
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let file = fs_err::File::open(path)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err_with(|| format!("Failed to read the certificates in {path}"))?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificate found in {path}"));
+    }
+
+    Ok(certs)
+}
+
+/// The SHA-256 fingerprint of the DER encoded certificate as lowercase hex.
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+/// Accept the hex of the fingerprint with or without the colons, e.g. from
+/// `openssl x509 -noout -fingerprint -sha256`.
+fn parse_fingerprint(value: &str) -> Result<String> {
+    let value = value
+        .trim()
+        .trim_start_matches("sha256:")
+        .replace(':', "")
+        .to_lowercase();
+    if value.len() != 64 || !value.chars().all(|x| x.is_ascii_hexdigit()) {
+        return Err(eyre!(
+            "The certificate pin has to be the hex of the SHA-256 fingerprint"
+        ));
+    }
+
+    Ok(value)
+}
+
+/// Trusts exactly the server certificate with the fingerprint, e.g. a self signed one. The
+/// handshake signatures are still verified against it.
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    fingerprint: String,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
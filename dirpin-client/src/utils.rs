@@ -1,4 +1,3 @@
-
 /// Prompt the user for an in put in the console
 pub fn read_input(name: &'static str) -> String {
     println!("Please enter {name}: ");
@@ -14,3 +13,11 @@ pub fn read_input_hidden(name: &'static str) -> String {
     rpassword::read_password().expect("Failed to read password input")
 }
 
+/// Prompt for a new passphrase twice to avoid typos in a secret the user can not see.
+pub fn read_new_passphrase() -> eyre::Result<String> {
+    let passphrase = read_input_hidden("passphrase");
+    let confirm = read_input_hidden("passphrase again");
+    eyre::ensure!(passphrase == confirm, "Passphrases do not match");
+    eyre::ensure!(!passphrase.is_empty(), "Passphrase can not be empty");
+    Ok(passphrase)
+}
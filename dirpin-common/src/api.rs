@@ -1,10 +1,63 @@
 use crate::domain::SyncVersion;
 use time::OffsetDateTime;
 
+/// Header carrying the version of the client that sent the request.
+pub const VERSION_HEADER: &str = "x-dirpin-version";
+/// Header carrying the sync protocol version of the client that sent the request.
+pub const API_VERSION_HEADER: &str = "x-dirpin-api-version";
+
+/// Version of the sync protocol. This covers the shape of the api requests and the layout of the
+/// encrypted records. Bump it whenever an older client would not be able to understand it.
+pub const API_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compatibility {
+    Compatible,
+    /// The client is older than the oldest version the server supports
+    ClientTooOld,
+    /// The client is newer than the newest version the server supports
+    ClientTooNew,
+}
+
+impl Compatibility {
+    pub fn check(api_version: u32, min_version: u32, max_version: u32) -> Self {
+        if api_version < min_version {
+            Self::ClientTooOld
+        } else if api_version > max_version {
+            Self::ClientTooNew
+        } else {
+            Self::Compatible
+        }
+    }
+}
+
+impl std::fmt::Display for Compatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            Compatibility::Compatible => "compatible",
+            Compatibility::ClientTooOld => "client is too old",
+            Compatibility::ClientTooNew => "client is too new",
+        };
+        write!(f, "{}", value)
+    }
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize, Eq, PartialEq)]
 pub struct HealthCheckResponse {
     pub status: String,
     pub version: String,
+    /// The sync protocol version of the server. Zero for servers without version negotiation.
+    #[serde(default)]
+    pub api_version: u32,
+    /// The oldest client protocol version the server accepts
+    #[serde(default)]
+    pub min_client_version: u32,
+    /// The newest client protocol version the server accepts
+    #[serde(default)]
+    pub max_client_version: u32,
+    /// The clock of the server when it answered, to detect skew between the hosts
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    pub time: Option<OffsetDateTime>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -18,6 +71,9 @@ pub struct AddSyncRequest {
     pub items: Vec<AddEntryRequest>,
     #[serde(with = "time::serde::rfc3339")]
     pub last_sync_ts: OffsetDateTime,
+    /// Id of the key all the items are encrypted with
+    #[serde(default)]
+    pub key_id: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
@@ -49,12 +105,99 @@ pub struct SyncResponse {
     pub deleted: Vec<RefDelete>,
 }
 
+/// The sync properties of a remote record without its data, to compare it with the local copy.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Eq, PartialEq)]
+pub struct RecordDigest {
+    /// Host: id of the record
+    pub client_id: String,
+    /// Differnet entity kind. Now one of entry/workspace
+    pub kind: String,
+    pub version: u32,
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub deleted_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct DigestResponse {
+    /// All the records of the user, including the deleted ones
+    pub items: Vec<RecordDigest>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct FetchRecordsRequest {
+    /// Host ids of the records to download
+    pub ids: Vec<String>,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct StatusResponse {
     /// The username of the currently signed in user
     pub username: String,
     /// The server version of the library
     pub version: String,
+    /// The sync protocol version of the server. Zero for servers without version negotiation.
+    #[serde(default)]
+    pub api_version: u32,
+    /// The oldest client protocol version the server accepts
+    #[serde(default)]
+    pub min_client_version: u32,
+    /// The newest client protocol version the server accepts
+    #[serde(default)]
+    pub max_client_version: u32,
+    /// Id of the key all the records of the account are encrypted with
+    #[serde(default)]
+    pub key_id: Option<String>,
+    /// Known plaintext encrypted with the account key. Hosts check their key against it.
+    #[serde(default)]
+    pub key_check: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Store the key check of the account. Only allowed when the account has none yet.
+pub struct KeyCheckRequest {
+    pub key_check: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Replace all the live records of the account with the data encrypted with a new key
+pub struct RotateKeyRequest {
+    /// Id of the new key
+    pub key_id: String,
+    /// Salt of the passphrase the new key is derived from. None for a random key.
+    #[serde(default)]
+    pub key_salt: Option<String>,
+    /// Key check encrypted with the new key
+    #[serde(default)]
+    pub key_check: Option<String>,
+    /// Every live record of the account with an unchanged version
+    pub items: Vec<AddEntryRequest>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct HostKeyStatus {
+    pub host_id: String,
+    /// Id of the key the host last uploaded with
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct KeyRecordCount {
+    pub key_id: Option<String>,
+    pub count: u64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct KeyStatusResponse {
+    /// Id of the key all the records of the account are encrypted with
+    pub key_id: Option<String>,
+    /// Salt of the passphrase the key is derived from. None for a random key.
+    #[serde(default)]
+    pub key_salt: Option<String>,
+    pub hosts: Vec<HostKeyStatus>,
+    /// Number of live records per key id. More than one entry means a mixed-key state.
+    pub records: Vec<KeyRecordCount>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -76,6 +219,12 @@ pub struct RegisterRequest {
     pub email: String,
     pub password: String,
     pub host_id: String,
+    /// Salt of the passphrase the encryption key is derived from. None for a random key.
+    #[serde(default)]
+    pub key_salt: Option<String>,
+    /// The invite code for the servers with closed registration
+    #[serde(default)]
+    pub invite: Option<String>,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -98,9 +247,140 @@ pub struct LoginRequest {
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct LoginResponse {
     pub session: String,
+    /// Salt to derive the encryption key from the passphrase. None when the account uses a
+    /// random key that has to be copied from another host.
+    #[serde(default)]
+    pub key_salt: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct DeleteAccountRequest {
+    /// The password of the account to confirm the deletion
+    pub password: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct VerifyRequest {
+    /// The code mailed to the user
+    pub token: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SendPasswordResetRequest {
+    pub username: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ResetPasswordRequest {
+    pub username: String,
+    /// The code mailed to the user
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CreateLinkRequest {
+    /// The account key encrypted with a secret that never leaves the hosts
+    pub data: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SessionInfo {
+    pub host_id: String,
+    /// None for the sessions from before it was tracked
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub created_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub last_used_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+    /// The session of the host that asked
+    pub current: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct SessionsResponse {
+    pub sessions: Vec<SessionInfo>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RevokeSessionsRequest {
+    /// Revoke the sessions of this host
+    pub host_id: Option<String>,
+    /// Revoke every session except the one of the host that asked
+    #[serde(default)]
+    pub all_others: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct RevokeSessionsResponse {
+    pub revoked: u64,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct CreateLinkResponse {
+    pub code: String,
+    #[serde(with = "time::serde::rfc3339")]
+    pub expires_at: OffsetDateTime,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ClaimLinkRequest {
+    pub code: String,
+    pub host_id: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ClaimLinkResponse {
+    pub session: String,
+    /// The encrypted account key from `CreateLinkRequest`
+    pub data: String,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 pub struct ErrorMessage {
     pub value: String,
 }
+
+/// A record of an upload that conflicts with the server copy, together with the server copy.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct RejectedRecord {
+    /// Host: id of the record
+    pub client_id: String,
+    /// Differnet entity kind. Now one of entry/workspace
+    pub kind: String,
+    /// Server: version of the record
+    pub version: u32,
+    /// Server: updated_at of the record
+    #[serde(with = "time::serde::rfc3339")]
+    pub updated_at: OffsetDateTime,
+    /// Server: deleted_at of the record
+    #[serde(with = "time::serde::rfc3339::option")]
+    pub deleted_at: Option<OffsetDateTime>,
+    /// Server: the encrypted data of the record
+    pub data: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Error body returned when some of the uploaded records conflict with the server copies. The rest
+/// of the upload is saved. Older clients read it as an `ErrorMessage`.
+pub struct ConflictMessage {
+    pub value: String,
+    #[serde(default)]
+    pub rejected: Vec<RejectedRecord>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// Error body returned when the server does not support the client protocol version.
+pub struct VersionMismatchMessage {
+    pub value: String,
+    pub api_version: u32,
+    pub min_client_version: u32,
+    pub max_client_version: u32,
+}
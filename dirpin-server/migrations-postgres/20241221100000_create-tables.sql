@@ -0,0 +1,74 @@
+-- Add migration script here
+-- the same schema as the sqlite migrations up to 20241219100000. the timestamps are unix
+-- timestamps, updated_at with nanoseconds.
+create table if not exists users (
+    id bigint generated by default as identity primary key,
+    username text not null unique,
+    email text not null unique,
+    password text not null,
+    verified_at bigint,
+    created_at bigint not null,
+    key_id text,                    -- id of the current encryption key of the account
+    key_salt text,                  -- salt of the passphrase derived key, null for a random key
+    key_check text,                 -- known plaintext encrypted with the account key
+    disabled_at bigint              -- set by the admin, the disabled users can't log in
+);
+
+create index if not exists email_unique_idx on users(lower(email));
+create index if not exists username_unique_idx on users(lower(username));
+
+create table if not exists sessions (
+    id bigint generated by default as identity primary key,
+    user_id bigint not null references users(id),
+    token text unique not null,
+    host_id text not null,          -- random device id for device sessions
+    expires_at bigint not null,
+    key_id text,                    -- id of the key the host last uploaded with
+    synced_at bigint,               -- the host has every change before this time
+    created_at bigint,              -- when the host logged in
+    last_used_at bigint             -- last authenticated request of the host
+);
+
+create table if not exists entries (
+    id bigint generated by default as identity primary key,
+    client_id text not null,        -- the id of the item on the client, unique per user
+    user_id bigint not null,        -- id of the registered user
+    version bigint not null,        -- nonencryped metadata to know the latest update
+    data text not null,             -- encrypted data for the pin
+    kind text not null,             -- setting the kind of the entry
+    updated_at bigint not null,     -- nonencryped metadata to konw the last update
+    synced_at bigint not null,      -- Tag when this was last synced at
+    deleted_at bigint,              -- Soft delete
+    key_id text,                    -- id of the key the data is encrypted with
+    unique(user_id, client_id)
+);
+
+create index if not exists idx_entries_updated_at on entries(updated_at);
+create index if not exists idx_entries_deleted_at on entries(deleted_at);
+
+create table if not exists user_verification_token (
+    id bigint generated by default as identity primary key,
+    user_id bigint not null references users(id) on delete cascade,
+    kind text not null,             -- one of verify/reset
+    token text not null,
+    expires_at bigint not null,
+    unique(user_id, kind)
+);
+
+create table if not exists link_codes (
+    id bigint generated by default as identity primary key,
+    user_id bigint not null references users(id),
+    code text unique not null,      -- random code the new host claims the link with
+    data text not null,             -- account key encrypted with the secret only the token holds
+    expires_at bigint not null,
+    created_at bigint not null
+);
+
+create table if not exists invite_codes (
+    id bigint generated by default as identity primary key,
+    code text unique not null,      -- random code a new user registers with
+    expires_at bigint,              -- null for the codes that don't expire
+    created_at bigint not null,
+    used_at bigint,                 -- every code registers one user
+    used_by bigint references users(id) on delete set null
+);
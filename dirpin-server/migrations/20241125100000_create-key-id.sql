@@ -0,0 +1,4 @@
+-- Add migration script here
+alter table entries add column key_id text;     -- id of the key the data is encrypted with
+alter table users add column key_id text;       -- id of the current encryption key of the account
+alter table sessions add column key_id text;    -- id of the key the host last uploaded with
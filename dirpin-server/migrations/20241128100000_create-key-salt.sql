@@ -0,0 +1,2 @@
+-- Add migration script here
+alter table users add column key_salt text;     -- salt of the passphrase derived key, null for a random key
@@ -0,0 +1,9 @@
+-- Add migration script here
+create table if not exists link_codes (
+    id integer primary key,
+    user_id integer not null references users(id),
+    code text unique not null,      -- random code the new host claims the link with
+    data text not null,             -- account key encrypted with the secret only the token holds
+    expires_at integer not null,
+    created_at integer not null
+);
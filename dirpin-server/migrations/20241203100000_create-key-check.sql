@@ -0,0 +1,2 @@
+-- Add migration script here
+alter table users add column key_check text;    -- known plaintext encrypted with the account key, opaque to the server
@@ -0,0 +1,2 @@
+-- Add migration script here
+alter table sessions add column synced_at integer;  -- the host has every change before this time
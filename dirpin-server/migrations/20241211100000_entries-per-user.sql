@@ -0,0 +1,28 @@
+-- Add migration script here
+-- The client ids are only unique per user. Sqlite can not change a constraint, so the table is
+-- rebuilt with the unique key on (user_id, client_id).
+create table entries_per_user(
+    id integer primary key,         -- internal id for this db
+    client_id text not null,        -- the id of the item on the client, unique per user
+    user_id integer not null,       -- id of the registered user
+    version integer not null,       -- nonencryped metadata to know the latest update
+    data text not null,             -- encrypted data for the pin
+    kind text not null,             -- setting the kind of the entry
+    updated_at integer not null,    -- nonencryped metadata to konw the last update
+    synced_at integer not null,     -- Tag when this was last synced at
+    deleted_at,                     -- Soft delete
+    key_id text,                    -- id of the key the data is encrypted with
+    unique(user_id, client_id)
+);
+
+insert into entries_per_user(
+    id, client_id, user_id, version, data, kind, updated_at, synced_at, deleted_at, key_id
+)
+select id, client_id, user_id, version, data, kind, updated_at, synced_at, deleted_at, key_id
+from entries;
+
+drop table entries;
+alter table entries_per_user rename to entries;
+
+create index if not exists idx_entries_updated_at on entries(updated_at);
+create index if not exists idx_entries_deleted_at on entries(deleted_at);
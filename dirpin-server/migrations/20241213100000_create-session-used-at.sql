@@ -0,0 +1,3 @@
+-- Add migration script here
+alter table sessions add column created_at integer;     -- when the host logged in
+alter table sessions add column last_used_at integer;   -- last authenticated request of the host
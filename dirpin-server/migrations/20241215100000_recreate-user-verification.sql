@@ -0,0 +1,13 @@
+-- Add migration script here
+-- the table was never written to. a user can have one pending code of every kind.
+drop table if exists user_verification_token;
+
+create table user_verification_token(
+  id integer primary key,
+  user_id integer not null references users(id) on delete cascade,
+  -- one of verify/reset
+  kind text not null,
+  token text not null,
+  expires_at integer not null,
+  unique(user_id, kind)
+);
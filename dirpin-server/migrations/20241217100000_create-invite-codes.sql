@@ -0,0 +1,9 @@
+-- Add migration script here
+create table if not exists invite_codes (
+	id integer primary key,
+	code text unique not null,                              -- random code a new user registers with
+	expires_at integer,                                     -- null for the codes that don't expire
+	created_at integer not null,
+	used_at integer,                                        -- every code registers one user
+	used_by integer references users(id) on delete set null
+);
@@ -0,0 +1,2 @@
+-- Add migration script here
+alter table users add column disabled_at integer;  -- set by the admin, the disabled users can't log in
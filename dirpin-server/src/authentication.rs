@@ -11,6 +11,11 @@ use eyre::Context;
 use time::{Duration, OffsetDateTime};
 use tracing::error;
 
+/// How long a session lives without being used.
+const SESSION_LIFETIME: Duration = Duration::weeks(12);
+/// A session used within this time of its expiry gets the full lifetime again.
+const SESSION_RENEW_WINDOW: Duration = Duration::weeks(6);
+
 pub struct UserSession {
     user: User,
     token: String,
@@ -26,10 +31,34 @@ impl UserSession {
     }
 }
 
+/// The session of a user that may not have verified the email address yet. Only for the
+/// endpoints of the verification itself, the rest use `UserSession`.
+pub struct UnverifiedSession(pub UserSession);
+
 #[async_trait]
 impl FromRequestParts<AppState> for UserSession {
     type Rejection = ServerError;
 
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let UnverifiedSession(session) = UnverifiedSession::from_request_parts(req, state).await?;
+
+        if state.settings.require_verification && session.user.verified_at.is_none() {
+            return Err(ServerError::Forbidden(
+                "Verify the email address of the account first",
+            ));
+        }
+
+        Ok(session)
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for UnverifiedSession {
+    type Rejection = ServerError;
+
     async fn from_request_parts(
         req: &mut Parts,
         state: &AppState,
@@ -59,16 +88,30 @@ impl FromRequestParts<AppState> for UserSession {
             .await
             .map_err(|err| match err {
                 DbError::NotFound => ServerError::Unauthorized("session not found"),
-                DbError::Other(err) => {
+                err => {
                     error!("get_session_user: database error {err}");
                     ServerError::UnexpectedError("failed to get session")
                 }
             })?;
 
-        Ok(UserSession {
+        if user.disabled_at.is_some() {
+            return Err(ServerError::Unauthorized("The account is disabled"));
+        }
+
+        let renew_before = OffsetDateTime::now_utc().saturating_add(SESSION_RENEW_WINDOW);
+        if let Err(err) = state
+            .database
+            .touch_session(token, renew_before, session_expires_at())
+            .await
+        {
+            // The request can go on with the session as it is.
+            error!("touch_session: database error {err}");
+        }
+
+        Ok(UnverifiedSession(UserSession {
             user,
             token: token.into(),
-        })
+        }))
     }
 }
 
@@ -85,7 +128,7 @@ pub(crate) fn hash_password(value: &str) -> eyre::Result<String> {
 }
 
 pub(crate) fn session_expires_at() -> OffsetDateTime {
-    OffsetDateTime::now_utc().saturating_add(Duration::weeks(12))
+    OffsetDateTime::now_utc().saturating_add(SESSION_LIFETIME)
 }
 
 pub(crate) fn verify_password_hash(
@@ -120,7 +163,7 @@ pub(crate) async fn validate_credentials(
             user = Some(u);
         }
         Err(DbError::NotFound) => {}
-        Err(DbError::Other(err)) => {
+        Err(err) => {
             error!("failed query {err}");
             return Err(ServerError::UnexpectedError("Failed to execute query"));
         }
@@ -0,0 +1,239 @@
+use crate::models::{
+    Entry, EntryDigest, HostSession, InviteCode, KeyRecordCount, LinkCode, NewEntry, NewInviteCode,
+    NewLinkCode, NewSession, NewUser, NewVerificationToken, RecordCount, RenewSession, ServerStats,
+    Session, User, VerificationKind,
+};
+use async_trait::async_trait;
+use eyre::Result;
+use std::ops::Deref;
+use std::sync::Arc;
+use time::OffsetDateTime;
+
+mod postgres;
+mod sqlite;
+
+pub use postgres::PostgresStorage;
+pub use sqlite::SqliteStorage;
+
+#[derive(Debug)]
+pub enum DbError {
+    NotFound,
+    /// The data changed in a way that does not allow the operation
+    Conflict,
+    // TODO: Not sure if the eyre::Error or eyre::Report is the right thing to have here
+    // as it no longer does a nice formatting in the serer axum logging as it addes
+    // empty lines and breaks the log flow?
+    Other(eyre::Error),
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl std::error::Error for DbError {}
+
+fn db_error(error: sqlx::Error) -> DbError {
+    match error {
+        sqlx::Error::RowNotFound => DbError::NotFound,
+        error => DbError::Other(error.into()),
+    }
+}
+
+/// The storage operations of the server. Every database backend implements them.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Create or update the tables of the backend.
+    async fn migrate(&self) -> eyre::Result<()>;
+
+    /// Check that the database answers queries.
+    async fn ping(&self) -> Result<(), DbError>;
+
+    async fn list_entries(&self, user_id: u32, from: OffsetDateTime)
+        -> Result<Vec<Entry>, DbError>;
+
+    async fn list_entries_deleted(
+        &self,
+        user_id: u32,
+        from: OffsetDateTime,
+    ) -> Result<Vec<Entry>, DbError>;
+
+    async fn list_changed_from(
+        &self,
+        user_id: u32,
+        from: OffsetDateTime,
+    ) -> Result<Vec<Entry>, DbError>;
+
+    /// The sync properties of all the records of the user, including the deleted ones.
+    async fn list_entry_digests(&self, user_id: u32) -> Result<Vec<EntryDigest>, DbError>;
+
+    async fn list_entries_by_client_ids(
+        &self,
+        user_id: u32,
+        client_ids: &[String],
+    ) -> Result<Vec<Entry>, DbError>;
+
+    async fn save_entries(&self, entries: &[NewEntry]) -> Result<(), DbError>;
+
+    /// Replace the data of all the live records of the user with the data encrypted with the new
+    /// key in one transaction. The records have to match the server versions exactly, otherwise
+    /// some host changed them in the meantime and nothing is written. The data of the deleted
+    /// records is not needed by anyone and is dropped, so no data stays under the old key.
+    async fn rotate_key(
+        &self,
+        user_id: u32,
+        session_token: &str,
+        key_id: &str,
+        key_salt: Option<&str>,
+        key_check: Option<&str>,
+        entries: &[NewEntry],
+    ) -> Result<(), DbError>;
+
+    async fn set_user_key(&self, user_id: u32, key_id: &str) -> Result<(), DbError>;
+
+    /// Store the key check unless the user already has one. Returns false when it was not stored.
+    async fn set_user_key_check(&self, user_id: u32, key_check: &str) -> Result<bool, DbError>;
+
+    async fn set_session_key(&self, token: &str, key_id: &str) -> Result<(), DbError>;
+
+    async fn list_user_sessions(&self, user_id: u32) -> Result<Vec<Session>, DbError>;
+
+    async fn count_entries_by_key(&self, user_id: u32) -> Result<Vec<KeyRecordCount>, DbError>;
+
+    /// Remember that the host of the session has all the changes before the timestamp. It only
+    /// moves forward.
+    async fn set_session_synced(
+        &self,
+        token: &str,
+        synced_at: OffsetDateTime,
+    ) -> Result<(), DbError>;
+
+    /// Drop the tombstones every known host of the user has synced past. A host is known as long
+    /// as it has a session, and a user without any hosts keeps the tombstones. Returns the number
+    /// of purged records.
+    async fn purge_tombstones(&self, user_id: Option<u32>) -> Result<u64, DbError>;
+
+    /// Add the user and use up the invite code of the user. Fails with `DbError::NotFound` when
+    /// the code is unknown, used or expired.
+    async fn add_user(&self, user: NewUser) -> Result<u32, DbError>;
+
+    async fn add_session(&self, session: NewSession) -> Result<(), DbError>;
+
+    async fn get_host_session(&self, session: HostSession) -> Result<Option<Session>, DbError>;
+
+    async fn renew_session(&self, session: RenewSession) -> Result<(), DbError>;
+
+    async fn remove_session(&self, token: &str) -> Result<(), DbError>;
+
+    /// Mark the session as used. A session that would expire before `renew_before` gets the new
+    /// expiry, so the hosts that are in use stay logged in.
+    async fn touch_session(
+        &self,
+        token: &str,
+        renew_before: OffsetDateTime,
+        expires_at: OffsetDateTime,
+    ) -> Result<(), DbError>;
+
+    /// Remove the sessions of the user on the host. Returns the number of removed sessions.
+    async fn remove_host_sessions(&self, user_id: u32, host_id: &str) -> Result<u64, DbError>;
+
+    /// Remove all the sessions of the user except the one with the token. Returns the number of
+    /// removed sessions.
+    async fn remove_other_sessions(&self, user_id: u32, token: &str) -> Result<u64, DbError>;
+
+    async fn get_session(&self, token: &str) -> Result<Option<Session>, DbError>;
+
+    async fn get_session_user(&self, token: &str) -> Result<User, DbError>;
+
+    async fn get_user(&self, username: &str) -> Result<User, DbError>;
+
+    async fn add_link_code(&self, link: NewLinkCode) -> Result<(), DbError>;
+
+    /// Remove the link code and return it when it's still valid. Every code can be claimed only
+    /// once. The expired codes are dropped on the way.
+    async fn take_link_code(&self, code: &str) -> Result<Option<LinkCode>, DbError>;
+
+    /// Store the token for the user. It replaces the pending token of the same kind.
+    async fn set_verification_token(&self, token: NewVerificationToken) -> Result<(), DbError>;
+
+    /// Remove the token of the user when it matches and is still valid. Returns whether it did.
+    async fn take_verification_token(
+        &self,
+        user_id: u32,
+        kind: VerificationKind,
+        token: &str,
+    ) -> Result<bool, DbError>;
+
+    async fn set_user_verified(&self, user_id: u32) -> Result<(), DbError>;
+
+    /// Replace the password hash of the user and log out all the hosts except the one with the
+    /// `keep` session token. Returns the number of removed sessions.
+    async fn set_password(
+        &self,
+        user_id: u32,
+        password: &str,
+        keep: Option<&str>,
+    ) -> Result<u64, DbError>;
+
+    /// Remove the user with all the records, sessions and codes.
+    async fn delete_user(&self, user_id: u32) -> Result<(), DbError>;
+
+    async fn add_invite_code(&self, invite: NewInviteCode) -> Result<(), DbError>;
+
+    async fn list_invite_codes(&self) -> Result<Vec<InviteCode>, DbError>;
+
+    /// Remove the code when nobody registered with it yet. Returns whether it did.
+    async fn remove_invite_code(&self, code: &str) -> Result<bool, DbError>;
+
+    async fn list_users(&self) -> Result<Vec<User>, DbError>;
+
+    /// Disable or enable the user. Disabling logs out all the hosts of the user and drops the
+    /// link codes.
+    async fn set_user_disabled(&self, user_id: u32, disabled: bool) -> Result<(), DbError>;
+
+    /// Count the records of every kind, of all the users by default.
+    async fn count_records(&self, user_id: Option<u32>) -> Result<Vec<RecordCount>, DbError>;
+
+    /// All the sessions including the expired ones.
+    async fn list_sessions(&self) -> Result<Vec<Session>, DbError>;
+
+    /// Remove the expired sessions. Returns the number of removed sessions.
+    async fn purge_expired_sessions(&self) -> Result<u64, DbError>;
+
+    async fn stats(&self) -> Result<ServerStats, DbError>;
+
+    /// Size of the database in bytes.
+    async fn db_size(&self) -> Result<i64, DbError>;
+
+    /// Rebuild the database file to give the space of the removed rows back.
+    async fn vacuum(&self) -> Result<(), DbError>;
+}
+
+/// Handle to the storage backend of the server.
+#[derive(Clone)]
+pub struct Database(Arc<dyn Storage>);
+
+impl Database {
+    /// Open the PostgreSQL database for a "postgres://" uri and the SQLite database file for
+    /// anything else.
+    pub async fn new(uri: &str) -> Result<Self> {
+        if uri.starts_with("postgres://") || uri.starts_with("postgresql://") {
+            Ok(Self::from_storage(PostgresStorage::new(uri).await?))
+        } else {
+            Ok(Self::from_storage(SqliteStorage::new(&uri).await?))
+        }
+    }
+
+    pub fn from_storage(storage: impl Storage + 'static) -> Self {
+        Self(Arc::new(storage))
+    }
+}
+
+impl Deref for Database {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
@@ -0,0 +1,978 @@
+use super::{db_error, DbError, Storage};
+use crate::models::{
+    Entry, EntryDigest, HostSession, InviteCode, KeyRecordCount, LinkCode, NewEntry, NewInviteCode,
+    NewLinkCode, NewSession, NewUser, NewVerificationToken, RecordCount, RenewSession, ServerStats,
+    Session, User, VerificationKind,
+};
+use async_trait::async_trait;
+use eyre::Result;
+use futures_util::TryStreamExt;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteRow};
+use sqlx::FromRow;
+use sqlx::{Row, SqlitePool};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use time::OffsetDateTime;
+use tracing::debug;
+
+// timestamp/updated_at -> unix timestamp with nanoseconds for precision
+// expires_at/created_at/deleted_at -> unix timestamp
+
+pub struct DbEntry(pub Entry);
+pub struct DbUser(pub User);
+pub struct DbSession(pub Session);
+pub struct DbLinkCode(pub LinkCode);
+pub struct DbInviteCode(pub InviteCode);
+pub struct DbEntryDigest(pub EntryDigest);
+
+impl<'r> FromRow<'r, SqliteRow> for DbEntry {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(Entry {
+            id: row.try_get("id")?,
+            client_id: row.try_get("client_id")?,
+            user_id: row.try_get("user_id")?,
+            version: row.try_get("version")?,
+            data: row.try_get("data")?,
+            kind: row.try_get("kind").map(|x: &str| x.parse().unwrap())?,
+            synced_at: row
+                .try_get("synced_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+            updated_at: row
+                .try_get("updated_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp_nanos(x as i128).unwrap())?,
+            deleted_at: row.try_get("deleted_at").map(|x: Option<i64>| match x {
+                Some(x) => OffsetDateTime::from_unix_timestamp(x).ok(),
+                None => None,
+            })?,
+            key_id: row.try_get("key_id")?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbEntryDigest {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(EntryDigest {
+            client_id: row.try_get("client_id")?,
+            kind: row.try_get("kind")?,
+            version: row.try_get("version")?,
+            updated_at: row
+                .try_get("updated_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp_nanos(x as i128).unwrap())?,
+            deleted_at: row.try_get("deleted_at").map(|x: Option<i64>| match x {
+                Some(x) => OffsetDateTime::from_unix_timestamp(x).ok(),
+                None => None,
+            })?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbUser {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(User {
+            id: row.try_get("id")?,
+            username: row.try_get("username")?,
+            email: row.try_get("email")?,
+            password: row.try_get("password")?,
+            verified_at: row
+                .try_get("verified_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+            created_at: row
+                .try_get("created_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+            key_id: row.try_get("key_id")?,
+            key_salt: row.try_get("key_salt")?,
+            key_check: row.try_get("key_check")?,
+            disabled_at: row
+                .try_get("disabled_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbSession {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(Session {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            host_id: row.try_get("host_id").ok(),
+            token: row.try_get("token")?,
+            expires_at: row
+                .try_get("expires_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+            key_id: row.try_get("key_id")?,
+            created_at: row
+                .try_get("created_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+            last_used_at: row
+                .try_get("last_used_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbLinkCode {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(LinkCode {
+            id: row.try_get("id")?,
+            user_id: row.try_get("user_id")?,
+            code: row.try_get("code")?,
+            data: row.try_get("data")?,
+            expires_at: row
+                .try_get("expires_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+            created_at: row
+                .try_get("created_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+        }))
+    }
+}
+
+impl<'r> FromRow<'r, SqliteRow> for DbInviteCode {
+    fn from_row(row: &'r SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self(InviteCode {
+            id: row.try_get("id")?,
+            code: row.try_get("code")?,
+            expires_at: row
+                .try_get("expires_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+            created_at: row
+                .try_get("created_at")
+                .map(|x: i64| OffsetDateTime::from_unix_timestamp(x).unwrap())?,
+            used_at: row
+                .try_get("used_at")
+                .map(|x: Option<i64>| x.map(|x| OffsetDateTime::from_unix_timestamp(x).unwrap()))?,
+            used_by: row.try_get("username")?,
+        }))
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pub pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    /// Open the database file, or an in memory database for "sqlite::memory:".
+    pub async fn new<P: AsRef<Path>>(path: &P) -> Result<Self> {
+        let path = path.as_ref();
+        debug!("opening database at {:?}", path);
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs_err::create_dir_all(dir)?;
+            }
+        }
+
+        let options =
+            SqliteConnectOptions::from_str(path.to_str().unwrap())?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        Ok(Self { pool })
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> eyre::Result<()> {
+        debug!("setting up database");
+        sqlx::migrate!("./migrations").run(&self.pool).await?;
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), DbError> {
+        sqlx::query("select 1")
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+
+    async fn list_entries(
+        &self,
+        user_id: u32,
+        from: OffsetDateTime,
+    ) -> Result<Vec<Entry>, DbError> {
+        sqlx::query_as(
+            "select * from entries where user_id = ?1 and updated_at >= ?2 and deleted_at is null",
+        )
+        .bind(user_id)
+        .bind(from.unix_timestamp_nanos() as i64)
+        .fetch(&self.pool)
+        .map_ok(|DbEntry(entry)| entry)
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn list_entries_deleted(
+        &self,
+        user_id: u32,
+        from: OffsetDateTime,
+    ) -> Result<Vec<Entry>, DbError> {
+        sqlx::query_as("select * from entries where user_id = ?1 and deleted_at >= ?2")
+            .bind(user_id)
+            .bind(from.unix_timestamp())
+            .fetch(&self.pool)
+            .map_ok(|DbEntry(entry)| entry)
+            .try_collect()
+            .await
+            .map_err(db_error)
+    }
+
+    async fn list_changed_from(
+        &self,
+        user_id: u32,
+        from: OffsetDateTime,
+    ) -> Result<Vec<Entry>, DbError> {
+        sqlx::query_as(
+            "select * from entries where user_id = ?1 and (updated_at >= ?2 or deleted_at >= ?3)",
+        )
+        .bind(user_id)
+        .bind(from.unix_timestamp_nanos() as i64)
+        .bind(from.unix_timestamp())
+        .fetch(&self.pool)
+        .map_ok(|DbEntry(entry)| entry)
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn list_entry_digests(&self, user_id: u32) -> Result<Vec<EntryDigest>, DbError> {
+        sqlx::query_as(
+            "select client_id, kind, version, updated_at, deleted_at from entries where user_id = ?1",
+        )
+        .bind(user_id)
+        .fetch(&self.pool)
+        .map_ok(|DbEntryDigest(entry)| entry)
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn list_entries_by_client_ids(
+        &self,
+        user_id: u32,
+        client_ids: &[String],
+    ) -> Result<Vec<Entry>, DbError> {
+        let mut conn = self.pool.acquire().await.map_err(db_error)?;
+        let mut res = Vec::with_capacity(client_ids.len());
+
+        for id in client_ids {
+            let entry: Option<DbEntry> =
+                sqlx::query_as("select * from entries where user_id = ?1 and client_id = ?2")
+                    .bind(user_id)
+                    .bind(id.as_str())
+                    .fetch_optional(&mut *conn)
+                    .await
+                    .map_err(db_error)?;
+            res.extend(entry.map(|DbEntry(x)| x));
+        }
+
+        Ok(res)
+    }
+
+    async fn save_entries(&self, entries: &[NewEntry]) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        for el in entries {
+            sqlx::query(
+                r#"
+                insert into entries(
+                    client_id, user_id, updated_at, version, data, kind, deleted_at, synced_at,
+                    key_id
+                ) 
+                values(
+                    ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9
+                )
+                on conflict(user_id, client_id) do update set
+                    updated_at = ?3, 
+                    version = ?4, 
+                    data = ?5,
+                    kind = ?6,
+                    deleted_at = ?7,
+                    synced_at = ?8,
+                    key_id = ?9
+            "#,
+            )
+            .bind(el.client_id.as_str())
+            .bind(el.user_id)
+            .bind(el.updated_at.unix_timestamp_nanos() as i64)
+            .bind(el.version)
+            .bind(el.data.as_str())
+            .bind(el.kind.to_string())
+            .bind(el.deleted_at.map(|x| x.unix_timestamp()))
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .bind(el.key_id.as_deref())
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        }
+
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(())
+    }
+
+    async fn rotate_key(
+        &self,
+        user_id: u32,
+        session_token: &str,
+        key_id: &str,
+        key_salt: Option<&str>,
+        key_check: Option<&str>,
+        entries: &[NewEntry],
+    ) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+
+        let (live,): (i64,) = sqlx::query_as(
+            "select count(1) from entries where user_id = ?1 and deleted_at is null",
+        )
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        if live as usize != entries.len() {
+            return Err(DbError::Conflict);
+        }
+
+        let synced_at = OffsetDateTime::now_utc().unix_timestamp();
+        for el in entries {
+            let res = sqlx::query(
+                r#"
+                update entries
+                set data = ?4, key_id = ?5, synced_at = ?6
+                where user_id = ?1 and client_id = ?2 and version = ?3 and deleted_at is null
+                "#,
+            )
+            .bind(user_id)
+            .bind(el.client_id.as_str())
+            .bind(el.version)
+            .bind(el.data.as_str())
+            .bind(key_id)
+            .bind(synced_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+            if res.rows_affected() != 1 {
+                return Err(DbError::Conflict);
+            }
+        }
+
+        sqlx::query(
+            r#"
+            update entries
+            set data = '', key_id = ?2
+            where user_id = ?1 and deleted_at is not null
+            "#,
+        )
+        .bind(user_id)
+        .bind(key_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        sqlx::query("update users set key_id = ?2, key_salt = ?3, key_check = ?4 where id = ?1")
+            .bind(user_id)
+            .bind(key_id)
+            .bind(key_salt)
+            .bind(key_check)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        sqlx::query("update sessions set key_id = ?2 where token = ?1")
+            .bind(session_token)
+            .bind(key_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(())
+    }
+
+    async fn set_user_key(&self, user_id: u32, key_id: &str) -> Result<(), DbError> {
+        sqlx::query("update users set key_id = ?2 where id = ?1")
+            .bind(user_id)
+            .bind(key_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+
+    async fn set_user_key_check(&self, user_id: u32, key_check: &str) -> Result<bool, DbError> {
+        sqlx::query("update users set key_check = ?2 where id = ?1 and key_check is null")
+            .bind(user_id)
+            .bind(key_check)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.rows_affected() == 1)
+    }
+
+    async fn set_session_key(&self, token: &str, key_id: &str) -> Result<(), DbError> {
+        sqlx::query("update sessions set key_id = ?2 where token = ?1")
+            .bind(token)
+            .bind(key_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+
+    async fn list_user_sessions(&self, user_id: u32) -> Result<Vec<Session>, DbError> {
+        sqlx::query_as(
+            r#"
+            select * from sessions
+            where user_id = ?1 and expires_at > strftime('%s', 'now')
+            order by host_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch(&self.pool)
+        .map_ok(|DbSession(session)| session)
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn count_entries_by_key(&self, user_id: u32) -> Result<Vec<KeyRecordCount>, DbError> {
+        sqlx::query_as(
+            r#"
+            select key_id, count(1) from entries
+            where user_id = ?1 and deleted_at is null
+            group by key_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch(&self.pool)
+        .map_ok(|(key_id, count)| KeyRecordCount { key_id, count })
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn set_session_synced(
+        &self,
+        token: &str,
+        synced_at: OffsetDateTime,
+    ) -> Result<(), DbError> {
+        sqlx::query(
+            "update sessions set synced_at = max(coalesce(synced_at, 0), ?2) where token = ?1",
+        )
+        .bind(token)
+        .bind(synced_at.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        Ok(())
+    }
+
+    async fn purge_tombstones(&self, user_id: Option<u32>) -> Result<u64, DbError> {
+        let hosts: Vec<(u32, i64)> = sqlx::query_as(
+            r#"
+            select user_id, max(coalesce(synced_at, 0)) from sessions
+            where ?1 is null or user_id = ?1
+            group by user_id, host_id
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        let mut synced: HashMap<u32, i64> = HashMap::new();
+        for (user_id, synced_at) in hosts {
+            synced
+                .entry(user_id)
+                .and_modify(|x| *x = (*x).min(synced_at))
+                .or_insert(synced_at);
+        }
+
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let mut purged = 0;
+        for (user_id, synced_at) in synced {
+            let res = sqlx::query(
+                r#"
+                delete from entries
+                where user_id = ?1 and deleted_at is not null and synced_at < ?2
+                "#,
+            )
+            .bind(user_id)
+            .bind(synced_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+            purged += res.rows_affected();
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(purged)
+    }
+
+    async fn add_user(&self, user: NewUser) -> Result<u32, DbError> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        let (user_id,): (u32,) = sqlx::query_as(
+            r#"
+            insert into users(username, email, password, created_at, key_salt)
+            values(?1, ?2, ?3, ?4, ?5)
+            returning id
+            "#,
+        )
+        .bind(user.username)
+        .bind(user.email)
+        .bind(user.password)
+        .bind(now)
+        .bind(user.key_salt)
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        if let Some(code) = user.invite {
+            let res = sqlx::query(
+                r#"
+                update invite_codes set used_at = ?2, used_by = ?3
+                where code = ?1 and used_at is null and (expires_at is null or expires_at > ?2)
+                "#,
+            )
+            .bind(code)
+            .bind(now)
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+            if res.rows_affected() == 0 {
+                return Err(DbError::NotFound);
+            }
+        }
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(user_id)
+    }
+
+    async fn add_session(&self, session: NewSession) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+            insert into sessions(user_id, host_id, token, expires_at, created_at, last_used_at)
+            values(?1, ?2, ?3, ?4, ?5, ?5)
+            "#,
+        )
+        .bind(session.user_id)
+        .bind(session.host_id)
+        .bind(session.token)
+        .bind(session.expires_at.unix_timestamp())
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn get_host_session(&self, session: HostSession) -> Result<Option<Session>, DbError> {
+        sqlx::query_as(
+            r#"
+            select * from sessions 
+            where user_id = ?1 and host_id = ?2 and expires_at > strftime('%s', 'now')
+        "#,
+        )
+        .bind(session.user_id)
+        .bind(session.host_id)
+        .fetch_optional(&self.pool)
+        .await
+        // TODO: here we don't have to cover all the database errors. Just the relevant one which
+        // is failure. Please review this and see how to go about making the errors a bit better
+        // for all the different queries.
+        .map_err(db_error)
+        .map(|x| x.map(|DbSession(session)| session))
+    }
+
+    async fn renew_session(&self, session: RenewSession) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+               update sessions  
+               set token = ?2, expires_at = ?3
+               where id = ?1
+            "#,
+        )
+        .bind(session.id)
+        .bind(session.token)
+        .bind(session.expires_at.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn remove_session(&self, token: &str) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+               delete from sessions  
+               where token = ?1 and expires_at > strftime('%s', 'now')
+            "#,
+        )
+        .bind(token)
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn touch_session(
+        &self,
+        token: &str,
+        renew_before: OffsetDateTime,
+        expires_at: OffsetDateTime,
+    ) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+            update sessions set
+                last_used_at = strftime('%s', 'now'),
+                expires_at = case when expires_at < ?2 then ?3 else expires_at end
+            where token = ?1
+            "#,
+        )
+        .bind(token)
+        .bind(renew_before.unix_timestamp())
+        .bind(expires_at.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn remove_host_sessions(&self, user_id: u32, host_id: &str) -> Result<u64, DbError> {
+        sqlx::query("delete from sessions where user_id = ?1 and host_id = ?2")
+            .bind(user_id)
+            .bind(host_id)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.rows_affected())
+    }
+
+    async fn remove_other_sessions(&self, user_id: u32, token: &str) -> Result<u64, DbError> {
+        sqlx::query("delete from sessions where user_id = ?1 and token != ?2")
+            .bind(user_id)
+            .bind(token)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.rows_affected())
+    }
+
+    async fn get_session(&self, token: &str) -> Result<Option<Session>, DbError> {
+        sqlx::query_as(
+            r#"
+            select * from sessions 
+            where token = ?1 and expires_at > strftime('%s', 'now')
+        "#,
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|x| x.map(|DbSession(session)| session))
+    }
+
+    async fn get_session_user(&self, token: &str) -> Result<User, DbError> {
+        sqlx::query_as(
+            r#"
+            select users.* from users 
+            left join sessions on sessions.user_id = users.id
+            where sessions.token = ?1 and sessions.expires_at > strftime('%s', 'now')
+        "#,
+        )
+        .bind(token)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|DbUser(user)| user)
+    }
+
+    async fn get_user(&self, username: &str) -> Result<User, DbError> {
+        sqlx::query_as("select * from users where username = ?1")
+            .bind(username)
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|DbUser(user)| user)
+    }
+
+    async fn add_link_code(&self, link: NewLinkCode) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+            insert into link_codes(user_id, code, data, expires_at, created_at)
+            values(?1, ?2, ?3, ?4, ?5)
+            "#,
+        )
+        .bind(link.user_id)
+        .bind(link.code)
+        .bind(link.data)
+        .bind(link.expires_at.unix_timestamp())
+        .bind(OffsetDateTime::now_utc().unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn take_link_code(&self, code: &str) -> Result<Option<LinkCode>, DbError> {
+        sqlx::query("delete from link_codes where expires_at <= strftime('%s', 'now')")
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)?;
+
+        sqlx::query_as("delete from link_codes where code = ?1 returning *")
+            .bind(code)
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.map(|DbLinkCode(link)| link))
+    }
+
+    async fn set_verification_token(&self, token: NewVerificationToken) -> Result<(), DbError> {
+        sqlx::query(
+            r#"
+            insert into user_verification_token(user_id, kind, token, expires_at)
+            values(?1, ?2, ?3, ?4)
+            on conflict(user_id, kind) do update set
+                token = excluded.token,
+                expires_at = excluded.expires_at
+            "#,
+        )
+        .bind(token.user_id)
+        .bind(token.kind.to_string())
+        .bind(token.token)
+        .bind(token.expires_at.unix_timestamp())
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|_| ())
+    }
+
+    async fn take_verification_token(
+        &self,
+        user_id: u32,
+        kind: VerificationKind,
+        token: &str,
+    ) -> Result<bool, DbError> {
+        sqlx::query(
+            r#"
+            delete from user_verification_token
+            where user_id = ?1 and kind = ?2 and token = ?3
+                and expires_at > strftime('%s', 'now')
+            "#,
+        )
+        .bind(user_id)
+        .bind(kind.to_string())
+        .bind(token)
+        .execute(&self.pool)
+        .await
+        .map_err(db_error)
+        .map(|x| x.rows_affected() > 0)
+    }
+
+    async fn set_user_verified(&self, user_id: u32) -> Result<(), DbError> {
+        sqlx::query("update users set verified_at = ?2 where id = ?1 and verified_at is null")
+            .bind(user_id)
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+
+    async fn set_password(
+        &self,
+        user_id: u32,
+        password: &str,
+        keep: Option<&str>,
+    ) -> Result<u64, DbError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        sqlx::query("update users set password = ?2 where id = ?1")
+            .bind(user_id)
+            .bind(password)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        let removed =
+            sqlx::query("delete from sessions where user_id = ?1 and (?2 is null or token != ?2)")
+                .bind(user_id)
+                .bind(keep)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?
+                .rows_affected();
+        tx.commit().await.map_err(db_error)?;
+
+        Ok(removed)
+    }
+
+    async fn delete_user(&self, user_id: u32) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        for query in [
+            "delete from entries where user_id = ?1",
+            "delete from sessions where user_id = ?1",
+            "delete from link_codes where user_id = ?1",
+            "delete from user_verification_token where user_id = ?1",
+            "delete from users where id = ?1",
+        ] {
+            sqlx::query(query)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await
+                .map_err(db_error)?;
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn add_invite_code(&self, invite: NewInviteCode) -> Result<(), DbError> {
+        sqlx::query("insert into invite_codes(code, expires_at, created_at) values(?1, ?2, ?3)")
+            .bind(invite.code)
+            .bind(invite.expires_at.map(|x| x.unix_timestamp()))
+            .bind(OffsetDateTime::now_utc().unix_timestamp())
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+
+    async fn list_invite_codes(&self) -> Result<Vec<InviteCode>, DbError> {
+        sqlx::query_as(
+            r#"
+            select invite_codes.*, users.username from invite_codes
+            left join users on users.id = invite_codes.used_by
+            order by invite_codes.created_at, invite_codes.id
+            "#,
+        )
+        .fetch(&self.pool)
+        .map_ok(|DbInviteCode(invite)| invite)
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn remove_invite_code(&self, code: &str) -> Result<bool, DbError> {
+        sqlx::query("delete from invite_codes where code = ?1 and used_at is null")
+            .bind(code)
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.rows_affected() > 0)
+    }
+
+    async fn list_users(&self) -> Result<Vec<User>, DbError> {
+        sqlx::query_as("select * from users order by id")
+            .fetch(&self.pool)
+            .map_ok(|DbUser(user)| user)
+            .try_collect()
+            .await
+            .map_err(db_error)
+    }
+
+    async fn set_user_disabled(&self, user_id: u32, disabled: bool) -> Result<(), DbError> {
+        let mut tx = self.pool.begin().await.map_err(db_error)?;
+        sqlx::query("update users set disabled_at = ?2 where id = ?1")
+            .bind(user_id)
+            .bind(disabled.then(|| OffsetDateTime::now_utc().unix_timestamp()))
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+        if disabled {
+            for query in [
+                "delete from sessions where user_id = ?1",
+                "delete from link_codes where user_id = ?1",
+            ] {
+                sqlx::query(query)
+                    .bind(user_id)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(db_error)?;
+            }
+        }
+        tx.commit().await.map_err(db_error)
+    }
+
+    async fn count_records(&self, user_id: Option<u32>) -> Result<Vec<RecordCount>, DbError> {
+        sqlx::query_as(
+            r#"
+            select kind, sum(deleted_at is null), sum(deleted_at is not null) from entries
+            where ?1 is null or user_id = ?1
+            group by kind
+            order by kind
+            "#,
+        )
+        .bind(user_id)
+        .fetch(&self.pool)
+        .map_ok(|(kind, live, deleted)| RecordCount {
+            kind,
+            live,
+            deleted,
+        })
+        .try_collect()
+        .await
+        .map_err(db_error)
+    }
+
+    async fn list_sessions(&self) -> Result<Vec<Session>, DbError> {
+        sqlx::query_as("select * from sessions order by user_id, host_id")
+            .fetch(&self.pool)
+            .map_ok(|DbSession(session)| session)
+            .try_collect()
+            .await
+            .map_err(db_error)
+    }
+
+    async fn purge_expired_sessions(&self) -> Result<u64, DbError> {
+        sqlx::query("delete from sessions where expires_at <= strftime('%s', 'now')")
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|x| x.rows_affected())
+    }
+
+    async fn stats(&self) -> Result<ServerStats, DbError> {
+        let (users, disabled_users): (i64, i64) =
+            sqlx::query_as("select count(1), count(disabled_at) from users")
+                .fetch_one(&self.pool)
+                .await
+                .map_err(db_error)?;
+        let (sessions,): (i64,) = sqlx::query_as(
+            "select count(1) from sessions where expires_at > strftime('%s', 'now')",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map_err(db_error)?;
+
+        Ok(ServerStats {
+            users,
+            disabled_users,
+            sessions,
+            records: self.count_records(None).await?,
+            db_size: self.db_size().await?,
+        })
+    }
+
+    async fn db_size(&self) -> Result<i64, DbError> {
+        sqlx::query_as("select page_count * page_size from pragma_page_count(), pragma_page_size()")
+            .fetch_one(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|(size,)| size)
+    }
+
+    async fn vacuum(&self) -> Result<(), DbError> {
+        sqlx::query("vacuum")
+            .execute(&self.pool)
+            .await
+            .map_err(db_error)
+            .map(|_| ())
+    }
+}
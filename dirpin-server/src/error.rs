@@ -1,6 +1,8 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Json};
-use dirpin_common::api::ErrorMessage;
+use dirpin_common::api::{
+    ConflictMessage, ErrorMessage, RejectedRecord, VersionMismatchMessage, API_VERSION,
+};
 use tracing::error;
 
 // TODO figure out what interface to implement so that I can do "map_err(ServerError::Validation)
@@ -24,11 +26,26 @@ pub enum ServerError {
     #[error("Unauthorized: {0}")]
     Unauthorized(&'static str),
 
+    #[error("Forbidden: {0}")]
+    Forbidden(&'static str),
+
+    #[error("Too many requests")]
+    TooManyRequests,
+
+    #[error("Unavailable: {0}")]
+    Unavailable(&'static str),
+
     #[error("Unexpected error: {0}")]
     UnexpectedError(&'static str),
 
     #[error("Conflict: {0}")]
     Conflict(&'static str),
+
+    #[error("Conflicting records: {}", .0.len())]
+    RecordConflicts(Vec<RejectedRecord>),
+
+    #[error("Unsupported client version: {client}")]
+    VersionMismatch { client: u32, min: u32, max: u32 },
 }
 
 impl ServerError {
@@ -39,9 +56,14 @@ impl ServerError {
             ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ServerError::InvalidCredentials => StatusCode::BAD_REQUEST,
             ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ServerError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             ServerError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ServerError::DatabaseError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ServerError::Conflict(_) => StatusCode::CONFLICT,
+            ServerError::RecordConflicts(_) => StatusCode::CONFLICT,
+            ServerError::VersionMismatch { .. } => StatusCode::UPGRADE_REQUIRED,
         }
     }
 
@@ -51,8 +73,17 @@ impl ServerError {
             ServerError::Validation(v) => v.to_string(),
             ServerError::BadRequest(v) => v.to_string(),
             ServerError::Conflict(v) => v.to_string(),
+            ServerError::RecordConflicts(v) => {
+                format!("{} records conflict with the server copies", v.len())
+            }
             ServerError::InvalidCredentials => "Invalid credentails".to_string(),
             ServerError::Unauthorized(v) => v.to_string(),
+            ServerError::Forbidden(v) => v.to_string(),
+            ServerError::TooManyRequests => "Too many requests. Please try again later".into(),
+            ServerError::Unavailable(v) => format!("The {v} is unavailable"),
+            ServerError::VersionMismatch { client, min, max } => format!(
+                "Client api version {client} is not supported. Supported versions are {min} to {max}"
+            ),
             ServerError::UnexpectedError(_) | ServerError::DatabaseError(_) => {
                 "An unexpected error occured. Please try agian later".into()
             }
@@ -65,6 +96,23 @@ impl IntoResponse for ServerError {
         let status = self.status_code();
         let value = self.message();
 
+        if let ServerError::VersionMismatch { min, max, .. } = self {
+            return (
+                status,
+                Json(VersionMismatchMessage {
+                    value,
+                    api_version: API_VERSION,
+                    min_client_version: min,
+                    max_client_version: max,
+                }),
+            )
+                .into_response();
+        }
+
+        if let ServerError::RecordConflicts(rejected) = self {
+            return (status, Json(ConflictMessage { value, rejected })).into_response();
+        }
+
         (
             status,
             Json(ErrorMessage {
@@ -7,7 +7,8 @@ use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Json};
 use dirpin_common::api::{
-    AddSyncRequest, RefDelete, RefItem, StatusResponse, SyncRequest, SyncResponse,
+    AddSyncRequest, DigestResponse, FetchRecordsRequest, RecordDigest, RefDelete, RefItem,
+    RejectedRecord, StatusResponse, SyncRequest, SyncResponse, API_VERSION,
 };
 use std::collections::HashMap;
 use tracing::error;
@@ -63,6 +64,83 @@ pub async fn sync(
         })
         .collect::<Vec<_>>();
 
+    // The host only moves its last sync timestamp after a successful sync, so it has all the
+    // changes before it. This allows to purge the tombstones.
+    state
+        .database
+        .set_session_synced(session.token(), params.last_sync_ts)
+        .await
+        .map_err(|err| {
+            error!("Failed to set session synced {err}");
+            ServerError::DatabaseError("list entries")
+        })?;
+
+    Ok(Json(SyncResponse { updated, deleted }))
+}
+
+/// The sync properties of all the records of the user for a full comparison with the host.
+pub async fn digest(
+    session: UserSession,
+    state: State<AppState>,
+) -> Result<Json<DigestResponse>, ServerError> {
+    let user_id = session.user().id;
+
+    let items = state
+        .database
+        .list_entry_digests(user_id)
+        .await
+        .map_err(|err| {
+            error!("Failed to list entry digests {err}");
+            ServerError::DatabaseError("list entries")
+        })?
+        .into_iter()
+        .map(|x| RecordDigest {
+            client_id: x.client_id,
+            kind: x.kind,
+            version: x.version,
+            updated_at: x.updated_at,
+            deleted_at: x.deleted_at,
+        })
+        .collect();
+
+    Ok(Json(DigestResponse { items }))
+}
+
+/// Download the chosen records no matter when they changed.
+pub async fn fetch(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<FetchRecordsRequest>,
+) -> Result<Json<SyncResponse>, ServerError> {
+    let user_id = session.user().id;
+
+    let res = state
+        .database
+        .list_entries_by_client_ids(user_id, &req.ids)
+        .await
+        .map_err(|err| {
+            error!("Failed to list entries {err}");
+            ServerError::DatabaseError("list entries")
+        })?;
+
+    let mut updated = vec![];
+    let mut deleted = vec![];
+    for x in res {
+        match x.deleted_at {
+            Some(deleted_at) => deleted.push(RefDelete {
+                client_id: x.client_id,
+                version: x.version.into(),
+                updated_at: x.updated_at,
+                deleted_at,
+                kind: x.kind,
+            }),
+            None => updated.push(RefItem {
+                data: x.data,
+                kind: x.kind,
+            }),
+        }
+    }
+
     Ok(Json(SyncResponse { updated, deleted }))
 }
 
@@ -73,6 +151,27 @@ pub async fn add(
 ) -> Result<impl IntoResponse, ServerError> {
     let user = session.user();
 
+    // All the records of the account have to be encrypted with the same key. The first upload
+    // decides the key, after that only a key rotation can change it.
+    match (&user.key_id, &req.key_id) {
+        (Some(current), Some(key_id)) if current != key_id => {
+            return Err(ServerError::Conflict(
+                "The records are encrypted with an outdated key. Switch to the current key first.",
+            ));
+        }
+        (None, Some(key_id)) => {
+            state
+                .database
+                .set_user_key(user.id, key_id)
+                .await
+                .map_err(|err| {
+                    error!("Failed to set user key {err}");
+                    ServerError::DatabaseError("add entries")
+                })?;
+        }
+        _ => {}
+    }
+
     let mut client_updates: HashMap<String, NewEntry> = HashMap::new();
     let mut client_deletes: HashMap<String, NewEntry> = HashMap::new();
 
@@ -85,6 +184,7 @@ pub async fn add(
             kind: item.kind,
             updated_at: item.updated_at,
             deleted_at: item.deleted_at,
+            key_id: req.key_id.clone(),
         };
 
         match new_entry.deleted_at {
@@ -106,34 +206,42 @@ pub async fn add(
         .collect();
 
     let mut update_buff = vec![];
+    let mut rejected = vec![];
 
     // from timestamp
     // - if we have an updated item
     //  - check if version and timestmap are higher
-    //  - otherwise report
+    //  - otherwise reject
     //  - if there is no such an item at all, just add it to the db.
-    //  - if the item has already been deleted, report
+    //  - if the item has already been deleted, reject
     //
     // - if we have deleted item
-    //  - if timestamp is newer than deleted, we report
+    //  - if timestamp is newer than deleted, we reject
     //  - if deleted timestamp is newer, we skip.
     //  - otherwise we really don't care and just delete.
-    for (id, c) in client_updates {
+    //
+    // The rest is saved and the rejected items go back to the client with the server copies.
+    // The deletions go through the same loop as the updates. They used to be collected and never
+    // saved, so a deletion only reached the other hosts through a later update of the record.
+    for (id, c) in client_updates.into_iter().chain(client_deletes) {
         match (server_entries.get(&id), c.deleted_at) {
             (Some(s), None) => {
-                if s.deleted_at.is_some() {
-                    return Err(ServerError::Conflict("Updating a deleted item."));
+                if let Some(deleted_at) = s.deleted_at {
+                    // A host restored the item after it was deleted.
+                    if c.updated_at >= deleted_at && c.version > s.version {
+                        update_buff.push(c);
+                    } else {
+                        rejected.push(rejected_record(s));
+                    }
                 } else if c.updated_at >= s.updated_at && c.version >= s.version {
                     update_buff.push(c);
                 } else {
-                    return Err(ServerError::Conflict("Updating an entry."));
+                    rejected.push(rejected_record(s));
                 }
             }
             (Some(s), Some(del_at)) => {
                 if s.updated_at > del_at {
-                    return Err(ServerError::Conflict(
-                        "Trying to delete newer entry with older delete timestamp.",
-                    ));
+                    rejected.push(rejected_record(s));
                 } else {
                     update_buff.push(c);
                 }
@@ -153,14 +261,49 @@ pub async fn add(
             ServerError::DatabaseError("add entries")
         })?;
 
+    if let Some(key_id) = &req.key_id {
+        state
+            .database
+            .set_session_key(session.token(), key_id)
+            .await
+            .map_err(|err| {
+                error!("Failed to set session key {err}");
+                ServerError::DatabaseError("add entries")
+            })?;
+    }
+
+    if !rejected.is_empty() {
+        state.metrics.reject_records(rejected.len());
+        return Err(ServerError::RecordConflicts(rejected));
+    }
+
     Ok(StatusCode::OK)
 }
 
-pub async fn status(session: UserSession) -> Result<Json<StatusResponse>, ServerError> {
+fn rejected_record(s: &Entry) -> RejectedRecord {
+    RejectedRecord {
+        client_id: s.client_id.clone(),
+        kind: s.kind.clone(),
+        version: s.version,
+        updated_at: s.updated_at,
+        deleted_at: s.deleted_at,
+        data: s.data.clone(),
+    }
+}
+
+pub async fn status(
+    session: UserSession,
+    state: State<AppState>,
+) -> Result<Json<StatusResponse>, ServerError> {
     let user = session.user();
 
     Ok(Json(StatusResponse {
         username: user.username.clone(),
         version: VERSION.into(),
+        api_version: API_VERSION,
+        min_client_version: state.settings.min_client_version,
+        max_client_version: state.settings.max_client_version,
+        key_id: user.key_id.clone(),
+        key_check: user.key_check.clone(),
     }))
 }
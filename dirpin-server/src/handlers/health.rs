@@ -0,0 +1,21 @@
+use crate::error::ServerError;
+use crate::router::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use tracing::error;
+
+/// The process is up. It doesn't touch the database so that a slow database doesn't get the
+/// server restarted.
+pub async fn healthz() -> StatusCode {
+    StatusCode::OK
+}
+
+/// The server can handle requests, which needs the database.
+pub async fn readyz(state: State<AppState>) -> Result<StatusCode, ServerError> {
+    state.database.ping().await.map_err(|err| {
+        error!("Failed to reach the database {err}");
+        ServerError::Unavailable("database")
+    })?;
+
+    Ok(StatusCode::OK)
+}
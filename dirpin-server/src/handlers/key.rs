@@ -0,0 +1,141 @@
+use crate::authentication::UserSession;
+use crate::database::DbError;
+use crate::error::ServerError;
+use crate::models::NewEntry;
+use crate::router::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use dirpin_common::api::{
+    HostKeyStatus, KeyCheckRequest, KeyRecordCount, KeyStatusResponse, RotateKeyRequest,
+};
+use tracing::error;
+
+/// Swap the data of every live record of the user for the data encrypted with a new key.
+pub async fn rotate(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<RotateKeyRequest>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = session.user();
+
+    if req.items.iter().any(|x| x.deleted_at.is_some()) {
+        return Err(ServerError::Validation(
+            "Only live records can be re-encrypted",
+        ));
+    }
+
+    let entries = req
+        .items
+        .into_iter()
+        .map(|item| NewEntry {
+            client_id: item.id,
+            user_id: user.id.into(),
+            version: item.version,
+            data: item.data,
+            kind: item.kind,
+            updated_at: item.updated_at,
+            deleted_at: None,
+            key_id: Some(req.key_id.clone()),
+        })
+        .collect::<Vec<_>>();
+
+    state
+        .database
+        .rotate_key(
+            user.id,
+            session.token(),
+            &req.key_id,
+            req.key_salt.as_deref(),
+            req.key_check.as_deref(),
+            &entries,
+        )
+        .await
+        .map_err(|err| match err {
+            DbError::Conflict => ServerError::Conflict(
+                "The records changed during the key rotation. Sync and try again.",
+            ),
+            err => {
+                error!("Failed to rotate key {err}");
+                ServerError::DatabaseError("rotate key")
+            }
+        })?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn status(
+    session: UserSession,
+    state: State<AppState>,
+) -> Result<Json<KeyStatusResponse>, ServerError> {
+    let user = session.user();
+
+    let hosts = state
+        .database
+        .list_user_sessions(user.id)
+        .await
+        .map_err(|err| {
+            error!("Failed to list sessions {err}");
+            ServerError::DatabaseError("key status")
+        })?
+        .into_iter()
+        .map(|x| HostKeyStatus {
+            host_id: x.host_id.unwrap_or_default(),
+            key_id: x.key_id,
+        })
+        .collect();
+
+    let records = state
+        .database
+        .count_entries_by_key(user.id)
+        .await
+        .map_err(|err| {
+            error!("Failed to count entries {err}");
+            ServerError::DatabaseError("key status")
+        })?
+        .into_iter()
+        .map(|x| KeyRecordCount {
+            key_id: x.key_id,
+            count: x.count as u64,
+        })
+        .collect();
+
+    Ok(Json(KeyStatusResponse {
+        key_id: user.key_id.clone(),
+        key_salt: user.key_salt.clone(),
+        hosts,
+        records,
+    }))
+}
+
+/// Store the key check of the account. The first host to sync decides it, after that only a key
+/// rotation replaces it.
+pub async fn set_check(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<KeyCheckRequest>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = session.user();
+
+    if let Some(current) = &user.key_check {
+        if *current == req.key_check {
+            return Ok(StatusCode::OK);
+        }
+        return Err(ServerError::Conflict("The account already has a key check"));
+    }
+
+    let stored = state
+        .database
+        .set_user_key_check(user.id, &req.key_check)
+        .await
+        .map_err(|err| {
+            error!("Failed to set key check {err}");
+            ServerError::DatabaseError("set key check")
+        })?;
+
+    if !stored {
+        return Err(ServerError::Conflict("The account already has a key check"));
+    }
+
+    Ok(StatusCode::OK)
+}
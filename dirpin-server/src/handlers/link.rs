@@ -0,0 +1,64 @@
+use crate::authentication::UserSession;
+use crate::error::ServerError;
+use crate::handlers::user::start_host_session;
+use crate::models::NewLinkCode;
+use crate::router::AppState;
+use axum::extract::State;
+use axum::response::Json;
+use dirpin_common::api::{
+    ClaimLinkRequest, ClaimLinkResponse, CreateLinkRequest, CreateLinkResponse,
+};
+use dirpin_common::utils::crypto_random_string;
+use time::{Duration, OffsetDateTime};
+use tracing::error;
+
+/// Store the encrypted account key under a one time code for a new host to claim.
+pub async fn create(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<CreateLinkRequest>,
+) -> Result<Json<CreateLinkResponse>, ServerError> {
+    let user = session.user();
+    let code = crypto_random_string::<12>();
+    let expires_at =
+        OffsetDateTime::now_utc() + Duration::seconds(state.settings.link_code_ttl as i64);
+
+    state
+        .database
+        .add_link_code(NewLinkCode {
+            user_id: user.id,
+            code: code.clone(),
+            data: req.data,
+            expires_at,
+        })
+        .await
+        .map_err(|err| {
+            error!("Failed to create link code {err}");
+            ServerError::DatabaseError("create link")
+        })?;
+
+    Ok(Json(CreateLinkResponse { code, expires_at }))
+}
+
+/// Exchange the link code for a session of the new host and the encrypted account key.
+pub async fn claim(
+    state: State<AppState>,
+    Json(req): Json<ClaimLinkRequest>,
+) -> Result<Json<ClaimLinkResponse>, ServerError> {
+    let link = state
+        .database
+        .take_link_code(&req.code)
+        .await
+        .map_err(|err| {
+            error!("Failed to claim link code {err}");
+            ServerError::DatabaseError("claim link")
+        })?
+        .ok_or(ServerError::NotFound("The link code is invalid or expired"))?;
+
+    let session = start_host_session(&state, link.user_id, &req.host_id).await?;
+
+    Ok(Json(ClaimLinkResponse {
+        session,
+        data: link.data,
+    }))
+}
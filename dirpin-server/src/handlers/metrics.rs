@@ -0,0 +1,30 @@
+use crate::error::ServerError;
+use crate::router::AppState;
+use axum::extract::State;
+use axum::http::header::CONTENT_TYPE;
+use axum::response::IntoResponse;
+use std::time::Duration;
+use tracing::error;
+
+/// How long the numbers of records and sessions are served before they are read again. Counting
+/// the records on every scrape would load the database for nothing.
+const STATS_MAX_AGE: Duration = Duration::from_secs(60);
+
+/// The metrics for Prometheus to scrape. The numbers of records and sessions come from the
+/// database at most once per `STATS_MAX_AGE`.
+pub async fn metrics(state: State<AppState>) -> Result<impl IntoResponse, ServerError> {
+    if !state.metrics.stats_fresh(STATS_MAX_AGE) {
+        let stats = state.database.stats().await.map_err(|err| {
+            error!("Failed to read the stats {err}");
+            ServerError::DatabaseError("metrics")
+        })?;
+        state.metrics.set_stats(&stats);
+    }
+
+    let body = state.metrics.encode().map_err(|err| {
+        error!("Failed to encode the metrics {err}");
+        ServerError::UnexpectedError("metrics")
+    })?;
+
+    Ok(([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
@@ -1,17 +1,30 @@
 use crate::error::ServerError;
+use crate::router::AppState;
+use axum::extract::State;
 use axum::response::{IntoResponse, Json};
-use dirpin_common::api::HealthCheckResponse;
+use dirpin_common::api::{HealthCheckResponse, API_VERSION};
+use time::OffsetDateTime;
 
 pub mod entry;
+pub mod health;
+pub mod key;
+pub mod link;
+pub mod metrics;
+pub mod session;
 pub mod user;
+pub mod verification;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-pub async fn index() -> Result<Json<HealthCheckResponse>, ServerError> {
+pub async fn index(state: State<AppState>) -> Result<Json<HealthCheckResponse>, ServerError> {
     let version = VERSION.to_string();
 
     Ok(Json(HealthCheckResponse {
         status: "Ok".to_string(),
         version,
+        api_version: API_VERSION,
+        min_client_version: state.settings.min_client_version,
+        max_client_version: state.settings.max_client_version,
+        time: Some(OffsetDateTime::now_utc()),
     }))
 }
@@ -0,0 +1,65 @@
+use crate::authentication::UserSession;
+use crate::error::ServerError;
+use crate::router::AppState;
+use axum::extract::State;
+use axum::response::Json;
+use dirpin_common::api::{
+    RevokeSessionsRequest, RevokeSessionsResponse, SessionInfo, SessionsResponse,
+};
+use tracing::error;
+
+/// List the live sessions of the account, one per logged in host.
+pub async fn list(
+    session: UserSession,
+    state: State<AppState>,
+) -> Result<Json<SessionsResponse>, ServerError> {
+    let sessions = state
+        .database
+        .list_user_sessions(session.user().id)
+        .await
+        .map_err(|err| {
+            error!("Failed to list sessions {err}");
+            ServerError::DatabaseError("list sessions")
+        })?
+        .into_iter()
+        .map(|x| SessionInfo {
+            current: x.token == session.token(),
+            host_id: x.host_id.unwrap_or_default(),
+            created_at: x.created_at,
+            last_used_at: x.last_used_at,
+            expires_at: x.expires_at,
+        })
+        .collect();
+
+    Ok(Json(SessionsResponse { sessions }))
+}
+
+/// Log out the host or every host except the one that asked.
+pub async fn revoke(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<RevokeSessionsRequest>,
+) -> Result<Json<RevokeSessionsResponse>, ServerError> {
+    let user_id = session.user().id;
+
+    let revoked = match (req.host_id, req.all_others) {
+        (Some(host_id), false) => state.database.remove_host_sessions(user_id, &host_id).await,
+        (None, true) => {
+            state
+                .database
+                .remove_other_sessions(user_id, session.token())
+                .await
+        }
+        _ => {
+            return Err(ServerError::BadRequest(
+                "Pick either a host or all the other sessions",
+            ))
+        }
+    }
+    .map_err(|err| {
+        error!("Failed to revoke sessions {err}");
+        ServerError::DatabaseError("revoke sessions")
+    })?;
+
+    Ok(Json(RevokeSessionsResponse { revoked }))
+}
@@ -1,12 +1,18 @@
-use crate::authentication::{hash_password, session_expires_at, validate_credentials, UserSession};
+use crate::authentication::{
+    hash_password, session_expires_at, validate_credentials, verify_password_hash,
+    UnverifiedSession, UserSession,
+};
 use crate::database::DbError;
 use crate::error::ServerError;
-use crate::models::{HostSession, NewSession, NewUser, RenewSession};
+use crate::handlers::verification::send_token;
+use crate::models::{HostSession, NewSession, NewUser, RenewSession, VerificationKind};
 use crate::router::AppState;
 use axum::extract::State;
-use axum::response::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
 use dirpin_common::api::{
-    LoginRequest, LoginResponse, LogoutResponse, RegisterRequest, RegisterResponse,
+    ChangePasswordRequest, DeleteAccountRequest, LoginRequest, LoginResponse, LogoutResponse,
+    RegisterRequest, RegisterResponse, RevokeSessionsResponse,
 };
 use dirpin_common::utils::crypto_random_string;
 use tracing::error;
@@ -25,6 +31,17 @@ pub async fn register(
         ));
     }
 
+    // The invite is only checked on the closed servers so that the code is not used up for
+    // nothing on the open ones.
+    let invite = if state.settings.open_registration {
+        None
+    } else {
+        Some(
+            req.invite
+                .ok_or(ServerError::Forbidden("Registration needs an invite code"))?,
+        )
+    };
+
     // TODO: try to get the user from the db based on the username to make sure that the user
     // can not create a duplicate account. Or otherwise, the unique constraint will fail in the
     // database query.
@@ -38,11 +55,21 @@ pub async fn register(
         email: req.email,
         username: req.username,
         password: hashed_password,
+        key_salt: req.key_salt,
+        invite,
     };
-    let user_id = state.database.add_user(new_user).await.map_err(|err| {
-        error!("Failed saving user: {err}");
-        ServerError::UnexpectedError("Failed to register user")
-    })?;
+    let email = new_user.email.clone();
+    let user_id = state
+        .database
+        .add_user(new_user)
+        .await
+        .map_err(|err| match err {
+            DbError::NotFound => ServerError::Forbidden("The invite code is invalid or used up"),
+            err => {
+                error!("Failed saving user: {err}");
+                ServerError::UnexpectedError("Failed to register user")
+            }
+        })?;
 
     let token = crypto_random_string::<24>();
     let expires_at = session_expires_at();
@@ -54,9 +81,6 @@ pub async fn register(
         expires_at,
     };
 
-    // TODO:: verification step for the user. don't return session yet. We first want to verify the
-    // user.
-
     state
         .database
         .add_session(new_session)
@@ -66,6 +90,12 @@ pub async fn register(
             ServerError::UnexpectedError("Failed to register user")
         })?;
 
+    // The session works for the verification even when the server requires it. The user can ask
+    // for a new code when this one does not arrive.
+    if let Err(err) = send_token(&state, user_id, &email, VerificationKind::Verify).await {
+        error!("Failed to send the verification code: {err}");
+    }
+
     Ok(Json(RegisterResponse { session: token }))
 }
 
@@ -73,13 +103,28 @@ pub async fn login(
     state: State<AppState>,
     req: Json<LoginRequest>,
 ) -> Result<Json<LoginResponse>, ServerError> {
-    // TODO: check if the user is verified. If not, return and ask the user to verify!
-
     let user = validate_credentials(&state.database, &req.username, &req.password).await?;
+    if user.disabled_at.is_some() {
+        return Err(ServerError::Forbidden("The account is disabled"));
+    }
+
+    let next_token = start_host_session(&state, user.id, &req.host_id).await?;
+
+    Ok(Json(LoginResponse {
+        session: next_token,
+        key_salt: user.key_salt.clone(),
+    }))
+}
 
+/// Renew the session of the host or create a new one. Returns the new session token.
+pub(crate) async fn start_host_session(
+    state: &AppState,
+    user_id: u32,
+    host_id: &str,
+) -> Result<String, ServerError> {
     let host_session = HostSession {
-        user_id: user.id,
-        host_id: req.host_id.to_owned(),
+        user_id,
+        host_id: host_id.to_owned(),
     };
 
     let session = match state.database.get_host_session(host_session).await {
@@ -105,8 +150,8 @@ pub async fn login(
         }
         None => {
             let new_session = NewSession {
-                user_id: user.id,
-                host_id: req.host_id.to_owned(),
+                user_id,
+                host_id: host_id.to_owned(),
                 token: next_token.clone(),
                 expires_at: next_expires_at,
             };
@@ -118,14 +163,10 @@ pub async fn login(
         ServerError::UnexpectedError("Database error")
     })?;
 
-    Ok(Json(LoginResponse {
-        session: next_token,
-    }))
+    Ok(next_token)
 }
 
-// TODO:: we don't actually use this in the dirpin client.
-// But we might want to use it at some point. In the client
-// we just remove the session from the host.
+/// End the session of the host that asked.
 pub async fn logout(
     session: UserSession,
     state: State<AppState>,
@@ -153,3 +194,47 @@ pub async fn logout(
 
     Ok(Json(LogoutResponse { ok: true }))
 }
+
+/// Set a new password. The other hosts of the user are logged out.
+pub async fn change_password(
+    session: UserSession,
+    state: State<AppState>,
+    Json(req): Json<ChangePasswordRequest>,
+) -> Result<Json<RevokeSessionsResponse>, ServerError> {
+    let user = session.user();
+    verify_password_hash(&user.password, &req.current_password)?;
+
+    let hashed_password = hash_password(&req.new_password).map_err(|err| {
+        error!("Failed to hash password {err}");
+        ServerError::UnexpectedError("Failed to change password")
+    })?;
+
+    let revoked = state
+        .database
+        .set_password(user.id, &hashed_password, Some(session.token()))
+        .await
+        .map_err(|err| {
+            error!("Failed to change the password {err}");
+            ServerError::DatabaseError("change password")
+        })?;
+
+    Ok(Json(RevokeSessionsResponse { revoked }))
+}
+
+/// Remove the account with all its data. The accounts that are not verified yet can be deleted
+/// too.
+pub async fn delete(
+    UnverifiedSession(session): UnverifiedSession,
+    state: State<AppState>,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = session.user();
+    verify_password_hash(&user.password, &req.password)?;
+
+    state.database.delete_user(user.id).await.map_err(|err| {
+        error!("Failed to delete the user {err}");
+        ServerError::DatabaseError("delete user")
+    })?;
+
+    Ok(StatusCode::OK)
+}
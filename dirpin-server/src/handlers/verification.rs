@@ -0,0 +1,191 @@
+use crate::authentication::{hash_password, UnverifiedSession};
+use crate::database::DbError;
+use crate::error::ServerError;
+use crate::mail::Mail;
+use crate::models::{NewVerificationToken, User, VerificationKind};
+use crate::router::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json};
+use dirpin_common::api::{ResetPasswordRequest, SendPasswordResetRequest, VerifyRequest};
+use dirpin_common::utils::crypto_random_string;
+use time::{Duration, OffsetDateTime};
+use tracing::error;
+
+/// How long the code to verify the email address stays valid.
+const VERIFY_TOKEN_LIFETIME: Duration = Duration::hours(24);
+/// How long the code to reset the password stays valid.
+const RESET_TOKEN_LIFETIME: Duration = Duration::hours(1);
+
+/// Create a new code of the kind for the user and mail it. The code replaces the pending one.
+pub(crate) async fn send_token(
+    state: &AppState,
+    user_id: u32,
+    email: &str,
+    kind: VerificationKind,
+) -> Result<(), ServerError> {
+    let token = crypto_random_string::<12>();
+    let lifetime = match kind {
+        VerificationKind::Verify => VERIFY_TOKEN_LIFETIME,
+        VerificationKind::Reset => RESET_TOKEN_LIFETIME,
+    };
+
+    state
+        .database
+        .set_verification_token(NewVerificationToken {
+            user_id,
+            kind,
+            token: token.clone(),
+            expires_at: OffsetDateTime::now_utc() + lifetime,
+        })
+        .await
+        .map_err(|err| {
+            error!("Failed to save the verification token {err}");
+            ServerError::DatabaseError("save verification token")
+        })?;
+
+    let mail = match kind {
+        VerificationKind::Verify => Mail {
+            to: email.into(),
+            subject: "Verify your dirpin account".into(),
+            body: format!(
+                "Your verification code is {token}\n\n\
+                Verify the account with `dirpin account verify {token}`. \
+                The code is valid for 24 hours."
+            ),
+        },
+        VerificationKind::Reset => Mail {
+            to: email.into(),
+            subject: "Reset your dirpin password".into(),
+            body: format!(
+                "Your password reset code is {token}\n\n\
+                Set a new password with `dirpin account reset-password --token {token}`. \
+                The code is valid for 1 hour. If you did not ask for it, ignore this mail."
+            ),
+        },
+    };
+
+    state.mail.send(&mail).await.map_err(|err| {
+        error!("Failed to send the {kind} mail {err}");
+        ServerError::UnexpectedError("Failed to send the mail")
+    })
+}
+
+/// Mail a new verification code to the user.
+pub async fn send_verification(
+    UnverifiedSession(session): UnverifiedSession,
+    state: State<AppState>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = session.user();
+    if user.verified_at.is_some() {
+        return Err(ServerError::Conflict("The account is already verified"));
+    }
+
+    send_token(&state, user.id, &user.email, VerificationKind::Verify).await?;
+
+    Ok(StatusCode::OK)
+}
+
+pub async fn verify(
+    UnverifiedSession(session): UnverifiedSession,
+    state: State<AppState>,
+    Json(req): Json<VerifyRequest>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = session.user();
+    if user.verified_at.is_some() {
+        return Ok(StatusCode::OK);
+    }
+
+    let valid = state
+        .database
+        .take_verification_token(user.id, VerificationKind::Verify, &req.token)
+        .await
+        .map_err(|err| {
+            error!("Failed to take the verification token {err}");
+            ServerError::DatabaseError("verify")
+        })?;
+    if !valid {
+        return Err(ServerError::Validation(
+            "The verification code is invalid or expired",
+        ));
+    }
+
+    state
+        .database
+        .set_user_verified(user.id)
+        .await
+        .map_err(|err| {
+            error!("Failed to verify the user {err}");
+            ServerError::DatabaseError("verify")
+        })?;
+
+    Ok(StatusCode::OK)
+}
+
+async fn find_user(state: &AppState, username: &str) -> Result<Option<User>, ServerError> {
+    match state.database.get_user(username).await {
+        Ok(user) => Ok(Some(user)),
+        Err(DbError::NotFound) => Ok(None),
+        Err(err) => {
+            error!("Failed to get the user {err}");
+            Err(ServerError::DatabaseError("get user"))
+        }
+    }
+}
+
+/// Mail a password reset code to the user. The response is the same for the unknown users and
+/// the failed mails so that it does not tell which accounts exist. The failures are only logged.
+pub async fn send_password_reset(
+    state: State<AppState>,
+    Json(req): Json<SendPasswordResetRequest>,
+) -> impl IntoResponse {
+    if let Ok(Some(user)) = find_user(&state, &req.username).await {
+        if let Err(err) = send_token(&state, user.id, &user.email, VerificationKind::Reset).await {
+            error!("Failed to send the password reset code: {err}");
+        }
+    }
+
+    StatusCode::OK
+}
+
+/// Set the new password with the mailed code. All the hosts of the user are logged out.
+pub async fn reset_password(
+    state: State<AppState>,
+    Json(req): Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, ServerError> {
+    let user = find_user(&state, &req.username)
+        .await?
+        .ok_or(ServerError::Validation(
+            "The reset code is invalid or expired",
+        ))?;
+
+    let valid = state
+        .database
+        .take_verification_token(user.id, VerificationKind::Reset, &req.token)
+        .await
+        .map_err(|err| {
+            error!("Failed to take the reset token {err}");
+            ServerError::DatabaseError("reset password")
+        })?;
+    if !valid {
+        return Err(ServerError::Validation(
+            "The reset code is invalid or expired",
+        ));
+    }
+
+    let hashed_password = hash_password(&req.password).map_err(|err| {
+        error!("Failed to hash password {err}");
+        ServerError::UnexpectedError("Failed to reset password")
+    })?;
+
+    state
+        .database
+        .set_password(user.id, &hashed_password, None)
+        .await
+        .map_err(|err| {
+            error!("Failed to reset the password {err}");
+            ServerError::DatabaseError("reset password")
+        })?;
+
+    Ok(StatusCode::OK)
+}
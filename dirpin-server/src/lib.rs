@@ -1,7 +1,10 @@
 use axum::{serve, Router};
-use eyre::{Context, Result};
+use eyre::{eyre, Context, Result};
+use mail::MailSender;
 use settings::Settings;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
 use tokio::signal;
 
@@ -11,9 +14,13 @@ mod authentication;
 pub mod database;
 mod error;
 mod handlers;
-mod models;
+pub mod mail;
+mod metrics;
+mod middleware;
+pub mod models;
 mod router;
 pub mod settings;
+pub mod tls;
 
 use database::Database;
 
@@ -40,22 +47,69 @@ async fn shutdown_signal() {
     eprintln!("Shutting down gracefully...");
 }
 
-pub async fn make_router(_settings: &Settings, database: Database) -> Router {
-    router::router(database)
+async fn purge_tombstones_periodically(database: Database, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        match database.purge_tombstones(None).await {
+            Ok(purged) => tracing::info!("Purged {purged} tombstones"),
+            Err(err) => tracing::error!("Failed to purge tombstones {err}"),
+        }
+    }
+}
+
+pub async fn make_router(settings: &Settings, database: Database) -> Result<Router> {
+    let mail = mail::build_sender(settings)?;
+    Ok(make_router_with_mail(settings, database, mail).await)
+}
+
+/// Build the router that delivers the mails with the sender instead of the one in the settings.
+pub async fn make_router_with_mail(
+    settings: &Settings,
+    database: Database,
+    mail: Arc<dyn MailSender>,
+) -> Router {
+    router::router(database, settings.clone(), mail)
 }
 
 pub async fn launch(settings: &Settings, address: SocketAddr) -> Result<()> {
+    let tls = match (&settings.tls_cert, &settings.tls_key) {
+        (Some(cert), Some(key)) => {
+            let config = tls::load_config(cert, key)?;
+            #[cfg(target_family = "unix")]
+            tls::reload_on_hangup(config.clone(), cert.clone(), key.clone())?;
+            Some(config)
+        }
+        (None, None) => None,
+        _ => return Err(eyre!("Set both tls_cert and tls_key to serve https")),
+    };
+
     let listener = TcpListener::bind(address)
         .await
         .context("Failed to connect to tcp listener")?;
-    let database = Database::new(&settings.db_path).await?;
+    let database = Database::new(settings.database_uri()).await?;
     database.migrate().await?;
-    let r = make_router(&settings, database).await;
+    if settings.tombstone_gc_interval > 0 {
+        tokio::spawn(purge_tombstones_periodically(
+            database.clone(),
+            Duration::from_secs(settings.tombstone_gc_interval),
+        ));
+    }
+    let r = make_router(&settings, database).await?;
+    let app = r.into_make_service_with_connect_info::<SocketAddr>();
 
-    tracing::info!("Server started at {}", address);
-    serve(listener, r.into_make_service())
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    match tls {
+        Some(config) => {
+            tracing::info!("Server started at https://{}", address);
+            tls::serve(listener, app, config, shutdown_signal()).await?;
+        }
+        None => {
+            tracing::info!("Server started at {}", address);
+            serve(listener, app)
+                .with_graceful_shutdown(shutdown_signal())
+                .await?;
+        }
+    }
 
     Ok(())
 }
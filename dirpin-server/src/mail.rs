@@ -0,0 +1,135 @@
+use crate::settings::{MailTransport, Settings};
+use async_trait::async_trait;
+use eyre::{eyre, Context, Result};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl std::fmt::Display for Mail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "To: {}", self.to)?;
+        writeln!(f, "Subject: {}", self.subject)?;
+        writeln!(f)?;
+        writeln!(f, "{}", self.body)
+    }
+}
+
+/// Delivers the mails of the server to the users.
+#[async_trait]
+pub trait MailSender: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<()>;
+}
+
+/// Writes the mails to the server log. For the self hosted servers without a mail server.
+pub struct LogSender;
+
+#[async_trait]
+impl MailSender for LogSender {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        tracing::info!("Mail to {}: {}\n{}", mail.to, mail.subject, mail.body);
+        Ok(())
+    }
+}
+
+/// Appends the mails to a file.
+pub struct FileSender {
+    path: String,
+}
+
+#[async_trait]
+impl MailSender for FileSender {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        let mut file = fs_err::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{mail}")?;
+        Ok(())
+    }
+}
+
+/// Keeps the mails in memory so that the tests can read them.
+#[derive(Clone, Default)]
+pub struct MemorySender {
+    mails: Arc<Mutex<Vec<Mail>>>,
+}
+
+impl MemorySender {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mails(&self) -> Vec<Mail> {
+        self.mails.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MailSender for MemorySender {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        self.mails.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+pub struct SmtpSender {
+    from: Mailbox,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpSender {
+    pub fn new(settings: &Settings) -> Result<Self> {
+        let host = settings
+            .smtp_host
+            .as_deref()
+            .ok_or_else(|| eyre!("smtp_host is required for the smtp mail transport"))?;
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+            .wrap_err("Failed to set up the smtp transport")?
+            .port(settings.smtp_port);
+        if let (Some(username), Some(password)) = (&settings.smtp_username, &settings.smtp_password)
+        {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Self {
+            from: settings
+                .mail_from
+                .parse()
+                .wrap_err("Invalid mail_from address")?,
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl MailSender for SmtpSender {
+    async fn send(&self, mail: &Mail) -> Result<()> {
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(mail.to.parse().wrap_err("Invalid recipient address")?)
+            .subject(&mail.subject)
+            .body(mail.body.clone())?;
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Build the sender of the transport set up in the settings.
+pub fn build_sender(settings: &Settings) -> Result<Arc<dyn MailSender>> {
+    Ok(match settings.mail_transport {
+        MailTransport::Log => Arc::new(LogSender),
+        MailTransport::File => Arc::new(FileSender {
+            path: settings.mail_file.clone(),
+        }),
+        MailTransport::Smtp => Arc::new(SmtpSender::new(settings)?),
+    })
+}
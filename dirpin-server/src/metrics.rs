@@ -0,0 +1,128 @@
+use crate::models::ServerStats;
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The metrics of one server in the Prometheus format. Every router has its own registry so that
+/// the servers of the tests don't share the counts.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    sync_payload: HistogramVec,
+    rejected_records: IntCounter,
+    records: IntGaugeVec,
+    sessions: IntGauge,
+    db_size: IntGauge,
+    /// When the stored state gauges were last read from the database
+    stats_read_at: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("dirpin".into()), None)?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of the handled requests"),
+            &["method", "route", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time to handle the requests",
+            ),
+            &["method", "route"],
+        )?;
+        let sync_payload = HistogramVec::new(
+            HistogramOpts::new(
+                "sync_payload_bytes",
+                "Size of the uploaded and downloaded records",
+            )
+            .buckets(exponential_buckets(256.0, 4.0, 9)?),
+            &["direction"],
+        )?;
+        let rejected_records = IntCounter::new(
+            "sync_rejected_records_total",
+            "Number of the uploaded records rejected for a conflict with the server copy",
+        )?;
+        let records = IntGaugeVec::new(
+            Opts::new("records", "Number of the stored records"),
+            &["kind", "state"],
+        )?;
+        let sessions = IntGauge::new("active_sessions", "Number of the unexpired sessions")?;
+        let db_size = IntGauge::new("database_size_bytes", "Size of the database")?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(sync_payload.clone()))?;
+        registry.register(Box::new(rejected_records.clone()))?;
+        registry.register(Box::new(records.clone()))?;
+        registry.register(Box::new(sessions.clone()))?;
+        registry.register(Box::new(db_size.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            sync_payload,
+            rejected_records,
+            records,
+            sessions,
+            db_size,
+            stats_read_at: Mutex::new(None),
+        })
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, route])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    /// Record the size of a sync body. The direction is "upload" or "download".
+    pub fn observe_sync_payload(&self, direction: &str, bytes: u64) {
+        self.sync_payload
+            .with_label_values(&[direction])
+            .observe(bytes as f64);
+    }
+
+    pub fn reject_records(&self, count: usize) {
+        self.rejected_records.inc_by(count as u64);
+    }
+
+    /// Whether the stored state gauges were read within the max age. Otherwise the caller is
+    /// expected to read them again with `set_stats`.
+    pub fn stats_fresh(&self, max_age: Duration) -> bool {
+        let read_at = self.stats_read_at.lock().unwrap();
+        read_at.is_some_and(|x| x.elapsed() < max_age)
+    }
+
+    /// Replace the stored state gauges with the current numbers of the database.
+    pub fn set_stats(&self, stats: &ServerStats) {
+        *self.stats_read_at.lock().unwrap() = Some(Instant::now());
+        self.records.reset();
+        for count in &stats.records {
+            self.records
+                .with_label_values(&[&count.kind, "live"])
+                .set(count.live);
+            self.records
+                .with_label_values(&[&count.kind, "deleted"])
+                .set(count.deleted);
+        }
+        self.sessions.set(stats.sessions);
+        self.db_size.set(stats.db_size);
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn encode(&self) -> prometheus::Result<String> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+}
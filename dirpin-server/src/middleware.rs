@@ -0,0 +1,133 @@
+use crate::error::ServerError;
+use crate::router::AppState;
+use axum::body::HttpBody;
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::header::CONTENT_LENGTH;
+use axum::middleware::Next;
+use axum::response::Response;
+use dirpin_common::api::{Compatibility, API_VERSION_HEADER};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Above this many tracked addresses the expired windows are dropped.
+const RATE_LIMIT_PRUNE_SIZE: usize = 10_000;
+
+/// Reject the clients that speak a sync protocol version outside of the configured range.
+/// Clients without the version header predate the negotiation and count as version 0.
+pub async fn check_client_version(
+    state: State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    let client = match req.headers().get(API_VERSION_HEADER) {
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(|x| x.parse::<u32>().ok())
+            .ok_or(ServerError::BadRequest("invalid api version header"))?,
+        None => 0,
+    };
+
+    let min = state.settings.min_client_version;
+    let max = state.settings.max_client_version;
+
+    match Compatibility::check(client, min, max) {
+        Compatibility::Compatible => Ok(next.run(req).await),
+        _ => Err(ServerError::VersionMismatch { client, min, max }),
+    }
+}
+
+/// Counts the requests of every address in fixed windows.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<IpAddr, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Count the request of the address. Returns false when the address is over the limit.
+    pub fn check(&self, addr: IpAddr) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > RATE_LIMIT_PRUNE_SIZE {
+            windows.retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let (start, count) = windows.entry(addr).or_insert((now, 0));
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+/// Limit the requests per client address to the auth endpoints. The requests without a known
+/// peer address are let through.
+pub async fn limit_auth_rate(
+    state: State<AppState>,
+    req: Request,
+    next: Next,
+) -> Result<Response, ServerError> {
+    let addr = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    match addr {
+        Some(addr) if !state.auth_limiter.check(addr) => Err(ServerError::TooManyRequests),
+        _ => Ok(next.run(req).await),
+    }
+}
+
+/// Count the requests and their latency per route. The sync routes also record the size of the
+/// uploaded and downloaded records.
+pub async fn track_metrics(state: State<AppState>, req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    // The route instead of the path keeps the labels bounded. Unknown paths share one label.
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |x| x.as_str())
+        .to_string();
+
+    if matches!(route.as_str(), "/entries" | "/entries/rotate") {
+        let length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok());
+        if let Some(length) = length {
+            state.metrics.observe_sync_payload("upload", length);
+        }
+    }
+
+    let res = next.run(req).await;
+
+    if matches!(route.as_str(), "/sync" | "/sync/records") && res.status().is_success() {
+        if let Some(length) = res.body().size_hint().exact() {
+            state.metrics.observe_sync_payload("download", length);
+        }
+    }
+    state
+        .metrics
+        .observe_request(&method, &route, res.status().as_u16(), start.elapsed());
+
+    res
+}
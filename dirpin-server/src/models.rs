@@ -11,6 +11,7 @@ pub struct NewEntry {
     pub kind: String,
     pub updated_at: OffsetDateTime,
     pub deleted_at: Option<OffsetDateTime>,
+    pub key_id: Option<String>,
 }
 
 #[derive(Debug)]
@@ -63,6 +64,18 @@ pub struct Entry {
     pub updated_at: OffsetDateTime,
     /// Host: deleted_at of the entry to conflict detect uploads
     pub deleted_at: Option<OffsetDateTime>,
+    /// Host: id of the key the data is encrypted with
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug)]
+/// The sync properties of a remote entry without the data
+pub struct EntryDigest {
+    pub client_id: String,
+    pub kind: String,
+    pub version: u32,
+    pub updated_at: OffsetDateTime,
+    pub deleted_at: Option<OffsetDateTime>,
 }
 
 #[derive(Debug)]
@@ -71,6 +84,9 @@ pub struct NewUser {
     pub username: String,
     pub email: String,
     pub password: String,
+    pub key_salt: Option<String>,
+    /// The invite code the user is registered with. It is used up with the registration.
+    pub invite: Option<String>,
 }
 
 #[derive(Debug)]
@@ -82,6 +98,33 @@ pub struct User {
     pub password: String,
     pub verified_at: Option<OffsetDateTime>,
     pub created_at: OffsetDateTime,
+    /// Id of the encryption key all the records of the user are encrypted with
+    pub key_id: Option<String>,
+    /// Salt of the passphrase the encryption key is derived from. None for a random key.
+    pub key_salt: Option<String>,
+    /// Known plaintext encrypted with the encryption key to verify the key of the hosts
+    pub key_check: Option<String>,
+    /// Set by the admin. The disabled users can't log in or use their sessions.
+    pub disabled_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug)]
+/// Number of the records of a kind
+pub struct RecordCount {
+    pub kind: String,
+    pub live: i64,
+    /// The tombstones not purged yet
+    pub deleted: i64,
+}
+
+#[derive(Debug)]
+pub struct ServerStats {
+    pub users: i64,
+    pub disabled_users: i64,
+    pub sessions: i64,
+    pub records: Vec<RecordCount>,
+    /// Size of the database in bytes
+    pub db_size: i64,
 }
 
 #[derive(Debug)]
@@ -113,4 +156,82 @@ pub struct Session {
     pub token: String,
     pub host_id: Option<String>,
     pub expires_at: OffsetDateTime,
+    /// Id of the encryption key the host last uploaded with
+    pub key_id: Option<String>,
+    /// None for the sessions from before it was tracked
+    pub created_at: Option<OffsetDateTime>,
+    /// Last authenticated request of the host
+    pub last_used_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug)]
+/// Number of records of a user encrypted with a key
+pub struct KeyRecordCount {
+    pub key_id: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Debug)]
+/// Code to enrol a new host created by an already logged in host
+pub struct NewLinkCode {
+    pub user_id: u32,
+    pub code: String,
+    /// The account key encrypted on the host. The server never sees the secret to decrypt it.
+    pub data: String,
+    pub expires_at: OffsetDateTime,
+}
+
+#[derive(Debug)]
+pub struct LinkCode {
+    pub id: u32,
+    pub user_id: u32,
+    pub code: String,
+    pub data: String,
+    pub expires_at: OffsetDateTime,
+    pub created_at: OffsetDateTime,
+}
+
+#[derive(Debug, Clone, Copy)]
+/// What a mailed token lets the user do
+pub enum VerificationKind {
+    /// Verify the email address of the account
+    Verify,
+    /// Set a new password without the old one
+    Reset,
+}
+
+impl std::fmt::Display for VerificationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = match self {
+            VerificationKind::Verify => "verify",
+            VerificationKind::Reset => "reset",
+        };
+        write!(f, "{}", value)
+    }
+}
+
+#[derive(Debug)]
+pub struct NewVerificationToken {
+    pub user_id: u32,
+    pub kind: VerificationKind,
+    pub token: String,
+    pub expires_at: OffsetDateTime,
+}
+
+#[derive(Debug)]
+/// Code created by the admin to let one new user register on a closed server
+pub struct NewInviteCode {
+    pub code: String,
+    pub expires_at: Option<OffsetDateTime>,
+}
+
+#[derive(Debug)]
+pub struct InviteCode {
+    pub id: u32,
+    pub code: String,
+    pub expires_at: Option<OffsetDateTime>,
+    pub created_at: OffsetDateTime,
+    pub used_at: Option<OffsetDateTime>,
+    /// Username of the user registered with the code
+    pub used_by: Option<String>,
 }
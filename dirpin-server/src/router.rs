@@ -1,32 +1,119 @@
 use super::handlers;
 use crate::database::Database;
+use crate::mail::MailSender;
+use crate::metrics::Metrics;
+use crate::middleware::{check_client_version, limit_auth_rate, track_metrics, RateLimiter};
+use crate::settings::Settings;
+use axum::extract::Request;
 use axum::http;
+use axum::middleware::from_fn_with_state;
 use axum::response::IntoResponse;
 use axum::routing::{get, post};
 use axum::Router;
-use tower_http::trace::TraceLayer;
+use std::sync::Arc;
+use std::time::Duration;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::trace::{DefaultOnResponse, TraceLayer};
+use tracing::Level;
 
 #[derive(Clone)]
 pub struct AppState {
     pub database: Database,
+    pub settings: Arc<Settings>,
+    pub mail: Arc<dyn MailSender>,
+    /// Rate limit of /register, /login, /link/claim and /password/reset/send per client address
+    pub auth_limiter: Arc<RateLimiter>,
+    pub metrics: Arc<Metrics>,
 }
 
 async fn not_found() -> impl IntoResponse {
     (http::StatusCode::NOT_FOUND, "404 not found")
 }
 
-pub fn router(database: Database) -> Router {
+pub fn router(database: Database, settings: Settings, mail: Arc<dyn MailSender>) -> Router {
+    let auth_limiter = RateLimiter::new(
+        settings.auth_rate_limit,
+        Duration::from_secs(settings.auth_rate_limit_window),
+    );
+    let state = AppState {
+        database,
+        settings: Arc::new(settings),
+        mail,
+        auth_limiter: Arc::new(auth_limiter),
+        metrics: Arc::new(Metrics::new().expect("failed to register the metrics")),
+    };
+
+    let auth_routes = Router::new()
+        .route("/register", post(handlers::user::register))
+        .route("/login", post(handlers::user::login))
+        .route("/link/claim", post(handlers::link::claim))
+        .route(
+            "/password/reset/send",
+            post(handlers::verification::send_password_reset),
+        )
+        .route_layer(from_fn_with_state(state.clone(), limit_auth_rate));
+
+    // The index stays reachable for every client so that it can find out about the supported
+    // versions.
     let routes = Router::new()
-        .route("/", get(handlers::index))
         .route("/sync", get(handlers::entry::sync))
         .route("/sync/status", get(handlers::entry::status))
+        .route("/sync/digest", get(handlers::entry::digest))
+        .route("/sync/records", post(handlers::entry::fetch))
         .route("/entries", post(handlers::entry::add))
-        .route("/register", post(handlers::user::register))
-        .route("/login", post(handlers::user::login))
-        .route("/logout", get(handlers::user::logout));
+        .route("/entries/rotate", post(handlers::key::rotate))
+        .route("/keys", get(handlers::key::status))
+        .route("/keys/check", post(handlers::key::set_check))
+        .merge(auth_routes)
+        .route("/logout", get(handlers::user::logout))
+        .route("/account/password", post(handlers::user::change_password))
+        .route("/account/delete", post(handlers::user::delete))
+        .route("/verify", post(handlers::verification::verify))
+        .route(
+            "/verify/send",
+            post(handlers::verification::send_verification),
+        )
+        .route(
+            "/password/reset",
+            post(handlers::verification::reset_password),
+        )
+        .route("/sessions", get(handlers::session::list))
+        .route("/sessions/revoke", post(handlers::session::revoke))
+        .route("/link", post(handlers::link::create))
+        .route_layer(from_fn_with_state(state.clone(), check_client_version));
+
+    let mut router = Router::new()
+        .route("/", get(handlers::index))
+        .route("/healthz", get(handlers::health::healthz))
+        .route("/readyz", get(handlers::health::readyz));
+    if state.settings.metrics {
+        router = router.route("/metrics", get(handlers::metrics::metrics));
+    }
 
-    routes
+    // Every request gets an id, unless the proxy in front already set one. It is in the logs of
+    // the request and the response headers.
+    router
+        .merge(routes)
         .fallback(not_found)
-        .with_state(AppState { database })
-        .layer(TraceLayer::new_for_http())
+        .with_state(state.clone())
+        .layer(from_fn_with_state(state, track_metrics))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(|req: &Request| {
+                    let request_id = req
+                        .headers()
+                        .get("x-request-id")
+                        .and_then(|x| x.to_str().ok())
+                        .unwrap_or_default();
+                    tracing::info_span!(
+                        "request",
+                        method = %req.method(),
+                        uri = %req.uri(),
+                        request_id,
+                    )
+                })
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
 }
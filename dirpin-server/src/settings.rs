@@ -1,5 +1,6 @@
 use config::builder::DefaultState;
 use config::{Config, ConfigBuilder, Environment, File as ConfigFile, FileFormat};
+use dirpin_common::api::API_VERSION;
 use dirpin_common::utils::{config_dir, data_dir};
 use eyre::{eyre, Error, Result};
 use std::fs::{create_dir_all, File};
@@ -8,14 +9,78 @@ use std::path::PathBuf;
 
 const EXAMPLE_CONFIG: &str = include_str!("../server.toml");
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Settings {
     pub host: String,
     pub port: u16,
+    /// The PEM certificate chain to serve https with. Needs `tls_key` too
+    pub tls_cert: Option<String>,
+    /// The PEM private key of `tls_cert`
+    pub tls_key: Option<String>,
     pub db_path: String,
+    /// The uri of a PostgreSQL database, e.g. "postgres://dirpin@localhost/dirpin". Takes the place
+    /// of the SQLite database at `db_path` when it is set
+    pub db_uri: Option<String>,
+    /// The oldest client sync protocol version that is allowed to talk to the server
+    pub min_client_version: u32,
+    /// The newest client sync protocol version that is allowed to talk to the server
+    pub max_client_version: u32,
+    /// How long a code to link a new host stays valid in seconds
+    pub link_code_ttl: u64,
+    /// How often to purge the tombstones all the hosts have synced past in seconds. Zero disables it
+    pub tombstone_gc_interval: u64,
+    /// Let anyone register. Otherwise a new user needs an invite code from `dirpin server invite`
+    pub open_registration: bool,
+    /// How many requests to /register, /login, /link/claim and /password/reset/send one address
+    /// can make within the window. Zero disables the limit
+    pub auth_rate_limit: u32,
+    /// The window of the auth rate limit in seconds
+    pub auth_rate_limit_window: u64,
+    /// Only let the users with a verified email address use their sessions
+    pub require_verification: bool,
+    /// How the verification and password reset mails are delivered
+    pub mail_transport: MailTransport,
+    /// The sender address of the mails
+    pub mail_from: String,
+    /// The file the mails are appended to with the "file" transport
+    pub mail_file: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// Serve the Prometheus metrics at /metrics. The endpoint has no authentication, so the proxy
+    /// in front has to keep it private
+    pub metrics: bool,
+    /// The format of the server logs
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    /// Write the mails to the server log
+    Log,
+    /// Append the mails to `mail_file`
+    File,
+    /// Send the mails through the `smtp_host` server
+    Smtp,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One json object per line with the fields of the request, e.g. the request id
+    Json,
 }
 
 impl Settings {
+    /// The database the server stores the data in. See `Database::new`.
+    pub fn database_uri(&self) -> &str {
+        self.db_uri.as_deref().unwrap_or(&self.db_path)
+    }
+
     pub fn new() -> Result<Self, Error> {
         let mut config_builder = Self::build_default()?;
 
@@ -44,11 +109,26 @@ impl Settings {
     pub fn build_default() -> Result<ConfigBuilder<DefaultState>> {
         let data_dir = std::env::var("DIRPIN_DATA_DIR").map_or(data_dir(), PathBuf::from);
         let db_path = data_dir.join("server.db");
+        let mail_file = data_dir.join("mail.log");
 
         Ok(Config::builder()
             .set_default("host", "127.0.0.1")?
             .set_default("port", 8090)?
             .set_default("db_path", db_path.to_str())?
+            .set_default("min_client_version", API_VERSION)?
+            .set_default("max_client_version", API_VERSION)?
+            .set_default("link_code_ttl", 600)?
+            .set_default("tombstone_gc_interval", 3600)?
+            .set_default("open_registration", true)?
+            .set_default("auth_rate_limit", 10)?
+            .set_default("auth_rate_limit_window", 60)?
+            .set_default("require_verification", false)?
+            .set_default("mail_transport", "log")?
+            .set_default("mail_from", "dirpin@localhost")?
+            .set_default("mail_file", mail_file.to_str())?
+            .set_default("smtp_port", 587)?
+            .set_default("metrics", false)?
+            .set_default("log_format", "text")?
             .add_source(
                 Environment::with_prefix("dirpin")
                     .prefix_separator("_")
@@ -0,0 +1,91 @@
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
+use eyre::{eyre, Context, Result};
+use rustls::crypto::ring;
+use rustls::ServerConfig;
+use std::future::Future;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// How long the open connections get to finish on shutdown.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Read the PEM certificate chain and private key into the rustls config of the server.
+pub fn load_server_config(cert_path: &str, key_path: &str) -> Result<ServerConfig> {
+    let cert = fs_err::File::open(cert_path)?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert))
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err_with(|| format!("Failed to read the certificates in {cert_path}"))?;
+    if certs.is_empty() {
+        return Err(eyre!("No certificate found in {cert_path}"));
+    }
+
+    let key = fs_err::File::open(key_path)?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key))
+        .wrap_err_with(|| format!("Failed to read the private key in {key_path}"))?
+        .ok_or_else(|| eyre!("No private key found in {key_path}"))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .wrap_err("The private key doesn't match the certificate")?;
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(config)
+}
+
+pub fn load_config(cert_path: &str, key_path: &str) -> Result<RustlsConfig> {
+    Ok(RustlsConfig::from_config(Arc::new(load_server_config(
+        cert_path, key_path,
+    )?)))
+}
+
+/// Load the certificate and key again on every SIGHUP, e.g. after a renewal. A broken pair is
+/// logged and the server keeps the previous one. The signal is registered before this returns.
+#[cfg(target_family = "unix")]
+pub fn reload_on_hangup(config: RustlsConfig, cert_path: String, key_path: String) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = signal(SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match load_server_config(&cert_path, &key_path) {
+                Ok(server_config) => {
+                    config.reload_from_config(Arc::new(server_config));
+                    tracing::info!("Reloaded the tls certificate");
+                }
+                Err(err) => tracing::error!("Failed to reload the tls certificate {err:#}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Serve https on the listener until the shutdown future completes.
+pub async fn serve(
+    listener: TcpListener,
+    app: IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    config: RustlsConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let handle = Handle::new();
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        shutdown.await;
+        shutdown_handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+    });
+
+    axum_server::from_tcp_rustls(listener.into_std()?, config)
+        .handle(handle)
+        .serve(app)
+        .await?;
+
+    Ok(())
+}
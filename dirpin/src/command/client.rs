@@ -3,23 +3,40 @@ use eyre::Result;
 
 mod account;
 mod add;
+mod doctor;
+mod gc;
+mod history;
 mod info;
 mod key;
 mod list;
+mod restore;
 mod search;
 mod status;
 mod sync;
+mod undo;
+mod workspace;
 
 #[derive(Parser, Debug)]
 #[clap(infer_subcommands = true)]
 pub enum Cmd {
     Info,
-    Key,
-    Doctor,
+    Key(key::Cmd),
+    Doctor(doctor::Cmd),
     Add(add::Cmd),
     List(list::Cmd),
     Sync(sync::Cmd),
+    /// Purge the old tombstones of deleted records
+    Gc(gc::Cmd),
+    /// Show the prior states of an entry
+    History(history::Cmd),
+    /// Revert the last change made on this host
+    Undo,
+    /// Put an entry back into one of its prior states
+    Restore(restore::Cmd),
     Search(search::Cmd),
+    /// Manage the workspaces the entries are grouped in
+    #[command(subcommand)]
+    Workspace(workspace::Cmd),
     #[command(subcommand)]
     Account(account::Cmd),
     Status,
@@ -28,19 +45,28 @@ pub enum Cmd {
 impl Cmd {
     #[tokio::main]
     pub async fn run(self) -> Result<()> {
+        if let Self::Doctor(cmd) = self {
+            return cmd.run().await;
+        }
+
         let settings = dirpin_client::settings::Settings::new()?;
         let db = dirpin_client::database::Database::new(&settings.db_path).await?;
 
         match self {
             Self::Info => info::run(&settings),
             Self::Status => status::run(&settings).await?,
-            Self::Key => key::run(&settings)?,
+            Self::Key(cmd) => cmd.run(&settings, &db).await?,
             Self::Add(cmd) => cmd.run(&settings, &db).await?,
             Self::List(cmd) => cmd.run(&settings, &db).await?,
             Self::Sync(cmd) => cmd.run(&settings, &db).await?,
+            Self::Gc(cmd) => cmd.run(settings, &db).await?,
+            Self::History(cmd) => cmd.run(&db).await?,
+            Self::Undo => undo::run(&db).await?,
+            Self::Restore(cmd) => cmd.run(&db).await?,
             Self::Search(cmd) => cmd.run(&settings, &db).await?,
+            Self::Workspace(cmd) => cmd.run(&db).await?,
             Self::Account(cmd) => cmd.run(&settings).await?,
-            Self::Doctor => todo!("Show the debug info about the program and what the issue is"),
+            Self::Doctor(_) => unreachable!(),
         };
 
         Ok(())
@@ -2,9 +2,15 @@ use clap::Parser;
 use dirpin_client::settings::Settings;
 use eyre::Result;
 
+mod delete;
+mod link;
 mod login;
 mod logout;
+mod password;
 mod register;
+mod reset_password;
+mod sessions;
+mod verify;
 
 #[derive(Parser, Debug)]
 #[clap(infer_subcommands = true)]
@@ -14,9 +20,19 @@ pub enum Cmd {
     /// Register a remote account for syncing
     Register(register::Cmd),
     Logout,
-    Delete,
-    /// Verification step of the new account on the server
-    Verify,
+    /// Create a one time token to log in a new host without typing the credentials and the key
+    Link,
+    /// Delete the account and all its data from the server
+    Delete(delete::Cmd),
+    /// Change the password of the account. The other hosts are logged out.
+    Password,
+    /// Verify the email address of the account with the mailed code
+    Verify(verify::Cmd),
+    /// Set a new password with a code mailed to the address of the account
+    ResetPassword(reset_password::Cmd),
+    /// See and log out the hosts of the account
+    #[command(subcommand)]
+    Sessions(sessions::Cmd),
 }
 
 impl Cmd {
@@ -25,8 +41,12 @@ impl Cmd {
             Self::Register(cmd) => cmd.run(settings).await?,
             Self::Login(cmd) => cmd.run(settings).await?,
             Self::Logout => logout::run(settings).await?,
-            Self::Delete => todo!("Delete"),
-            Self::Verify => todo!("Verify"),
+            Self::Link => link::run(settings).await?,
+            Self::Delete(cmd) => cmd.run(settings).await?,
+            Self::Password => password::run(settings).await?,
+            Self::Verify(cmd) => cmd.run(settings).await?,
+            Self::ResetPassword(cmd) => cmd.run(settings).await?,
+            Self::Sessions(cmd) => cmd.run(settings).await?,
         }
 
         Ok(())
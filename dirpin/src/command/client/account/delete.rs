@@ -0,0 +1,54 @@
+use clap::Parser;
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::encryption;
+use dirpin_client::settings::Settings;
+use dirpin_client::utils::read_input_hidden;
+use dirpin_common::api::DeleteAccountRequest;
+use eyre::{eyre, Result};
+use std::path::Path;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Also remove the local database, the keys and the session of this host
+    #[arg(long)]
+    wipe: bool,
+}
+
+fn remove_if_exists(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    if path.exists() {
+        fs_err::remove_file(path)?;
+    }
+    Ok(())
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+        let client = AuthClient::with_options(
+            &settings.server_address,
+            &session,
+            &ClientOptions::from(settings),
+        )?;
+
+        println!("This removes the account and all its synced data from the server.");
+        let password = read_input_hidden("password");
+        client
+            .delete_account(&DeleteAccountRequest { password })
+            .await?;
+
+        remove_if_exists(&settings.session_path)?;
+        if self.wipe {
+            remove_if_exists(&settings.key_path)?;
+            remove_if_exists(encryption::previous_keys_path(settings))?;
+            for suffix in ["", "-wal", "-shm"] {
+                remove_if_exists(format!("{}{suffix}", settings.db_path))?;
+            }
+            println!("Your account and the local data are deleted.");
+        } else {
+            println!("Your account is deleted. The local data stays on this host.");
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,13 @@
+use dirpin_client::settings::Settings;
+use eyre::Result;
+use time::OffsetDateTime;
+
+pub async fn run(settings: &Settings) -> Result<()> {
+    let (token, expires_at) = dirpin_client::link::create_link(settings).await?;
+    let minutes = ((expires_at - OffsetDateTime::now_utc()).whole_seconds() + 59) / 60;
+
+    println!("Run this on the new host within {minutes} minutes. The token works only once.");
+    println!("dirpin account login --token {token}");
+
+    Ok(())
+}
@@ -1,5 +1,7 @@
 use clap::Parser;
-use dirpin_client::encryption;
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::encryption::{self, Key};
+use dirpin_client::link::LinkToken;
 use dirpin_client::settings::Settings;
 use dirpin_client::utils::{read_input, read_input_hidden};
 use eyre::{bail, Context, Result};
@@ -13,14 +15,20 @@ pub struct Cmd {
     pub username: Option<String>,
     #[arg(long, short)]
     pub password: Option<String>,
-    /// The encryption key for computer to decrypt remote data
+    /// The encryption key for computer to decrypt remote data. Either the base64 form or the
+    /// mnemonic from `dirpin key --mnemonic`
     #[arg(long, short)]
     pub key: Option<String>,
+    /// The passphrase the encryption key is derived from, for accounts registered with one
+    #[arg(long)]
+    pub passphrase: Option<String>,
+    /// The token from `dirpin account link` on an already logged in host. Replaces the
+    /// username, password and key.
+    #[arg(long, short, conflicts_with_all = ["username", "password", "key", "passphrase"])]
+    pub token: Option<String>,
 }
 
 impl Cmd {
-    // TODO: make this into an encoded value that you can pass that will hold the username,
-    // password and the key for easier login
     pub async fn run(self, settings: &Settings) -> Result<()> {
         let session_path = PathBuf::from(&settings.session_path);
 
@@ -29,19 +37,104 @@ impl Cmd {
             return Ok(());
         }
 
-        // Try to get the username and password and the key
-        let username = self.username.unwrap_or_else(|| read_input("username"));
-        let password = self
-            .password
-            .unwrap_or_else(|| read_input_hidden("password"));
-        // NOTE: do we need to hide this key in the temrinal?
-        // Maybe it might be a good idea to create a corssterm interactive one liner instead?
-        let key = self
-            .key
-            .unwrap_or_else(|| read_input("key [to use exiting key leave empty]"));
-        let key_path = PathBuf::from(&settings.key_path);
-
-        if key.is_empty() {
+        let (session, provided_key) = if let Some(token) = self.token {
+            // The token from `dirpin account link` holds everything to enrol this host.
+            let token = token.parse::<LinkToken>()?;
+            let (session, key) = dirpin_client::link::claim_link(
+                &settings.server_address,
+                &token,
+                Settings::host_id().as_ref(),
+                &ClientOptions::from(settings),
+            )
+            .await?;
+            (session, Some(key))
+        } else {
+            // Try to get the username and password and the key
+            let username = self.username.unwrap_or_else(|| read_input("username"));
+            let password = self
+                .password
+                .unwrap_or_else(|| read_input_hidden("password"));
+
+            // Get the session. The user must have valid username and password.
+            let res = dirpin_client::api_client::login(
+                settings.server_address.as_str(),
+                username.as_str(),
+                password.as_str(),
+                Settings::host_id().as_ref(),
+                &ClientOptions::from(settings),
+            )
+            .await?;
+
+            // The account with a passphrase derived key stores the salt on the server. The key is
+            // then derived from the passphrase instead of copying it from another host.
+            let provided_key = match (self.key, &res.key_salt) {
+                (Some(key), _) => Some(encryption::parse_key(&key)?),
+                (None, Some(salt)) => {
+                    let passphrase = self
+                        .passphrase
+                        .unwrap_or_else(|| read_input_hidden("passphrase"));
+                    Some(encryption::derive_key(&passphrase, salt)?)
+                }
+                (None, None) => {
+                    // NOTE: do we need to hide this key in the temrinal?
+                    // Maybe it might be a good idea to create a corssterm interactive one liner instead?
+                    let key = read_input("key [to use exiting key leave empty]");
+                    if key.is_empty() {
+                        None
+                    } else {
+                        Some(encryption::parse_key(&key)?)
+                    }
+                }
+            };
+
+            (res.session, provided_key)
+        };
+
+        let client = AuthClient::with_options(
+            &settings.server_address,
+            &session,
+            &ClientOptions::from(settings),
+        )?;
+        if let Err(err) = store_login(settings, &client, &session, provided_key).await {
+            // Without the session file the server session is of no use to anyone.
+            if let Err(logout_err) = client.logout().await {
+                eprintln!("Failed to log out the session on the server: {logout_err}");
+            }
+            return Err(err);
+        }
+
+        println!("Logged in!");
+
+        Ok(())
+    }
+}
+
+/// Check the key against the account and keep the key and the session on this host.
+async fn store_login(
+    settings: &Settings,
+    client: &AuthClient<'_>,
+    session: &str,
+    provided_key: Option<Key>,
+) -> Result<()> {
+    let key_path = PathBuf::from(&settings.key_path);
+
+    // Make sure the key decrypts the key check of the account before anything is written.
+    // The account without a key check gets one from the first host that syncs.
+    let key_to_check = match &provided_key {
+        Some(key) => Some(*key),
+        None if key_path.exists() => {
+            Some(encryption::read_key(&key_path).wrap_err("Failed to read local key from file")?)
+        }
+        None => None,
+    };
+    let key_check = client.status().await?.key_check;
+    if let (Some(key), Some(check)) = (key_to_check, &key_check) {
+        encryption::verify_key_check(check, &key)
+            .wrap_err("The key can not decrypt the data of this account")?;
+    }
+
+    match provided_key {
+        None => {
             // If key is empty, check if it exists and is valid in a key file. If not, ask the user if
             // to create a new key. This is incase the user just accidentally presses enter while
             // logging in on a new computer.
@@ -50,54 +143,50 @@ impl Cmd {
                     Ok(_) => {}
                     Err(_) => bail!("Failed to read local key from file"),
                 }
+            } else if key_check.is_some() {
+                // A new key could not decrypt what the other hosts already synced.
+                bail!("This account already has data encrypted with a key. Log in with the key from `dirpin key` on another host, e.g. `dirpin account login --key <key>`.");
             } else {
                 println!("You have not provided a key and we could not find file key.");
                 println!("Do you want to create a new key?");
-                todo!("create new key");
-            }
-        } else {
-            // The user provided a key. However, we need to make sure it's not trying to overwrite
-            // the existing key. So we try to load the key file and if we can't find it, then
-            // save this key. This happens for the case when the user is looging in to a new
-            // compter with an existing account.
-            if !key_path.exists() {
-                // You have provided a key and the key path does not exist
-                match encryption::decode_key(key.clone()) {
-                    Ok(_) => fs_err::write(key_path, key).wrap_err("Failed to write key.")?,
-                    Err(_) => bail!("Provided key seems to be invalid"),
-                }
-            } else {
-                // Make sure to compare the provided and the local key from the key file.
-                // We need to make sure that we ask the user if they are sure to overwrite
-                // the key. As this would require a re-encryption of the data remotely.
-                let local_key: [u8; 32] = encryption::read_key(&key_path)?.into();
-                let provided_key: [u8; 32] = encryption::decode_key(key)?.into();
-
-                if local_key != provided_key {
-                    println!("You already have a key locally and you provided a different key.");
-                    println!("Do you want to use the new key and re-encrypt the data?");
-                    todo!("re-encrypt remote data");
+                let answer = read_input("y/N");
+                if !answer.eq_ignore_ascii_case("y") {
+                    bail!("No key to log in with. Pass the key from `dirpin key` on another host with `--key`.");
                 }
+                encryption::create_key(settings)?;
+                println!("Created a new key in {}", settings.key_path);
             }
         }
+        // The user provided a key. However, we need to make sure it's not trying to overwrite
+        // the existing key. So we try to load the key file and if we can't find it, then
+        // save this key. This happens for the case when the user is looging in to a new
+        // compter with an existing account.
+        Some(provided_key) if !key_path.exists() => {
+            fs_err::write(key_path, encryption::encode_key(&provided_key)?)
+                .wrap_err("Failed to write key.")?;
+        }
+        Some(provided_key) => {
+            // Make sure to compare the provided and the local key from the key file.
+            // We need to make sure that we ask the user if they are sure to overwrite
+            // the key. As this would require a re-encryption of the data remotely.
+            let local_key = encryption::read_key(&key_path)?;
 
-        // Get the session
-        // - user must have valid username and password
-        // - how do we make sure that we have correct key? Only on download and decryption
-        // -
-        let res = dirpin_client::api_client::login(
-            settings.server_address.as_str(),
-            username.as_str(),
-            password.as_str(),
-            Settings::host_id().as_ref(),
-        )
-        .await?;
-
-        fs_err::write(settings.session_path.as_str(), res.session.as_bytes())
-            .wrap_err("Failed to create a session file")?;
+            if encryption::key_id(&local_key) != encryption::key_id(&provided_key) {
+                println!("You already have a key locally and you provided a different key.");
+                println!(
+                    "Do you want to use the provided key? The local key is kept as a previous key."
+                );
+                let answer = read_input("y/N");
+                if !answer.eq_ignore_ascii_case("y") {
+                    bail!("Kept the local key. To re-encrypt the remote data with a new key, use `dirpin key rotate` after logging in.");
+                }
+                encryption::use_key(settings, &provided_key)?;
+            }
+        }
+    }
 
-        println!("Logged in!");
+    fs_err::write(settings.session_path.as_str(), session.as_bytes())
+        .wrap_err("Failed to create a session file")?;
 
-        Ok(())
-    }
+    Ok(())
 }
@@ -1,3 +1,4 @@
+use dirpin_client::api_client::{AuthClient, ClientOptions};
 use dirpin_client::settings::Settings;
 use eyre::Result;
 
@@ -9,10 +10,21 @@ pub async fn run(settings: &Settings) -> Result<()> {
         return Ok(());
     }
 
-    // 3. remove session file.
+    // 1. End the session on the server. The host logs out even when the server can't be reached.
+    let session = session.unwrap();
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+    if let Err(err) = client.logout().await {
+        println!("The session could not be ended on the server: {err}");
+    }
+
+    // 2. remove session file.
     fs_err::remove_file(&settings.session_path)?;
 
-    // 4. Notify user that we are logged out.
+    // 3. Notify user that we are logged out.
     println!("You are logged out!");
     Ok(())
 }
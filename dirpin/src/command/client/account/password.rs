@@ -0,0 +1,30 @@
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::settings::Settings;
+use dirpin_client::utils::read_input_hidden;
+use dirpin_common::api::ChangePasswordRequest;
+use eyre::{ensure, eyre, Result};
+
+pub async fn run(settings: &Settings) -> Result<()> {
+    let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+    let client = AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )?;
+
+    let current_password = read_input_hidden("current password");
+    let new_password = read_input_hidden("new password");
+    let confirm = read_input_hidden("new password again");
+    ensure!(new_password == confirm, "Passwords do not match");
+    ensure!(!new_password.is_empty(), "Password can not be empty");
+
+    let revoked = client
+        .change_password(&ChangePasswordRequest {
+            current_password,
+            new_password,
+        })
+        .await?;
+
+    println!("Your password is changed. {revoked} other hosts were logged out.");
+    Ok(())
+}
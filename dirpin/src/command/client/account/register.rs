@@ -1,7 +1,9 @@
 use clap::Parser;
+use dirpin_client::api_client::ClientOptions;
 use dirpin_client::settings::Settings;
-use dirpin_client::utils::{read_input, read_input_hidden};
+use dirpin_client::utils::{read_input, read_input_hidden, read_new_passphrase};
 use dirpin_client::{api_client, encryption};
+use dirpin_common::api::RegisterRequest;
 use eyre::{Context, Result};
 use fs_err;
 use std::path::PathBuf;
@@ -15,6 +17,13 @@ pub struct Cmd {
     email: Option<String>,
     #[arg(short, long)]
     password: Option<String>,
+    /// Derive the encryption key from a passphrase instead of generating a random key. Other
+    /// hosts then log in with the passphrase instead of copying the key.
+    #[arg(long)]
+    passphrase: bool,
+    /// The invite code from the admin of a server with closed registration
+    #[arg(long)]
+    invite: Option<String>,
 }
 
 impl Cmd {
@@ -32,12 +41,26 @@ impl Cmd {
             .password
             .unwrap_or_else(|| read_input_hidden("password"));
 
+        let derived = if self.passphrase {
+            let passphrase = read_new_passphrase()?;
+            let salt = encryption::generate_key_salt();
+            let key = encryption::derive_key(&passphrase, &salt)?;
+            Some((key, salt))
+        } else {
+            None
+        };
+
         let res = api_client::register(
             &settings.server_address,
-            &username,
-            &email,
-            &password,
-            Settings::host_id().as_ref(),
+            &RegisterRequest {
+                username,
+                email: email.clone(),
+                password,
+                host_id: Settings::host_id().to_string(),
+                key_salt: derived.as_ref().map(|(_, salt)| salt.clone()),
+                invite: self.invite,
+            },
+            &ClientOptions::from(settings),
         )
         .await
         .wrap_err("Failed to register user")?;
@@ -46,9 +69,17 @@ impl Cmd {
             .wrap_err("Failed to store session in file")?;
 
         // make sure the "key" is created right after login
-        encryption::load_key(settings)?;
+        match derived {
+            Some((key, _)) => encryption::use_key(settings, &key)?,
+            None => {
+                encryption::load_key(settings)?;
+            }
+        }
 
-        println!("You are registered! Next verify your account.");
+        println!(
+            "You are registered! Verify your account with the code mailed to {email} \
+            using `dirpin account verify`."
+        );
 
         Ok(())
     }
@@ -0,0 +1,59 @@
+use clap::Parser;
+use dirpin_client::api_client::{self, ClientOptions};
+use dirpin_client::settings::Settings;
+use dirpin_client::utils::{read_input, read_input_hidden};
+use eyre::{Context, Result};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    #[arg(long, short)]
+    username: Option<String>,
+    /// The code mailed by the first run of the command. Without it a new code is mailed.
+    #[arg(long, short)]
+    token: Option<String>,
+    #[arg(long, short)]
+    password: Option<String>,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let username = self.username.unwrap_or_else(|| read_input("username"));
+
+        let Some(token) = self.token else {
+            api_client::send_password_reset(
+                &settings.server_address,
+                &username,
+                &ClientOptions::from(settings),
+            )
+            .await?;
+            println!(
+                "If the account exists, a reset code is on the way to its address. \
+                Run the command again with --token to set the new password."
+            );
+            return Ok(());
+        };
+
+        let password = self
+            .password
+            .unwrap_or_else(|| read_input_hidden("new password"));
+        api_client::reset_password(
+            &settings.server_address,
+            &username,
+            &token,
+            &password,
+            &ClientOptions::from(settings),
+        )
+        .await
+        .wrap_err("Failed to reset the password")?;
+
+        // The server logged out all the hosts of the account.
+        let session_path = PathBuf::from(&settings.session_path);
+        if session_path.exists() {
+            fs_err::remove_file(session_path)?;
+        }
+
+        println!("Your password is changed. Log in again on all your hosts.");
+        Ok(())
+    }
+}
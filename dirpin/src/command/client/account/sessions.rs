@@ -0,0 +1,65 @@
+use clap::Subcommand;
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::settings::Settings;
+use dirpin_common::api::RevokeSessionsRequest;
+use eyre::{eyre, Result};
+use time::format_description::well_known::Rfc3339;
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// List the hosts that are logged in to the account
+    List,
+    /// Log out a host from the server
+    Revoke {
+        /// The host to log out, see `dirpin account sessions list`
+        #[arg(required_unless_present = "all_others")]
+        host: Option<String>,
+        /// Log out every host except this one
+        #[arg(long, conflicts_with = "host")]
+        all_others: bool,
+    },
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, settings: &Settings) -> Result<()> {
+        let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+        let client = AuthClient::with_options(
+            &settings.server_address,
+            &session,
+            &ClientOptions::from(settings),
+        )?;
+
+        match self {
+            Self::List => {
+                for el in client.sessions().await?.sessions {
+                    let last_used = match el.last_used_at {
+                        Some(x) => x.format(&Rfc3339)?,
+                        None => "unknown".into(),
+                    };
+                    println!(
+                        "{}{} last used {last_used}, expires {}",
+                        el.host_id,
+                        if el.current { " (this host)" } else { "" },
+                        el.expires_at.format(&Rfc3339)?
+                    );
+                }
+            }
+            Self::Revoke { host, all_others } => {
+                let res = client
+                    .revoke_sessions(&RevokeSessionsRequest {
+                        host_id: host.clone(),
+                        all_others,
+                    })
+                    .await?;
+                println!("Revoked {} sessions", res.revoked);
+
+                if host.is_some_and(|x| x == Settings::host_id().as_ref()) {
+                    fs_err::remove_file(&settings.session_path)?;
+                    println!("This host is logged out.");
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
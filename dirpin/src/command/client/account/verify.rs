@@ -0,0 +1,39 @@
+use clap::Parser;
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::settings::Settings;
+use dirpin_client::utils::read_input;
+use eyre::{eyre, Result};
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// The code mailed to the address of the account
+    token: Option<String>,
+    /// Mail a new code instead
+    #[arg(long, conflicts_with = "token")]
+    resend: bool,
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings) -> Result<()> {
+        let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+        let client = AuthClient::with_options(
+            &settings.server_address,
+            &session,
+            &ClientOptions::from(settings),
+        )?;
+
+        if self.resend {
+            client.send_verification().await?;
+            println!("A new verification code is on the way.");
+            return Ok(());
+        }
+
+        let token = self
+            .token
+            .unwrap_or_else(|| read_input("verification code"));
+        client.verify(&token).await?;
+        println!("Your account is verified!");
+
+        Ok(())
+    }
+}
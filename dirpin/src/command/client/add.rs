@@ -2,12 +2,13 @@ use clap::Parser;
 use dirpin_client::database::Database;
 use dirpin_client::domain::context::Context;
 use dirpin_client::domain::entry::{Entry, EntryKind};
-use dirpin_client::domain::workspace::Workspace;
+use dirpin_client::domain::workspace::{Workspace, WorkspacePath};
 use dirpin_client::settings::Settings;
 use dirpin_common::utils;
 use eyre::{bail, Context as Ctx, Result};
 use std::path::PathBuf;
 use std::str::FromStr;
+use time::OffsetDateTime;
 
 #[derive(Parser, Debug)]
 pub struct Cmd {
@@ -53,8 +54,21 @@ impl Cmd {
         } else {
             let context = Context::cwd();
             let mut workspace = db.workspace(None, None, &context).await?;
-            if context.git.is_some() && workspace.is_none() {
-                let ws = Workspace::new(context.workspace_name(), &context);
+            if let (Some(git), None) = (&context.git, &workspace) {
+                // Another host might have synced the workspace of this repository already.
+                let ws = match db.workspace_by_git(git).await? {
+                    Some(mut ws) => {
+                        let path =
+                            WorkspacePath::new(context.host_id.clone(), context.path.clone());
+                        if !ws.paths.contains(&path) {
+                            ws.paths.push(path);
+                            ws.version.bump();
+                            ws.updated_at = OffsetDateTime::now_utc();
+                        }
+                        ws
+                    }
+                    None => Workspace::new(context.workspace_name(), &context),
+                };
                 db.save_workspace(&ws).await?;
                 workspace = Some(ws);
             }
@@ -68,8 +82,13 @@ impl Cmd {
             context.host_id,
         );
 
-        if let Some(kind) = self.kind.map(|x| EntryKind::from_str(&x).unwrap()) {
-            entry = entry.kind(kind);
+        if let Some(kind) = self.kind {
+            match EntryKind::from_str(&kind) {
+                Ok(EntryKind::Other(_)) | Err(_) => {
+                    bail!("Unknown type '{kind}', use one of note, cmd or todo")
+                }
+                Ok(kind) => entry = entry.kind(kind),
+            }
         }
 
         db.save(&entry).await?;
@@ -0,0 +1,69 @@
+use clap::Parser;
+use dirpin_client::database::Database;
+use dirpin_client::doctor::{self, Severity};
+use dirpin_client::settings::Settings;
+use eyre::Result;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Print the report as json
+    #[arg(long)]
+    json: bool,
+    /// Parse the quarantined remote records again, e.g. after switching to the right key
+    #[arg(long, conflicts_with = "purge_quarantined")]
+    retry_quarantined: bool,
+    /// Drop the quarantined remote records for good
+    #[arg(long)]
+    purge_quarantined: bool,
+}
+
+impl Cmd {
+    /// Runs without the settings and the database of the other commands, because these may be
+    /// exactly what is broken.
+    pub(crate) async fn run(self) -> Result<()> {
+        if self.retry_quarantined || self.purge_quarantined {
+            let settings = Settings::new()?;
+            let db = Database::new(&settings.db_path).await?;
+            self.fix_quarantined(&settings, &db).await?;
+        }
+
+        let report = doctor::diagnose().await;
+
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(&report)?);
+        } else {
+            for el in &report.checks {
+                println!("[{}] {}: {}", el.severity.as_str(), el.name, el.message);
+                if let Some(fix) = &el.fix {
+                    println!("    fix: {fix}");
+                }
+            }
+        }
+
+        if report.severity() == Severity::Error {
+            std::process::exit(1);
+        }
+
+        Ok(())
+    }
+
+    async fn fix_quarantined(&self, settings: &Settings, db: &Database) -> Result<()> {
+        if self.retry_quarantined {
+            let recovered = dirpin_client::sync::retry_quarantined(settings, db).await?;
+            println!("Recovered {recovered} quarantined records");
+        }
+
+        if self.purge_quarantined {
+            let ids = db
+                .list_quarantined()
+                .await?
+                .into_iter()
+                .map(|x| x.id)
+                .collect::<Vec<_>>();
+            db.delete_quarantined(&ids).await?;
+            println!("Purged {} quarantined records", ids.len());
+        }
+
+        Ok(())
+    }
+}
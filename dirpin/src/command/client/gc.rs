@@ -0,0 +1,24 @@
+use clap::Parser;
+use dirpin_client::database::Database;
+use dirpin_client::settings::Settings;
+use eyre::Result;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Days to keep the synced tombstones, overrides `tombstone_retention` from the config
+    #[arg(long)]
+    retention: Option<u64>,
+}
+
+impl Cmd {
+    pub async fn run(self, mut settings: Settings, db: &Database) -> Result<()> {
+        if let Some(retention) = self.retention {
+            settings.tombstone_retention = retention;
+        }
+
+        let (workspaces, entries) = dirpin_client::sync::purge_tombstones(&settings, db).await?;
+        println!("Purged {workspaces} workspace and {entries} entry tombstones");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use clap::Parser;
+use dirpin_client::database::Database;
+use dirpin_client::domain::entry::Entry;
+use dirpin_client::domain::revision::Revision;
+use eyre::{bail, Result};
+use time::format_description::well_known::Rfc3339;
+use uuid::Uuid;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Id of the entry. Without it, the latest changes across all the entries are listed
+    id: Option<Uuid>,
+
+    /// How many of the latest changes to list
+    #[arg(long, short, default_value_t = 20)]
+    limit: u32,
+}
+
+fn describe(entry: &Entry) -> String {
+    match entry.deleted_at {
+        Some(_) => format!("v{} {} (deleted)", entry.version.inner(), entry.value),
+        None => format!("v{} {}", entry.version.inner(), entry.value),
+    }
+}
+
+fn describe_revision(revision: &Revision) -> Result<String> {
+    let origin = if revision.local { "local" } else { "sync" };
+    Ok(format!(
+        "{} {} by {origin}: {}",
+        revision.created_at.format(&Rfc3339)?,
+        revision.operation,
+        describe(&revision.entry)
+    ))
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, db: &Database) -> Result<()> {
+        let Some(id) = self.id else {
+            for el in db.list_recent_revisions(self.limit).await? {
+                println!("{} rev {} {}", el.entry.id, el.rev, describe_revision(&el)?);
+            }
+            return Ok(());
+        };
+
+        let revisions = db.list_revisions(id).await?;
+        let current = db.get(id).await?;
+        if current.is_none() && revisions.is_empty() {
+            bail!("No entry with id {id}");
+        }
+
+        for el in &revisions {
+            println!("rev {} {}", el.rev, describe_revision(el)?);
+        }
+        if let Some(current) = current {
+            println!("current {}", describe(&current));
+        }
+
+        Ok(())
+    }
+}
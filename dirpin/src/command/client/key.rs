@@ -1,10 +1,142 @@
+use clap::{Parser, Subcommand};
+use dirpin_client::api_client::{AuthClient, ClientOptions};
+use dirpin_client::database::Database;
 use dirpin_client::encryption;
 use dirpin_client::settings::Settings;
-use eyre::Result;
+use dirpin_client::utils::{read_input_hidden, read_new_passphrase};
+use eyre::{bail, eyre, Result};
 
-pub fn run(settings: &Settings) -> Result<()> {
-    let key = encryption::load_key(settings)?;
-    let key = encryption::encode_key(&key)?;
-    println!("{key}");
-    Ok(())
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Print the key as a word list that is easier to write down
+    #[arg(long)]
+    mnemonic: bool,
+    #[command(subcommand)]
+    cmd: Option<KeyCmd>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCmd {
+    /// Generate a new key and re-encrypt all the remote data with it
+    Rotate {
+        /// Derive the new key from a passphrase instead of generating a random key
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Switch to the key another host rotated to. The current key is kept as a previous key
+    Use {
+        /// The key in the base64 or the mnemonic form
+        key: Option<String>,
+        /// Derive the key from the passphrase of the account
+        #[arg(long)]
+        passphrase: bool,
+    },
+    /// Show which key every host and the remote records use
+    Status,
+    /// Forget the previous keys once every host uses the current key
+    Prune {
+        /// Prune even when some hosts still use an old key
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+impl Cmd {
+    pub async fn run(self, settings: &Settings, db: &Database) -> Result<()> {
+        match self.cmd {
+            None => {
+                let key = encryption::load_key(settings)?;
+                let key = if self.mnemonic {
+                    encryption::encode_key_mnemonic(&key)?
+                } else {
+                    encryption::encode_key(&key)?
+                };
+                println!("{key}");
+            }
+            Some(KeyCmd::Rotate { passphrase }) => {
+                let passphrase = passphrase.then(read_new_passphrase).transpose()?;
+                let key_id =
+                    dirpin_client::sync::rotate_key(settings, db, passphrase.as_deref()).await?;
+                if passphrase.is_some() {
+                    println!("Rotated to the key {key_id}. Run `dirpin key use --passphrase` on your other hosts.");
+                } else {
+                    let key = encryption::encode_key(&encryption::load_key(settings)?)?;
+                    println!("Rotated to the key {key_id}. Run `dirpin key use <key>` on your other hosts with:");
+                    println!("{key}");
+                }
+            }
+            Some(KeyCmd::Use { key, passphrase }) => {
+                let key = match (key, passphrase) {
+                    (Some(key), false) => encryption::parse_key(&key)?,
+                    (None, true) => {
+                        let res = auth_client(settings)?.key_status().await?;
+                        let salt = res.key_salt.ok_or_else(|| {
+                            eyre!("The account key is not derived from a passphrase")
+                        })?;
+                        let passphrase = read_input_hidden("passphrase");
+                        encryption::derive_key(&passphrase, &salt)?
+                    }
+                    _ => bail!("Provide either the key or --passphrase"),
+                };
+                encryption::use_key(settings, &key)?;
+                println!("Using the key {}", encryption::key_id(&key));
+            }
+            Some(KeyCmd::Status) => {
+                let res = auth_client(settings)?.key_status().await?;
+                let local = encryption::key_id(&encryption::load_key(settings)?);
+
+                println!("Local key: {local}");
+                println!(
+                    "Previous keys: {}",
+                    encryption::read_previous_keys(settings)?.len()
+                );
+                println!("Remote key: {}", res.key_id.as_deref().unwrap_or("none"));
+                for host in &res.hosts {
+                    println!(
+                        "Host {}: {}",
+                        host.host_id,
+                        host.key_id.as_deref().unwrap_or("unknown")
+                    );
+                }
+                for record in &res.records {
+                    println!(
+                        "Records with key {}: {}",
+                        record.key_id.as_deref().unwrap_or("unknown"),
+                        record.count
+                    );
+                }
+                if res.records.len() > 1 {
+                    println!("Mixed keys detected. Run `dirpin key rotate` to re-encrypt all the records.");
+                }
+            }
+            Some(KeyCmd::Prune { force }) => {
+                let res = auth_client(settings)?.key_status().await?;
+                let pending = res
+                    .hosts
+                    .iter()
+                    .filter(|x| x.key_id.is_some() && x.key_id != res.key_id)
+                    .count();
+
+                if pending > 0 && !force {
+                    bail!(
+                        "{pending} hosts still use an old key. Switch them first or use --force."
+                    );
+                }
+
+                let count = encryption::prune_previous_keys(settings)?;
+                println!("Removed {count} previous keys");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn auth_client(settings: &Settings) -> Result<AuthClient<'_>> {
+    let session = settings.session().ok_or_else(|| eyre!("Log in first!"))?;
+    AuthClient::with_options(
+        &settings.server_address,
+        &session,
+        &ClientOptions::from(settings),
+    )
 }
@@ -0,0 +1,30 @@
+use clap::Parser;
+use dirpin_client::database::Database;
+use eyre::{eyre, Result};
+use uuid::Uuid;
+
+#[derive(Parser, Debug)]
+pub struct Cmd {
+    /// Id of the entry
+    id: Uuid,
+
+    /// The revision to go back to, see `dirpin history <id>`
+    #[arg(long)]
+    rev: u32,
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, db: &Database) -> Result<()> {
+        let revision = db
+            .get_revision(self.id, self.rev)
+            .await?
+            .ok_or_else(|| eyre!("Entry {} has no revision {}", self.id, self.rev))?;
+        let entry = db.restore(&revision).await?;
+        println!(
+            "Restored {} to revision {}: {}",
+            entry.id, self.rev, entry.value
+        );
+
+        Ok(())
+    }
+}
@@ -513,6 +513,7 @@ enum QueryKind {
     Entries,
     SaveEntry,
     DeleteEntry,
+    Undo,
 }
 
 #[derive(Debug)]
@@ -588,6 +589,13 @@ impl AppState<'_> {
         Ok(())
     }
 
+    async fn query_undo(&mut self) -> Result<bool> {
+        let entry = self.database.undo().await?;
+        self.query_queue.push(QueryKind::Entries);
+
+        Ok(entry.is_some())
+    }
+
     async fn query_save(&mut self) -> Result<()> {
         match self.entry_list.list.selected_mut() {
             Some(item) => {
@@ -774,6 +782,9 @@ impl AppState<'_> {
                             self.set_prompt(PromptState::confirm(ConfirmKind::DeleteEntry));
                             self.set_focus(BlockFocus::Prompt);
                         }
+                        KeyCode::Char('u') => {
+                            self.query_queue.push(QueryKind::Undo);
+                        }
                         KeyCode::Char('t') => {
                             self.set_focus(BlockFocus::Main);
                             self.set_route(Route::KindList);
@@ -1290,12 +1301,23 @@ pub async fn run(settings: &Settings, db: &Database, context: &Context) -> Resul
                 },
                 QueryKind::DeleteEntry => match app.query_delete().await {
                     Ok(_) => {
-                        app.set_prompt(PromptState::info("Item deleted".into()));
+                        app.set_prompt(PromptState::info("Item deleted, (u) to undo".into()));
                     }
                     Err(_) => {
                         app.set_prompt(PromptState::error("Failed to delete entry".into()));
                     }
                 },
+                QueryKind::Undo => match app.query_undo().await {
+                    Ok(true) => {
+                        app.set_prompt(PromptState::info("Undone".into()));
+                    }
+                    Ok(false) => {
+                        app.set_prompt(PromptState::info("Nothing to undo".into()));
+                    }
+                    Err(_) => {
+                        app.set_prompt(PromptState::error("Failed to undo".into()));
+                    }
+                },
                 QueryKind::SaveEntry => match app.query_save().await {
                     Ok(_) => {
                         app.set_prompt(PromptState::info("Item updated".into()));
@@ -1,9 +1,39 @@
-use dirpin_client::api_client;
+use dirpin_client::api_client::{self, ClientOptions};
 use dirpin_client::settings::Settings;
+use dirpin_common::api::{Compatibility, API_VERSION};
 use eyre::Result;
 
 pub async fn run(settings: &Settings) -> Result<()> {
-    let res = api_client::health_check(&settings.server_address).await?;
-    println!("{res:?}");
+    let res =
+        api_client::health_check(&settings.server_address, &ClientOptions::from(settings)).await?;
+
+    println!("Server: {}", settings.server_address);
+    println!("Status: {}", res.status);
+    println!("Server version: {}", res.version);
+
+    if res.api_version == 0 {
+        println!("Compatibility: unknown, the server does not support version negotiation");
+        return Ok(());
+    }
+
+    println!("Server api version: {}", res.api_version);
+    println!(
+        "Supported client api versions: {} - {}",
+        res.min_client_version, res.max_client_version
+    );
+    println!("Client api version: {API_VERSION}");
+
+    let compatibility =
+        Compatibility::check(API_VERSION, res.min_client_version, res.max_client_version);
+    match compatibility {
+        Compatibility::Compatible => println!("Compatibility: {compatibility}"),
+        Compatibility::ClientTooOld => {
+            println!("Compatibility: {compatibility}. Please upgrade dirpin.")
+        }
+        Compatibility::ClientTooNew => {
+            println!("Compatibility: {compatibility}. Please upgrade the server.")
+        }
+    }
+
     Ok(())
 }
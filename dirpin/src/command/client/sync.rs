@@ -7,10 +7,35 @@ use eyre::Result;
 pub struct Cmd {
     #[arg(short, long)]
     force: bool,
+    /// Compare every local record with the server and repair the differences
+    #[arg(long, conflicts_with = "force")]
+    verify: bool,
 }
 
 impl Cmd {
     pub async fn run(&self, settings: &Settings, db: &Database) -> Result<()> {
+        if self.verify {
+            let report = dirpin_client::sync::verify(settings, db).await?;
+            println!("Checked {} records", report.checked);
+            println!("In sync: {}", report.in_sync);
+            println!("Downloaded: {}", report.downloaded);
+            println!("Uploaded: {}", report.uploaded);
+            if report.conflicts > 0 {
+                println!(
+                    "{} conflicts. Resolve in app before resyncing",
+                    report.conflicts
+                );
+            }
+            if report.quarantined > 0 {
+                println!(
+                    "{} remote records could not be read and were quarantined. Run `dirpin \
+                    doctor` to inspect them.",
+                    report.quarantined
+                );
+            }
+            return Ok(());
+        }
+
         dirpin_client::sync::sync(settings, db, self.force).await?;
         Ok(())
     }
@@ -0,0 +1,14 @@
+use dirpin_client::database::Database;
+use eyre::Result;
+
+pub(crate) async fn run(db: &Database) -> Result<()> {
+    match db.undo().await? {
+        Some(entry) if entry.deleted_at.is_some() => {
+            println!("Deleted {}: {}", entry.id, entry.value)
+        }
+        Some(entry) => println!("Restored {} to: {}", entry.id, entry.value),
+        None => println!("Nothing to undo"),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,202 @@
+use clap::Parser;
+use dirpin_client::database::Database;
+use dirpin_client::domain::context::Context;
+use dirpin_client::domain::workspace::{Workspace, WorkspacePath};
+use dirpin_client::settings::Settings;
+use eyre::{bail, Context as Ctx, Result};
+use std::path::Path;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+#[derive(Parser, Debug)]
+#[clap(infer_subcommands = true)]
+pub enum Cmd {
+    /// List all the workspaces
+    List,
+    /// Show the details of a workspace, the one of the current directory by default
+    Show { workspace: Option<String> },
+    /// Give the workspace a new name
+    Rename { workspace: String, name: String },
+    /// Delete the workspace. Its entries are kept and stay pinned to their own paths
+    Delete { workspace: String },
+    /// Add a path on this host to the workspace
+    Attach {
+        path: String,
+        /// Name or id of the workspace, the one of the current directory by default
+        #[arg(long, short)]
+        workspace: Option<String>,
+    },
+    /// Remove a path on this host from the workspace. A path that no longer exists is matched as
+    /// it is stored.
+    Detach {
+        path: String,
+        /// Name or id of the workspace, the one of the current directory by default
+        #[arg(long, short)]
+        workspace: Option<String>,
+    },
+    /// Move all the entries and paths of the first workspace into the second one and delete the
+    /// first one
+    Merge { from: String, into: String },
+}
+
+/// Find a live workspace by its id or its name.
+async fn find(db: &Database, query: &str) -> Result<Workspace> {
+    let mut matches = db
+        .list_workspaces("")
+        .await?
+        .into_iter()
+        .filter(|x| x.deleted_at.is_none())
+        .filter(|x| x.id.to_string() == query || x.name == query)
+        .collect::<Vec<_>>();
+
+    match matches.len() {
+        0 => bail!("No workspace named {query}"),
+        1 => Ok(matches.remove(0)),
+        _ => bail!("More workspaces are named {query}, use the id instead"),
+    }
+}
+
+async fn find_or_current(db: &Database, query: Option<&str>) -> Result<Workspace> {
+    if let Some(query) = query {
+        return find(db, query).await;
+    }
+
+    match db.workspace(None, None, &Context::cwd()).await? {
+        Some(ws) => Ok(ws),
+        None => bail!("The current directory is not part of a workspace"),
+    }
+}
+
+/// The absolute path of the directory as it is stored in the workspace paths.
+fn host_path(path: &str) -> Result<WorkspacePath> {
+    let path = fs_err::canonicalize(path)
+        .wrap_err("Failed to resolve the path")?
+        .to_string_lossy()
+        .to_string();
+    if path.contains(',') {
+        bail!("Paths with a ',' can not be part of a workspace");
+    }
+
+    Ok(WorkspacePath::new(Settings::host_id(), path))
+}
+
+/// The path to detach from the workspace. A directory that is gone is taken as it is stored,
+/// either as given on this host or in the `host:path` form that `show` prints.
+fn detach_path(path: &str) -> Result<WorkspacePath> {
+    if Path::new(path).exists() {
+        return host_path(path);
+    }
+
+    Ok(WorkspacePath::try_from(path)
+        .unwrap_or_else(|_| WorkspacePath::new(Settings::host_id(), path.to_string())))
+}
+
+fn touch(ws: &mut Workspace) {
+    ws.version.bump();
+    ws.updated_at = OffsetDateTime::now_utc();
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, db: &Database) -> Result<()> {
+        match self {
+            Self::List => {
+                let mut workspaces = db
+                    .list_workspaces("")
+                    .await?
+                    .into_iter()
+                    .filter(|x| x.deleted_at.is_none())
+                    .collect::<Vec<_>>();
+                workspaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+                for el in workspaces {
+                    let entries = db.count_workspace_entries(&el.id).await?;
+                    println!(
+                        "{} {} ({entries} entries) {}",
+                        el.id,
+                        el.name,
+                        el.git.as_deref().unwrap_or("")
+                    );
+                }
+            }
+            Self::Show { workspace } => {
+                let ws = find_or_current(db, workspace.as_deref()).await?;
+                let entries = db.count_workspace_entries(&ws.id).await?;
+
+                println!("Id: {}", ws.id);
+                println!("Name: {}", ws.name);
+                println!("Git: {}", ws.git.as_deref().unwrap_or("none"));
+                println!("Entries: {entries}");
+                println!("Version: {}", ws.version.inner());
+                println!("Updated: {}", ws.updated_at.format(&Rfc3339)?);
+                println!("Paths:");
+                for el in &ws.paths {
+                    println!("  {el}");
+                }
+            }
+            Self::Rename { workspace, name } => {
+                let mut ws = find(db, &workspace).await?;
+                ws.name = name;
+                touch(&mut ws);
+                db.save_workspace(&ws).await?;
+                println!("Workspace renamed to {}", ws.name);
+            }
+            Self::Delete { workspace } => {
+                let ws = find(db, &workspace).await?;
+                let entries = db.delete_workspace(&ws).await?;
+                println!("Workspace {} deleted, {entries} entries detached", ws.name);
+            }
+            Self::Attach { path, workspace } => {
+                let mut ws = find_or_current(db, workspace.as_deref()).await?;
+                let path = host_path(&path)?;
+                if ws.paths.contains(&path) {
+                    bail!("{path} is already part of {}", ws.name);
+                }
+                ws.paths.push(path.clone());
+                touch(&mut ws);
+                db.save_workspace(&ws).await?;
+                println!("Attached {path} to {}", ws.name);
+            }
+            Self::Detach { path, workspace } => {
+                let mut ws = find_or_current(db, workspace.as_deref()).await?;
+                let path = detach_path(&path)?;
+                if !ws.paths.contains(&path) {
+                    bail!("{path} is not part of {}", ws.name);
+                }
+                if ws.paths.len() == 1 {
+                    bail!(
+                        "{path} is the last path of {}, delete the workspace instead",
+                        ws.name
+                    );
+                }
+                ws.paths.retain(|x| x != &path);
+                touch(&mut ws);
+                db.save_workspace(&ws).await?;
+                println!("Detached {path} from {}", ws.name);
+            }
+            Self::Merge { from, into } => {
+                let from = find(db, &from).await?;
+                let mut into = find(db, &into).await?;
+                if from.id == into.id {
+                    bail!("Can not merge a workspace into itself");
+                }
+
+                for el in &from.paths {
+                    if !into.paths.contains(el) {
+                        into.paths.push(el.clone());
+                    }
+                }
+                if into.git.is_none() {
+                    into.git = from.git.clone();
+                }
+                touch(&mut into);
+                let entries = db.merge_workspaces(&from, &into).await?;
+                println!(
+                    "Merged {} into {}, {entries} entries moved",
+                    from.name, into.name
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
 use clap::Parser;
-use dirpin_server::settings::Settings;
-use eyre::Result;
+use dirpin_server::database::Database;
+use dirpin_server::models::RecordCount;
+use dirpin_server::settings::{LogFormat, Settings};
+use eyre::{eyre, Result};
 use std::net::SocketAddr;
 use tracing_subscriber::{self, fmt, prelude::*, EnvFilter};
 
+mod invite;
+mod session;
+mod user;
+
 #[derive(Parser, Debug)]
 #[clap(infer_subcommands = true)]
 pub enum Cmd {
@@ -16,13 +22,91 @@ pub enum Cmd {
         #[clap(long, short)]
         port: Option<u16>,
     },
+    /// Purge the deleted records every host of the user has synced past
+    Gc {
+        /// Only purge the records of this user
+        #[clap(long)]
+        user: Option<String>,
+    },
+    /// Manage the codes that let new users register on a server with closed registration
+    #[command(subcommand)]
+    Invite(invite::Cmd),
+    /// Manage the users. Prints json
+    #[command(subcommand)]
+    User(user::Cmd),
+    /// Manage the sessions of the hosts. Prints json
+    #[command(subcommand)]
+    Session(session::Cmd),
+    /// Print the numbers of users, sessions and records and the database size as json
+    Stats,
+    /// Rebuild the database file to give back the space of the removed data
+    Vacuum,
+}
+
+#[derive(serde::Serialize)]
+struct RecordsOutput {
+    kind: String,
+    live: i64,
+    deleted: i64,
+}
+
+impl From<RecordCount> for RecordsOutput {
+    fn from(count: RecordCount) -> Self {
+        Self {
+            kind: count.kind,
+            live: count.live,
+            deleted: count.deleted,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct StatsOutput {
+    users: i64,
+    disabled_users: i64,
+    sessions: i64,
+    records: Vec<RecordsOutput>,
+    tombstones: i64,
+    db_size: i64,
+}
+
+#[derive(serde::Serialize)]
+struct VacuumOutput {
+    db_size_before: i64,
+    db_size_after: i64,
+}
+
+/// Print the value as one line of json.
+fn print_json<T: serde::Serialize>(value: &T) -> Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+async fn open_database(settings: &Settings) -> Result<Database> {
+    let database = Database::new(settings.database_uri()).await?;
+    database.migrate().await?;
+    Ok(database)
 }
 
 impl Cmd {
     #[tokio::main]
     pub async fn run(self) -> Result<()> {
+        let settings = Settings::new()?;
+        let (text, json) = match settings.log_format {
+            LogFormat::Text => (Some(fmt::layer()), None),
+            LogFormat::Json => (
+                None,
+                Some(
+                    fmt::layer()
+                        .json()
+                        .flatten_event(true)
+                        .with_span_list(false),
+                ),
+            ),
+        };
         tracing_subscriber::registry()
-            .with(fmt::layer())
+            .with(text)
+            .with(json)
             .with(EnvFilter::from_default_env())
             .init();
 
@@ -30,12 +114,67 @@ impl Cmd {
 
         match self {
             Self::Start { host, port } => {
-                let settings = Settings::new()?;
                 let host = host.as_ref().unwrap_or(&settings.host);
                 let port = port.unwrap_or(settings.port);
                 let address = SocketAddr::new(host.parse()?, port);
                 dirpin_server::launch(&settings, address).await
             }
+            Self::Gc { user } => {
+                let database = open_database(&settings).await?;
+                let user_id = match user {
+                    Some(username) => Some(
+                        database
+                            .get_user(&username)
+                            .await
+                            .map_err(|err| eyre!("Failed to find user {username}: {err}"))?
+                            .id,
+                    ),
+                    None => None,
+                };
+                let purged = database
+                    .purge_tombstones(user_id)
+                    .await
+                    .map_err(|err| eyre!("Failed to purge tombstones: {err}"))?;
+                println!("Purged {purged} tombstones");
+                Ok(())
+            }
+            Self::Invite(cmd) => cmd.run(&open_database(&settings).await?).await,
+            Self::User(cmd) => cmd.run(&open_database(&settings).await?).await,
+            Self::Session(cmd) => cmd.run(&open_database(&settings).await?).await,
+            Self::Stats => {
+                let database = open_database(&settings).await?;
+                let stats = database
+                    .stats()
+                    .await
+                    .map_err(|err| eyre!("Failed to read the stats: {err}"))?;
+                print_json(&StatsOutput {
+                    users: stats.users,
+                    disabled_users: stats.disabled_users,
+                    sessions: stats.sessions,
+                    tombstones: stats.records.iter().map(|x| x.deleted).sum(),
+                    records: stats.records.into_iter().map(Into::into).collect(),
+                    db_size: stats.db_size,
+                })
+            }
+            Self::Vacuum => {
+                let database = open_database(&settings).await?;
+                let db_size_before = database
+                    .db_size()
+                    .await
+                    .map_err(|err| eyre!("Failed to read the database size: {err}"))?;
+                database
+                    .vacuum()
+                    .await
+                    .map_err(|err| eyre!("Failed to vacuum the database: {err}"))?;
+                let db_size_after = database
+                    .db_size()
+                    .await
+                    .map_err(|err| eyre!("Failed to read the database size: {err}"))?;
+                print_json(&VacuumOutput {
+                    db_size_before,
+                    db_size_after,
+                })
+            }
         }
     }
 }
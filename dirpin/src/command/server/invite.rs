@@ -0,0 +1,74 @@
+use clap::Subcommand;
+use dirpin_common::utils::crypto_random_string;
+use dirpin_server::database::Database;
+use dirpin_server::models::NewInviteCode;
+use eyre::{bail, eyre, Result};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Create a code that lets one new user register
+    Create {
+        /// Hours until the code expires. It does not expire by default
+        #[arg(long)]
+        expires_in: Option<u32>,
+    },
+    /// List the invite codes and who used them
+    List,
+    /// Remove a code nobody registered with yet
+    Revoke { code: String },
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, database: &Database) -> Result<()> {
+        match self {
+            Self::Create { expires_in } => {
+                let code = crypto_random_string::<12>();
+                let expires_at =
+                    expires_in.map(|x| OffsetDateTime::now_utc() + Duration::hours(x.into()));
+                database
+                    .add_invite_code(NewInviteCode {
+                        code: code.clone(),
+                        expires_at,
+                    })
+                    .await
+                    .map_err(|err| eyre!("Failed to create the invite code: {err}"))?;
+                println!("{code}");
+            }
+            Self::List => {
+                let invites = database
+                    .list_invite_codes()
+                    .await
+                    .map_err(|err| eyre!("Failed to list the invite codes: {err}"))?;
+                let now = OffsetDateTime::now_utc();
+                for el in invites {
+                    let status = match (&el.used_by, el.used_at, el.expires_at) {
+                        (Some(user), _, _) => format!("used by {user}"),
+                        (None, Some(_), _) => "used by a deleted user".into(),
+                        (None, None, Some(x)) if x <= now => "expired".into(),
+                        (None, None, Some(x)) => format!("expires {}", x.format(&Rfc3339)?),
+                        (None, None, None) => "unused".into(),
+                    };
+                    println!(
+                        "{} created {} {status}",
+                        el.code,
+                        el.created_at.format(&Rfc3339)?
+                    );
+                }
+            }
+            Self::Revoke { code } => {
+                let removed = database
+                    .remove_invite_code(&code)
+                    .await
+                    .map_err(|err| eyre!("Failed to revoke the invite code: {err}"))?;
+                if !removed {
+                    bail!("No unused invite code {code}");
+                }
+                println!("Revoked {code}");
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+use super::print_json;
+use clap::Subcommand;
+use dirpin_server::database::Database;
+use eyre::{eyre, Result};
+use std::collections::HashMap;
+use time::OffsetDateTime;
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Print every session as a json line, the expired ones too
+    List {
+        /// Only the sessions of this user
+        #[arg(long)]
+        user: Option<String>,
+    },
+    /// Remove the expired sessions
+    PurgeExpired,
+}
+
+#[derive(serde::Serialize)]
+struct SessionOutput {
+    id: u32,
+    username: Option<String>,
+    host_id: Option<String>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    created_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    last_used_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339")]
+    expires_at: OffsetDateTime,
+    expired: bool,
+}
+
+#[derive(serde::Serialize)]
+struct PurgeOutput {
+    purged: u64,
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, database: &Database) -> Result<()> {
+        match self {
+            Self::List { user } => {
+                let usernames = database
+                    .list_users()
+                    .await
+                    .map_err(|err| eyre!("Failed to list the users: {err}"))?
+                    .into_iter()
+                    .map(|x| (x.id, x.username))
+                    .collect::<HashMap<_, _>>();
+                let sessions = database
+                    .list_sessions()
+                    .await
+                    .map_err(|err| eyre!("Failed to list the sessions: {err}"))?;
+
+                let now = OffsetDateTime::now_utc();
+                for el in sessions {
+                    let username = usernames.get(&el.user_id).cloned();
+                    if user.is_some() && username != user {
+                        continue;
+                    }
+                    print_json(&SessionOutput {
+                        id: el.id,
+                        username,
+                        host_id: el.host_id,
+                        created_at: el.created_at,
+                        last_used_at: el.last_used_at,
+                        expires_at: el.expires_at,
+                        expired: el.expires_at <= now,
+                    })?;
+                }
+            }
+            Self::PurgeExpired => {
+                let purged = database
+                    .purge_expired_sessions()
+                    .await
+                    .map_err(|err| eyre!("Failed to purge the sessions: {err}"))?;
+                print_json(&PurgeOutput { purged })?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use super::{print_json, RecordsOutput};
+use clap::Subcommand;
+use dirpin_server::database::Database;
+use dirpin_server::models::User;
+use eyre::{eyre, Result};
+use time::OffsetDateTime;
+
+#[derive(Subcommand, Debug)]
+pub enum Cmd {
+    /// Print every user as a json line
+    List,
+    /// Print the user with the sessions and the records as json
+    Show { username: String },
+    /// Stop the user from logging in and log out all the hosts of the user
+    Disable { username: String },
+    /// Let a disabled user log in again
+    Enable { username: String },
+    /// Remove the user with all the records and sessions
+    Delete { username: String },
+}
+
+#[derive(serde::Serialize)]
+struct UserOutput {
+    id: u32,
+    username: String,
+    email: String,
+    #[serde(with = "time::serde::rfc3339")]
+    created_at: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339::option")]
+    verified_at: Option<OffsetDateTime>,
+    #[serde(with = "time::serde::rfc3339::option")]
+    disabled_at: Option<OffsetDateTime>,
+    key_id: Option<String>,
+}
+
+impl From<User> for UserOutput {
+    fn from(user: User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            email: user.email,
+            created_at: user.created_at,
+            verified_at: user.verified_at,
+            disabled_at: user.disabled_at,
+            key_id: user.key_id,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+struct UserDetailOutput {
+    #[serde(flatten)]
+    user: UserOutput,
+    sessions: usize,
+    records: Vec<RecordsOutput>,
+}
+
+#[derive(serde::Serialize)]
+struct ActionOutput<'a> {
+    username: &'a str,
+    action: &'a str,
+}
+
+async fn find(database: &Database, username: &str) -> Result<User> {
+    database
+        .get_user(username)
+        .await
+        .map_err(|err| eyre!("Failed to find user {username}: {err}"))
+}
+
+async fn set_disabled(database: &Database, username: &str, disabled: bool) -> Result<()> {
+    let user = find(database, username).await?;
+    database
+        .set_user_disabled(user.id, disabled)
+        .await
+        .map_err(|err| eyre!("Failed to update user {username}: {err}"))?;
+    print_json(&ActionOutput {
+        username,
+        action: if disabled { "disabled" } else { "enabled" },
+    })
+}
+
+impl Cmd {
+    pub(crate) async fn run(self, database: &Database) -> Result<()> {
+        match self {
+            Self::List => {
+                let users = database
+                    .list_users()
+                    .await
+                    .map_err(|err| eyre!("Failed to list the users: {err}"))?;
+                for el in users {
+                    print_json(&UserOutput::from(el))?;
+                }
+            }
+            Self::Show { username } => {
+                let user = find(database, &username).await?;
+                let sessions = database
+                    .list_user_sessions(user.id)
+                    .await
+                    .map_err(|err| eyre!("Failed to list the sessions: {err}"))?;
+                let records = database
+                    .count_records(Some(user.id))
+                    .await
+                    .map_err(|err| eyre!("Failed to count the records: {err}"))?;
+                print_json(&UserDetailOutput {
+                    user: user.into(),
+                    sessions: sessions.len(),
+                    records: records.into_iter().map(Into::into).collect(),
+                })?;
+            }
+            Self::Disable { username } => set_disabled(database, &username, true).await?,
+            Self::Enable { username } => set_disabled(database, &username, false).await?,
+            Self::Delete { username } => {
+                let user = find(database, &username).await?;
+                database
+                    .delete_user(user.id)
+                    .await
+                    .map_err(|err| eyre!("Failed to delete user {username}: {err}"))?;
+                print_json(&ActionOutput {
+                    username: &username,
+                    action: "deleted",
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -3,12 +3,15 @@ use dirpin_client::database::Database as ClientDatabase;
 use dirpin_client::domain::host::HostId;
 use dirpin_client::settings::Settings as ClientSettings;
 use dirpin_server::database::Database as ServerDatabase;
-use dirpin_server::make_router;
+use dirpin_server::mail::MemorySender;
+use dirpin_server::make_router_with_mail;
 use dirpin_server::settings::Settings as ServerSettings;
 use eyre::{eyre, Result};
 use fake::faker::internet::en::Username;
 use fake::faker::lorem::en::Word;
-use fake::{Fake};
+use fake::Fake;
+use std::net::SocketAddr;
+use std::sync::Arc;
 use tokio::net::TcpListener;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -50,6 +53,8 @@ impl TestClient {
 pub struct TestServer {
     pub settings: ServerSettings,
     pub database: ServerDatabase,
+    /// The mails the server sent
+    pub mail: MemorySender,
 }
 
 impl TestServer {
@@ -63,12 +68,14 @@ impl TestServer {
             .try_deserialize()
             .map_err(|e| eyre!("Failed to deseriazlie {e}"))?;
 
-        let database = ServerDatabase::new(&settings.db_path).await?;
-        sqlx::migrate!("../dirpin-server/migrations")
-            .run(&database.pool)
-            .await?;
+        let database = ServerDatabase::new(settings.database_uri()).await?;
+        database.migrate().await?;
 
-        Ok(TestServer { settings, database })
+        Ok(TestServer {
+            settings,
+            database,
+            mail: MemorySender::new(),
+        })
     }
 
     pub fn address(&self) -> String {
@@ -82,15 +89,35 @@ pub struct TestSyncApp {
 }
 
 pub async fn spawn_sync_app() -> Result<TestServer> {
+    spawn_sync_app_with(|_| {}).await
+}
+
+/// Spawn the test server with the settings changed by the function.
+pub async fn spawn_sync_app_with(
+    configure: impl FnOnce(&mut ServerSettings),
+) -> Result<TestServer> {
     let host = "127.0.0.1";
     let mut port = 0;
     let listener = TcpListener::bind(format!("{}:{}", host, port)).await?;
     port = listener.local_addr().unwrap().port();
 
-    let server = TestServer::build(&host, port).await?;
-
-    let r = make_router(&server.settings, server.database.clone()).await;
-    let _ = tokio::spawn(async move { serve(listener, r.into_make_service()).await.unwrap() });
+    let mut server = TestServer::build(&host, port).await?;
+    configure(&mut server.settings);
+
+    let r = make_router_with_mail(
+        &server.settings,
+        server.database.clone(),
+        Arc::new(server.mail.clone()),
+    )
+    .await;
+    let _ = tokio::spawn(async move {
+        serve(
+            listener,
+            r.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap()
+    });
     Ok(server)
 }
 
@@ -1,19 +1,325 @@
 mod helpers;
-use dirpin_common::api::HealthCheckResponse;
-use helpers::{spawn_sync_app, VERSION};
+use dirpin_client::api_client::{self, ApiError, AuthClient, ClientOptions};
+use dirpin_common::api::{
+    HealthCheckResponse, RegisterRequest, VersionMismatchMessage, API_VERSION, API_VERSION_HEADER,
+};
+use dirpin_server::{make_router_with_mail, tls};
+use helpers::{spawn_sync_app, spawn_sync_app_with, TestServer, VERSION};
+use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::net::TcpListener;
 
 #[tokio::test]
 async fn health_check() {
     let server = spawn_sync_app().await.unwrap();
-    let response = dirpin_client::api_client::health_check(&server.address())
-        .await
-        .unwrap();
+    let response =
+        dirpin_client::api_client::health_check(&server.address(), &ClientOptions::default())
+            .await
+            .unwrap();
 
+    assert!(response.time.is_some());
     assert_eq!(
         HealthCheckResponse {
             status: "Ok".into(),
-            version: VERSION.into()
+            version: VERSION.into(),
+            api_version: API_VERSION,
+            min_client_version: API_VERSION,
+            max_client_version: API_VERSION,
+            time: response.time,
         },
         response
     );
 }
+
+#[tokio::test]
+async fn unsupported_client_version_is_rejected() {
+    let server = spawn_sync_app().await.unwrap();
+    let client = reqwest::Client::new();
+
+    for version in [0, API_VERSION + 1] {
+        let response = client
+            .get(format!("{}/sync/status", server.address()))
+            .header(API_VERSION_HEADER, version)
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), reqwest::StatusCode::UPGRADE_REQUIRED);
+
+        let body = response.json::<VersionMismatchMessage>().await.unwrap();
+        assert_eq!(body.api_version, API_VERSION);
+        assert_eq!(body.min_client_version, API_VERSION);
+        assert_eq!(body.max_client_version, API_VERSION);
+    }
+}
+
+#[tokio::test]
+async fn health_and_readiness_checks() {
+    let server = spawn_sync_app().await.unwrap();
+
+    for path in ["/healthz", "/readyz"] {
+        let response = reqwest::get(format!("{}{path}", server.address()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+    }
+}
+
+#[tokio::test]
+async fn requests_get_an_id() {
+    let server = spawn_sync_app().await.unwrap();
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/healthz", server.address()))
+        .send()
+        .await
+        .unwrap();
+    assert!(response.headers().contains_key("x-request-id"));
+
+    // The id of the proxy in front of the server is kept.
+    let response = client
+        .get(format!("{}/healthz", server.address()))
+        .header("x-request-id", "proxy-id")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["x-request-id"], "proxy-id");
+}
+
+#[tokio::test]
+async fn metrics_are_off_by_default() {
+    let server = spawn_sync_app().await.unwrap();
+
+    let response = reqwest::get(format!("{}/metrics", server.address()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn metrics_count_the_requests_per_route() {
+    let server = spawn_sync_app_with(|x| x.metrics = true).await.unwrap();
+
+    for path in ["/healthz", "/healthz", "/sync/status", "/users/1"] {
+        reqwest::get(format!("{}{path}", server.address()))
+            .await
+            .unwrap();
+    }
+
+    let response = reqwest::get(format!("{}/metrics", server.address()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), reqwest::StatusCode::OK);
+    let metrics = response.text().await.unwrap();
+    for line in [
+        r#"dirpin_http_requests_total{method="GET",route="/healthz",status="200"} 2"#,
+        r#"dirpin_http_requests_total{method="GET",route="/sync/status",status="426"} 1"#,
+        r#"dirpin_http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+        r#"dirpin_http_request_duration_seconds_count{method="GET",route="/healthz"} 2"#,
+        "dirpin_active_sessions 0",
+    ] {
+        assert!(metrics.lines().any(|x| x == line), "{line} in {metrics}");
+    }
+
+    // The numbers of the database are not read again on the next scrape.
+    api_client::register(
+        &server.address(),
+        &RegisterRequest {
+            username: "username".to_string(),
+            email: "user@example.com".to_string(),
+            password: "password".to_string(),
+            host_id: "host@one".to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let metrics = reqwest::get(format!("{}/metrics", server.address()))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.lines().any(|x| x == "dirpin_active_sessions 0"));
+}
+
+/// Serve the test server over https with the pem files in the directory. Returns the address.
+async fn spawn_tls_app(dir: &Path) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = TestServer::build("127.0.0.1", port).await.unwrap();
+    let router = make_router_with_mail(
+        &server.settings,
+        server.database.clone(),
+        Arc::new(server.mail.clone()),
+    )
+    .await;
+
+    let cert = dir.join("cert.pem").to_str().unwrap().to_string();
+    let key = dir.join("key.pem").to_str().unwrap().to_string();
+    let config = tls::load_config(&cert, &key).unwrap();
+    tls::reload_on_hangup(config.clone(), cert, key).unwrap();
+    tokio::spawn(tls::serve(
+        listener,
+        router.into_make_service_with_connect_info::<SocketAddr>(),
+        config,
+        std::future::pending(),
+    ));
+
+    format!("https://localhost:{port}")
+}
+
+fn write_cert(dir: &Path, cert: &rcgen::Certificate, key: &KeyPair) {
+    std::fs::write(dir.join("cert.pem"), cert.pem()).unwrap();
+    std::fs::write(dir.join("key.pem"), key.serialize_pem()).unwrap();
+}
+
+fn self_signed() -> CertifiedKey {
+    rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap()
+}
+
+fn tls_options(tls_ca: Option<String>, tls_pin: Option<String>) -> ClientOptions {
+    ClientOptions {
+        retries: 0,
+        tls_ca,
+        tls_pin,
+        ..ClientOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn https_with_a_custom_ca() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(vec![]).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".into()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+    write_cert(dir.path(), &cert, &key);
+    let ca_path = dir.path().join("ca.pem");
+    std::fs::write(&ca_path, ca.pem()).unwrap();
+
+    let address = spawn_tls_app(dir.path()).await;
+
+    // The system CAs don't know the private CA.
+    assert!(api_client::health_check(&address, &tls_options(None, None))
+        .await
+        .is_err());
+
+    let options = tls_options(Some(ca_path.to_str().unwrap().into()), None);
+    let response = api_client::health_check(&address, &options).await.unwrap();
+    assert_eq!(response.version, VERSION);
+
+    let client = AuthClient::with_options(&address, "unknown", &options).unwrap();
+    let err = client.status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
+}
+
+#[tokio::test]
+async fn https_with_a_pinned_certificate() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let CertifiedKey { cert, key_pair } = self_signed();
+    write_cert(dir.path(), &cert, &key_pair);
+
+    let address = spawn_tls_app(dir.path()).await;
+
+    let pin = tls_options(None, Some(dirpin_client::tls::fingerprint(cert.der())));
+    api_client::health_check(&address, &pin).await.unwrap();
+
+    let other = self_signed();
+    let wrong_pin = tls_options(
+        None,
+        Some(dirpin_client::tls::fingerprint(other.cert.der())),
+    );
+    assert!(api_client::health_check(&address, &wrong_pin)
+        .await
+        .is_err());
+}
+
+#[cfg(target_family = "unix")]
+#[tokio::test]
+async fn certificate_is_reloaded_on_hangup() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let first = self_signed();
+    write_cert(dir.path(), &first.cert, &first.key_pair);
+
+    let address = spawn_tls_app(dir.path()).await;
+
+    let second = self_signed();
+    let pin = tls_options(
+        None,
+        Some(dirpin_client::tls::fingerprint(second.cert.der())),
+    );
+    assert!(api_client::health_check(&address, &pin).await.is_err());
+
+    write_cert(dir.path(), &second.cert, &second.key_pair);
+    let status = std::process::Command::new("kill")
+        .args(["-HUP", &std::process::id().to_string()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // The signal is handled in the background.
+    for _ in 0..50 {
+        if api_client::health_check(&address, &pin).await.is_ok() {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    panic!("the server kept the old certificate");
+}
+
+#[tokio::test]
+async fn register_and_login_over_https() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let CertifiedKey { cert, key_pair } = self_signed();
+    write_cert(dir.path(), &cert, &key_pair);
+
+    let address = spawn_tls_app(dir.path()).await;
+    let pin = tls_options(None, Some(dirpin_client::tls::fingerprint(cert.der())));
+
+    let registered = api_client::register(
+        &address,
+        &RegisterRequest {
+            username: "username".to_string(),
+            email: "user@example.com".to_string(),
+            password: "password".to_string(),
+            host_id: "host@one".to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &pin,
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::with_options(&address, &registered.session, &pin).unwrap();
+    client.status().await.unwrap();
+
+    let login = api_client::login(&address, "username", "password", "host@two", &pin)
+        .await
+        .unwrap();
+    assert_ne!(login.session, registered.session);
+
+    // Without the pin the self signed certificate is not trusted.
+    assert!(api_client::login(
+        &address,
+        "username",
+        "password",
+        "host@two",
+        &tls_options(None, None)
+    )
+    .await
+    .is_err());
+}
@@ -0,0 +1,529 @@
+//! The storage suite runs against every database backend of the server. The PostgreSQL tests need
+//! a server to create the test databases in, e.g.
+//! `DIRPIN_TEST_POSTGRES_URI=postgres://postgres@localhost/postgres`. Without it they are skipped.
+use dirpin_server::database::{Database, DbError, PostgresStorage};
+use dirpin_server::models::{
+    HostSession, NewEntry, NewInviteCode, NewLinkCode, NewSession, NewUser, NewVerificationToken,
+    RenewSession, VerificationKind,
+};
+use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+use sqlx::{ConnectOptions, Executor};
+use std::str::FromStr;
+use time::{Duration, OffsetDateTime};
+
+const POSTGRES_URI: &str = "DIRPIN_TEST_POSTGRES_URI";
+
+async fn sqlite() -> Database {
+    let database = Database::new("sqlite::memory:").await.unwrap();
+    database.migrate().await.unwrap();
+    database
+}
+
+/// A fresh database on the test server, dropped again by `drop_postgres`.
+async fn postgres() -> Option<(Database, String)> {
+    let Ok(uri) = std::env::var(POSTGRES_URI) else {
+        eprintln!("{POSTGRES_URI} is not set, skipping the postgres storage test");
+        return None;
+    };
+    let name = format!("dirpin_test_{}", uuid::Uuid::new_v4().simple());
+    let mut conn = PgConnectOptions::from_str(&uri)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    conn.execute(format!("create database {name}").as_str())
+        .await
+        .unwrap();
+
+    let options = PgConnectOptions::from_str(&uri).unwrap().database(&name);
+    let pool = PgPoolOptions::new().connect_with(options).await.unwrap();
+    let database = Database::from_storage(PostgresStorage { pool });
+    database.migrate().await.unwrap();
+    Some((database, name))
+}
+
+async fn drop_postgres(database: Database, name: &str) {
+    drop(database);
+    let uri = std::env::var(POSTGRES_URI).unwrap();
+    let mut conn = PgConnectOptions::from_str(&uri)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    conn.execute(format!("drop database {name} with (force)").as_str())
+        .await
+        .unwrap();
+}
+
+/// Run every test against each backend.
+macro_rules! storage_tests {
+    ($($name:ident),* $(,)?) => {
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    super::$name(super::sqlite().await).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            $(
+                #[tokio::test]
+                async fn $name() {
+                    if let Some((database, name)) = super::postgres().await {
+                        super::$name(database.clone()).await;
+                        super::drop_postgres(database, &name).await;
+                    }
+                }
+            )*
+        }
+    };
+}
+
+storage_tests!(
+    entries_sync_by_timestamp,
+    sessions_belong_to_the_users,
+    invite_codes_are_used_once,
+    tokens_are_taken_once,
+    users_are_disabled_and_deleted,
+    keys_are_rotated,
+    sessions_are_touched_and_expire,
+    database_is_up,
+);
+
+async fn add_user(database: &Database, username: &str) -> u32 {
+    database
+        .add_user(NewUser {
+            username: username.into(),
+            email: format!("{username}@example.com"),
+            password: "password".into(),
+            key_salt: None,
+            invite: None,
+        })
+        .await
+        .unwrap()
+}
+
+async fn add_session(database: &Database, user_id: u32, host_id: &str) -> String {
+    let token = format!("{user_id}-{host_id}");
+    database
+        .add_session(NewSession {
+            user_id,
+            host_id: host_id.into(),
+            token: token.clone(),
+            expires_at: OffsetDateTime::now_utc() + Duration::days(1),
+        })
+        .await
+        .unwrap();
+    token
+}
+
+fn new_entry(user_id: u32, client_id: &str, version: u32) -> NewEntry {
+    NewEntry {
+        client_id: client_id.into(),
+        user_id: user_id.into(),
+        version,
+        data: format!("{client_id}-{version}"),
+        kind: "entry".into(),
+        updated_at: OffsetDateTime::now_utc(),
+        deleted_at: None,
+        key_id: Some("key".into()),
+    }
+}
+
+async fn entries_sync_by_timestamp(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let other_id = add_user(&database, "bob").await;
+    let start = OffsetDateTime::now_utc();
+
+    database
+        .save_entries(&[
+            new_entry(user_id, "a", 1),
+            new_entry(user_id, "b", 1),
+            new_entry(other_id, "a", 1),
+        ])
+        .await
+        .unwrap();
+
+    let entries = database.list_entries(user_id, start).await.unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries.iter().all(|x| x.user_id == i64::from(user_id)));
+
+    let later = OffsetDateTime::now_utc() + Duration::seconds(1);
+    let mut deleted = new_entry(user_id, "b", 2);
+    deleted.updated_at = later;
+    deleted.deleted_at = Some(later);
+    database.save_entries(&[deleted]).await.unwrap();
+
+    let entries = database.list_entries(user_id, start).await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].client_id, "a");
+    assert_eq!(entries[0].data, "a-1");
+
+    let changed = database.list_changed_from(user_id, later).await.unwrap();
+    assert_eq!(changed.len(), 1);
+    assert_eq!(changed[0].client_id, "b");
+    assert_eq!(changed[0].version, 2);
+    assert!(changed[0].deleted_at.is_some());
+
+    let digests = database.list_entry_digests(user_id).await.unwrap();
+    assert_eq!(digests.len(), 2);
+
+    let found = database
+        .list_entries_by_client_ids(user_id, &["a".into(), "missing".into()])
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].client_id, "a");
+
+    let counts = database.count_records(Some(user_id)).await.unwrap();
+    assert_eq!(counts.len(), 1);
+    assert_eq!((counts[0].live, counts[0].deleted), (1, 1));
+
+    let keys = database.count_entries_by_key(user_id).await.unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].key_id.as_deref(), Some("key"));
+    assert_eq!(keys[0].count, 1);
+
+    // The host synced past the tombstone, so it can go.
+    let token = add_session(&database, user_id, "host").await;
+    database
+        .set_session_synced(&token, later + Duration::seconds(5))
+        .await
+        .unwrap();
+    assert_eq!(database.purge_tombstones(Some(user_id)).await.unwrap(), 1);
+    assert_eq!(database.list_entry_digests(user_id).await.unwrap().len(), 1);
+}
+
+async fn sessions_belong_to_the_users(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let first = add_session(&database, user_id, "first").await;
+    let second = add_session(&database, user_id, "second").await;
+
+    let user = database.get_session_user(&first).await.unwrap();
+    assert_eq!(user.id, user_id);
+    assert_eq!(user.username, "alice");
+    assert_eq!(database.get_user("alice").await.unwrap().id, user_id);
+    assert!(matches!(
+        database.get_user("nobody").await,
+        Err(DbError::NotFound)
+    ));
+
+    let session = database
+        .get_host_session(HostSession {
+            user_id,
+            host_id: "second".into(),
+        })
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(session.token, second);
+
+    let expires_at = OffsetDateTime::now_utc() + Duration::days(7);
+    database
+        .renew_session(RenewSession {
+            id: session.id,
+            token: "renewed".into(),
+            expires_at,
+        })
+        .await
+        .unwrap();
+    assert!(database.get_session(&second).await.unwrap().is_none());
+    let renewed = database.get_session("renewed").await.unwrap().unwrap();
+    assert_eq!(
+        renewed.expires_at.unix_timestamp(),
+        expires_at.unix_timestamp()
+    );
+
+    database.set_session_key(&first, "key").await.unwrap();
+    let sessions = database.list_user_sessions(user_id).await.unwrap();
+    assert_eq!(sessions.len(), 2);
+    assert_eq!(sessions[0].key_id.as_deref(), Some("key"));
+
+    assert_eq!(
+        database
+            .remove_other_sessions(user_id, &first)
+            .await
+            .unwrap(),
+        1
+    );
+    database.remove_session(&first).await.unwrap();
+    assert!(database
+        .list_user_sessions(user_id)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+async fn invite_codes_are_used_once(database: Database) {
+    for (code, expires_at) in [
+        ("open", None),
+        (
+            "expired",
+            Some(OffsetDateTime::now_utc() - Duration::hours(1)),
+        ),
+    ] {
+        database
+            .add_invite_code(NewInviteCode {
+                code: code.into(),
+                expires_at,
+            })
+            .await
+            .unwrap();
+    }
+
+    let user = |username: &str, invite: &str| NewUser {
+        username: username.into(),
+        email: format!("{username}@example.com"),
+        password: "password".into(),
+        key_salt: None,
+        invite: Some(invite.into()),
+    };
+    database.add_user(user("alice", "open")).await.unwrap();
+    for invite in ["open", "expired", "unknown"] {
+        assert!(matches!(
+            database.add_user(user("bob", invite)).await,
+            Err(DbError::NotFound)
+        ));
+    }
+    // The failed registrations leave no user behind.
+    assert_eq!(database.list_users().await.unwrap().len(), 1);
+
+    let invites = database.list_invite_codes().await.unwrap();
+    assert_eq!(invites.len(), 2);
+    assert_eq!(invites[0].used_by.as_deref(), Some("alice"));
+
+    assert!(!database.remove_invite_code("open").await.unwrap());
+    assert!(database.remove_invite_code("expired").await.unwrap());
+}
+
+async fn tokens_are_taken_once(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let expires_at = OffsetDateTime::now_utc() + Duration::hours(1);
+
+    database
+        .set_verification_token(NewVerificationToken {
+            user_id,
+            kind: VerificationKind::Verify,
+            token: "first".into(),
+            expires_at,
+        })
+        .await
+        .unwrap();
+    database
+        .set_verification_token(NewVerificationToken {
+            user_id,
+            kind: VerificationKind::Verify,
+            token: "second".into(),
+            expires_at,
+        })
+        .await
+        .unwrap();
+    let take = |token: &'static str| {
+        database.take_verification_token(user_id, VerificationKind::Verify, token)
+    };
+    assert!(!take("first").await.unwrap());
+    assert!(take("second").await.unwrap());
+    assert!(!take("second").await.unwrap());
+
+    database.set_user_verified(user_id).await.unwrap();
+    assert!(database
+        .get_user("alice")
+        .await
+        .unwrap()
+        .verified_at
+        .is_some());
+
+    database
+        .add_link_code(NewLinkCode {
+            user_id,
+            code: "link".into(),
+            data: "key".into(),
+            expires_at,
+        })
+        .await
+        .unwrap();
+    let link = database.take_link_code("link").await.unwrap().unwrap();
+    assert_eq!((link.user_id, link.data.as_str()), (user_id, "key"));
+    assert!(database.take_link_code("link").await.unwrap().is_none());
+}
+
+async fn users_are_disabled_and_deleted(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let other_id = add_user(&database, "bob").await;
+    let token = add_session(&database, user_id, "first").await;
+    add_session(&database, user_id, "second").await;
+    add_session(&database, other_id, "first").await;
+    database
+        .save_entries(&[new_entry(user_id, "a", 1)])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        database
+            .set_password(user_id, "changed", Some(&token))
+            .await
+            .unwrap(),
+        1
+    );
+    assert_eq!(
+        database.get_user("alice").await.unwrap().password,
+        "changed"
+    );
+    assert!(database.get_session(&token).await.unwrap().is_some());
+
+    let stats = database.stats().await.unwrap();
+    assert_eq!((stats.users, stats.sessions), (2, 2));
+    assert!(stats.db_size > 0);
+
+    database.set_user_disabled(user_id, true).await.unwrap();
+    let user = database.get_user("alice").await.unwrap();
+    assert!(user.disabled_at.is_some());
+    assert!(database.get_session(&token).await.unwrap().is_none());
+    assert_eq!(database.stats().await.unwrap().disabled_users, 1);
+
+    database.set_user_disabled(user_id, false).await.unwrap();
+    assert!(database
+        .get_user("alice")
+        .await
+        .unwrap()
+        .disabled_at
+        .is_none());
+
+    database.delete_user(user_id).await.unwrap();
+    let users = database.list_users().await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].id, other_id);
+    assert!(database
+        .list_entry_digests(user_id)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(database.list_sessions().await.unwrap().len(), 1);
+
+    database.vacuum().await.unwrap();
+}
+
+async fn keys_are_rotated(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let token = add_session(&database, user_id, "host").await;
+    let start = OffsetDateTime::now_utc() - Duration::seconds(1);
+
+    database.set_user_key(user_id, "old").await.unwrap();
+    assert!(database.set_user_key_check(user_id, "check").await.unwrap());
+    assert!(!database.set_user_key_check(user_id, "other").await.unwrap());
+    let user = database.get_user("alice").await.unwrap();
+    assert_eq!(user.key_id.as_deref(), Some("old"));
+    assert_eq!(user.key_check.as_deref(), Some("check"));
+
+    let mut deleted = new_entry(user_id, "b", 2);
+    deleted.deleted_at = Some(OffsetDateTime::now_utc());
+    database
+        .save_entries(&[new_entry(user_id, "a", 1), deleted])
+        .await
+        .unwrap();
+    let deleted = database.list_entries_deleted(user_id, start).await.unwrap();
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].client_id, "b");
+
+    let rotate = |entries: Vec<NewEntry>| {
+        let (database, token) = (database.clone(), token.clone());
+        async move {
+            database
+                .rotate_key(
+                    user_id,
+                    &token,
+                    "new",
+                    Some("salt"),
+                    Some("new-check"),
+                    &entries,
+                )
+                .await
+        }
+    };
+    // Every live record has to come with its current version.
+    for entries in [vec![], vec![new_entry(user_id, "a", 2)]] {
+        assert!(matches!(rotate(entries).await, Err(DbError::Conflict)));
+    }
+    let mut rotated = new_entry(user_id, "a", 1);
+    rotated.data = "rotated".into();
+    rotate(vec![rotated]).await.unwrap();
+
+    let user = database.get_user("alice").await.unwrap();
+    assert_eq!(user.key_id.as_deref(), Some("new"));
+    assert_eq!(user.key_salt.as_deref(), Some("salt"));
+    assert_eq!(user.key_check.as_deref(), Some("new-check"));
+    let session = database.get_session(&token).await.unwrap().unwrap();
+    assert_eq!(session.key_id.as_deref(), Some("new"));
+
+    let entries = database
+        .list_entries_by_client_ids(user_id, &["a".into(), "b".into()])
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 2);
+    for entry in entries {
+        assert_eq!(entry.key_id.as_deref(), Some("new"));
+        match entry.client_id.as_str() {
+            "a" => assert_eq!(entry.data, "rotated"),
+            _ => assert_eq!(entry.data, ""),
+        }
+    }
+}
+
+async fn sessions_are_touched_and_expire(database: Database) {
+    let user_id = add_user(&database, "alice").await;
+    let first = add_session(&database, user_id, "first").await;
+    let second = add_session(&database, user_id, "second").await;
+    let now = OffsetDateTime::now_utc();
+    let expiry = |token: String| {
+        let database = database.clone();
+        async move {
+            let session = database.get_session(&token).await.unwrap().unwrap();
+            (session.expires_at.unix_timestamp(), session.last_used_at)
+        }
+    };
+
+    // A session far from its expiry keeps it.
+    let (expires_at, _) = expiry(first.clone()).await;
+    database
+        .touch_session(&first, now, now + Duration::days(30))
+        .await
+        .unwrap();
+    let (touched, last_used_at) = expiry(first.clone()).await;
+    assert_eq!(touched, expires_at);
+    assert!(last_used_at.is_some());
+
+    let renewed = now + Duration::days(30);
+    database
+        .touch_session(&first, now + Duration::days(2), renewed)
+        .await
+        .unwrap();
+    assert_eq!(expiry(first.clone()).await.0, renewed.unix_timestamp());
+
+    assert_eq!(
+        database
+            .remove_host_sessions(user_id, "first")
+            .await
+            .unwrap(),
+        1
+    );
+    assert!(database.get_session(&first).await.unwrap().is_none());
+
+    let session = database.get_session(&second).await.unwrap().unwrap();
+    database
+        .renew_session(RenewSession {
+            id: session.id,
+            token: second,
+            expires_at: OffsetDateTime::UNIX_EPOCH,
+        })
+        .await
+        .unwrap();
+    assert_eq!(database.purge_expired_sessions().await.unwrap(), 1);
+    assert!(database.list_sessions().await.unwrap().is_empty());
+}
+
+async fn database_is_up(database: Database) {
+    database.ping().await.unwrap();
+    assert!(database.db_size().await.unwrap() > 0);
+}
@@ -1,11 +1,18 @@
 mod helpers;
-use dirpin_client::api_client::AuthClient;
+use dirpin_client::api_client::ApiError;
+use dirpin_client::api_client::{AuthClient, ClientOptions};
 use dirpin_client::domain::entry::Entry;
-use dirpin_common::api::{AddEntryRequest, AddSyncRequest};
+use dirpin_client::domain::host::HostId;
+use dirpin_client::encryption;
+use dirpin_client::settings::Settings;
+use dirpin_common::api::{
+    AddEntryRequest, AddSyncRequest, FetchRecordsRequest, KeyCheckRequest, RegisterRequest,
+    RotateKeyRequest,
+};
 use fake::faker::internet::en::{FreeEmail, Password, Username};
 use fake::faker::lorem::en::Word;
 use fake::Fake;
-use helpers::spawn_sync_app;
+use helpers::{spawn_sync_app, spawn_sync_app_with, TestClient};
 use time::OffsetDateTime;
 
 #[tokio::test]
@@ -24,10 +31,15 @@ async fn sync() {
 
     let register_session = dirpin_client::api_client::register(
         &server_address,
-        &username,
-        &email,
-        &password,
-        &host_id,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
     )
     .await
     .unwrap();
@@ -61,6 +73,7 @@ async fn sync() {
     let request = AddSyncRequest {
         items: vec![entry1, entry2],
         last_sync_ts: now,
+        key_id: None,
     };
 
     client.post_entries(&request).await.unwrap();
@@ -83,3 +96,645 @@ async fn sync() {
     assert_eq!(Some(data1), res1);
     assert_eq!(Some(data2), res2);
 }
+
+/// A fake username the server accepts. The generated ones can have a "." in them.
+fn build_username() -> String {
+    Username().fake::<String>().replace('.', "_")
+}
+
+fn entry_request(data: &str) -> AddEntryRequest {
+    let entry = Entry::new(data.into(), data.into(), None, helpers::build_host_id());
+    AddEntryRequest {
+        id: entry.id.to_string(),
+        version: entry.version.inner(),
+        data: data.into(),
+        kind: "entry".into(),
+        updated_at: entry.updated_at,
+        deleted_at: entry.deleted_at,
+    }
+}
+
+#[tokio::test]
+async fn key_rotation() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    let entry1 = entry_request("old-1");
+    let entry2 = entry_request("old-2");
+    let (id1, version1, updated_at1) = (entry1.id.clone(), entry1.version, entry1.updated_at);
+    let (id2, version2, updated_at2) = (entry2.id.clone(), entry2.version, entry2.updated_at);
+    client
+        .post_entries(&AddSyncRequest {
+            items: vec![entry1, entry2],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: Some("a".into()),
+        })
+        .await
+        .unwrap();
+
+    let reencrypted = |id: &str, version, updated_at, data: &str| AddEntryRequest {
+        id: id.into(),
+        version,
+        data: data.into(),
+        kind: "entry".into(),
+        updated_at,
+        deleted_at: None,
+    };
+
+    // A batch that misses a record must not replace anything.
+    let err = client
+        .rotate_key(&RotateKeyRequest {
+            key_id: "b".into(),
+            key_salt: None,
+            key_check: None,
+            items: vec![reencrypted(&id1, version1, updated_at1, "new-1")],
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Conflict(_))
+    ));
+    assert_eq!(client.status().await.unwrap().key_id.as_deref(), Some("a"));
+
+    client
+        .rotate_key(&RotateKeyRequest {
+            key_id: "b".into(),
+            key_salt: None,
+            key_check: None,
+            items: vec![
+                reencrypted(&id1, version1, updated_at1, "new-1"),
+                reencrypted(&id2, version2, updated_at2, "new-2"),
+            ],
+        })
+        .await
+        .unwrap();
+
+    let response = client.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+    let mut data = response
+        .updated
+        .iter()
+        .map(|x| x.data.as_str())
+        .collect::<Vec<_>>();
+    data.sort();
+    assert_eq!(data, vec!["new-1", "new-2"]);
+
+    // A host that did not switch yet can not upload with the old key.
+    let err = client
+        .post_entries(&AddSyncRequest {
+            items: vec![entry_request("stale")],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: Some("a".into()),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Conflict(_))
+    ));
+
+    let status = client.key_status().await.unwrap();
+    assert_eq!(status.key_id.as_deref(), Some("b"));
+    assert_eq!(status.records.len(), 1);
+    assert_eq!(status.records[0].key_id.as_deref(), Some("b"));
+    assert_eq!(status.records[0].count, 2);
+    assert!(status
+        .hosts
+        .iter()
+        .any(|x| x.host_id == host_id.as_ref() && x.key_id.as_deref() == Some("b")));
+}
+
+#[tokio::test]
+async fn key_check() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    let (key, _) = encryption::generate_encoded_key().unwrap();
+    let (wrong_key, _) = encryption::generate_encoded_key().unwrap();
+
+    // The first host stores the key check
+    let status = client.status().await.unwrap();
+    assert_eq!(status.key_check, None);
+    dirpin_client::sync::check_account_key(&client, &status, &key)
+        .await
+        .unwrap();
+
+    let status = client.status().await.unwrap();
+    assert!(status.key_check.is_some());
+    dirpin_client::sync::check_account_key(&client, &status, &key)
+        .await
+        .unwrap();
+    assert!(
+        dirpin_client::sync::check_account_key(&client, &status, &wrong_key)
+            .await
+            .is_err()
+    );
+
+    // Only the rotation can replace the key check
+    let err = client
+        .set_key_check(&KeyCheckRequest {
+            key_check: encryption::create_key_check(&wrong_key).unwrap(),
+        })
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Conflict(_))
+    ));
+}
+
+#[tokio::test]
+async fn digest_and_fetch_records() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    let live = entry_request("live");
+    let mut deleted = entry_request("deleted");
+    deleted.deleted_at = Some(OffsetDateTime::now_utc());
+    let (live_id, deleted_id) = (live.id.clone(), deleted.id.clone());
+    client
+        .post_entries(&AddSyncRequest {
+            items: vec![live, deleted],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    let digest = client.digest().await.unwrap();
+    assert_eq!(digest.items.len(), 2);
+    let item = digest
+        .items
+        .iter()
+        .find(|x| x.client_id == deleted_id)
+        .unwrap();
+    assert!(item.deleted_at.is_some());
+    assert!(!digest
+        .items
+        .iter()
+        .any(|x| x.client_id == live_id && x.deleted_at.is_some()));
+
+    let response = client
+        .fetch_records(&FetchRecordsRequest {
+            ids: vec![live_id, deleted_id.clone(), "unknown".into()],
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.updated.len(), 1);
+    assert_eq!(response.updated[0].data, "live");
+    assert_eq!(response.deleted.len(), 1);
+    assert_eq!(response.deleted[0].client_id, deleted_id);
+}
+
+#[tokio::test]
+async fn uploaded_deletions_are_saved() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username = build_username();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id();
+
+    let session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &session.session).unwrap();
+
+    let live = entry_request("live");
+    let deleted_at = OffsetDateTime::now_utc();
+    let deleted = AddEntryRequest {
+        id: live.id.clone(),
+        version: live.version + 1,
+        data: live.data.clone(),
+        kind: live.kind.clone(),
+        updated_at: deleted_at,
+        deleted_at: Some(deleted_at),
+    };
+    let (id, version) = (deleted.id.clone(), deleted.version);
+    let start = OffsetDateTime::now_utc();
+    client
+        .post_entries(&AddSyncRequest {
+            items: vec![live],
+            last_sync_ts: start,
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    // The other hosts get the deletion of the synced record.
+    let rejected = client
+        .post_entries(&AddSyncRequest {
+            items: vec![deleted],
+            last_sync_ts: start,
+            key_id: None,
+        })
+        .await
+        .unwrap();
+    assert!(rejected.is_empty());
+
+    let response = client.sync(start).await.unwrap();
+    assert!(response.updated.is_empty());
+    assert_eq!(response.deleted.len(), 1);
+    assert_eq!(response.deleted[0].client_id, id);
+    assert_eq!(response.deleted[0].version.inner(), version);
+}
+
+#[tokio::test]
+async fn tombstones_are_purged_once_every_host_synced() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host1 = helpers::build_host_id();
+    let host2 = helpers::build_host_id();
+
+    let session1 = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host1.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let session2 = dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        host2.as_ref(),
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client1 = AuthClient::new(&server_address, &session1.session).unwrap();
+    let client2 = AuthClient::new(&server_address, &session2.session).unwrap();
+
+    let live = entry_request("live");
+    let mut deleted = entry_request("deleted");
+    deleted.deleted_at = Some(OffsetDateTime::now_utc());
+    client1
+        .post_entries(&AddSyncRequest {
+            items: vec![live, deleted],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    let later = OffsetDateTime::now_utc() + time::Duration::seconds(5);
+
+    // The second host did not sync past the tombstone yet.
+    client1.sync(later).await.unwrap();
+    assert_eq!(server.database.purge_tombstones(None).await.unwrap(), 0);
+
+    client2.sync(later).await.unwrap();
+    assert_eq!(server.database.purge_tombstones(None).await.unwrap(), 1);
+
+    let digest = client1.digest().await.unwrap();
+    assert_eq!(digest.items.len(), 1);
+    assert!(digest.items[0].deleted_at.is_none());
+}
+
+#[tokio::test]
+async fn records_are_isolated_per_user() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let username = build_username();
+        let password: String = Password(3..24).fake();
+        let email: String = FreeEmail().fake();
+        let host_id = helpers::build_host_id();
+        let session = dirpin_client::api_client::register(
+            &server_address,
+            &RegisterRequest {
+                username: username.to_string(),
+                email: email.to_string(),
+                password: password.to_string(),
+                host_id: host_id.as_ref().to_string(),
+                key_salt: None,
+                invite: None,
+            },
+            &ClientOptions::default(),
+        )
+        .await
+        .unwrap();
+        clients.push(AuthClient::new(&server_address, &session.session).unwrap());
+    }
+    let (alice, mallory) = (&clients[0], &clients[1]);
+
+    let record = entry_request("alice");
+    let private = entry_request("private");
+    let (id, private_id) = (record.id.clone(), private.id.clone());
+
+    // The other user uploads the same client ids with newer versions, changing one and deleting
+    // the other one
+    let overwrite = AddEntryRequest {
+        id: id.clone(),
+        version: record.version + 1,
+        data: "mallory".into(),
+        kind: "entry".into(),
+        updated_at: OffsetDateTime::now_utc(),
+        deleted_at: None,
+    };
+    let delete = AddEntryRequest {
+        id: private_id.clone(),
+        version: private.version + 1,
+        data: "".into(),
+        kind: "entry".into(),
+        updated_at: private.updated_at,
+        deleted_at: Some(OffsetDateTime::now_utc()),
+    };
+
+    alice
+        .post_entries(&AddSyncRequest {
+            items: vec![record, private],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    mallory
+        .post_entries(&AddSyncRequest {
+            items: vec![overwrite, delete],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    let response = alice.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+    assert_eq!(response.deleted.len(), 0);
+    let mut data = response
+        .updated
+        .iter()
+        .map(|x| x.data.as_str())
+        .collect::<Vec<_>>();
+    data.sort();
+    assert_eq!(data, vec!["alice", "private"]);
+
+    let response = mallory.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+    assert_eq!(response.updated.len(), 1);
+    assert_eq!(response.updated[0].data, "mallory");
+    assert_eq!(response.deleted.len(), 1);
+
+    let digest = alice.digest().await.unwrap();
+    assert_eq!(digest.items.len(), 2);
+    assert!(digest
+        .items
+        .iter()
+        .all(|x| x.version == 1 && x.deleted_at.is_none()));
+
+    // Fetching by the id only ever returns the own records
+    let response = mallory
+        .fetch_records(&FetchRecordsRequest {
+            ids: vec![id.clone(), private_id],
+        })
+        .await
+        .unwrap();
+    assert_eq!(response.updated.len(), 1);
+    assert_eq!(response.updated[0].data, "mallory");
+
+    let response = alice
+        .fetch_records(&FetchRecordsRequest { ids: vec![id] })
+        .await
+        .unwrap();
+    assert_eq!(response.updated.len(), 1);
+    assert_eq!(response.updated[0].data, "alice");
+}
+
+#[tokio::test]
+async fn conflicting_records_are_rejected_and_the_rest_saved() {
+    let server = spawn_sync_app_with(|x| x.metrics = true).await.unwrap();
+    let server_address = server.address();
+
+    let username = build_username();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id();
+    let session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.as_ref().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &session.session).unwrap();
+
+    let mut changed = entry_request("server");
+    changed.version = 2;
+    let id = changed.id.clone();
+    let stale_updated_at = changed.updated_at - time::Duration::seconds(10);
+    let rejected = client
+        .post_entries(&AddSyncRequest {
+            items: vec![changed],
+            last_sync_ts: OffsetDateTime::now_utc(),
+            key_id: None,
+        })
+        .await
+        .unwrap();
+    assert!(rejected.is_empty());
+
+    // An older copy of the record and a new record
+    let stale = AddEntryRequest {
+        id: id.clone(),
+        version: 1,
+        data: "stale".into(),
+        kind: "entry".into(),
+        updated_at: stale_updated_at,
+        deleted_at: None,
+    };
+    let new = entry_request("new");
+    let rejected = client
+        .post_entries(&AddSyncRequest {
+            items: vec![stale, new],
+            last_sync_ts: OffsetDateTime::UNIX_EPOCH,
+            key_id: None,
+        })
+        .await
+        .unwrap();
+
+    assert_eq!(rejected.len(), 1);
+    assert_eq!(rejected[0].client_id, id);
+    assert_eq!(rejected[0].version, 2);
+    assert_eq!(rejected[0].data, "server");
+    assert!(rejected[0].deleted_at.is_none());
+
+    let response = client.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+    let mut data = response
+        .updated
+        .iter()
+        .map(|x| x.data.as_str())
+        .collect::<Vec<_>>();
+    data.sort();
+    assert_eq!(data, vec!["new", "server"]);
+
+    let metrics = reqwest::get(format!("{server_address}/metrics"))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    for line in [
+        "dirpin_sync_rejected_records_total 1",
+        r#"dirpin_records{kind="entry",state="live"} 2"#,
+        "dirpin_active_sessions 1",
+        r#"dirpin_sync_payload_bytes_count{direction="upload"} 2"#,
+        r#"dirpin_sync_payload_bytes_count{direction="download"} 1"#,
+    ] {
+        assert!(metrics.lines().any(|x| x == line), "{line} in {metrics}");
+    }
+}
+
+#[tokio::test]
+async fn unreachable_server_keeps_the_changes_queued() {
+    // The time of the last sync is kept in the data directory.
+    let data_dir = tempfile::TempDir::new().unwrap();
+    std::env::set_var("XDG_DATA_HOME", data_dir.path());
+    std::fs::create_dir_all(dirpin_common::utils::data_dir()).unwrap();
+
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: build_username(),
+            email: FreeEmail().fake(),
+            password: Password(3..24).fake(),
+            host_id: helpers::build_host_id().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let mut client = TestClient::build().await.unwrap();
+    let dir = tempfile::TempDir::new().unwrap();
+    client.settings.key_path = dir.path().join("key").to_str().unwrap().into();
+    client.settings.session_path = dir.path().join("session").to_str().unwrap().into();
+    client.settings.network_retries = 0;
+    std::fs::write(&client.settings.session_path, &register_session.session).unwrap();
+    encryption::create_key(&client.settings).unwrap();
+
+    let data = Word().fake::<String>();
+    // The generated host ids are not always valid in the client database.
+    let host_id = HostId::custom("user".into(), "host".into());
+    let entry = Entry::new(data.clone(), data, None, host_id);
+    client.database.save(&entry).await.unwrap();
+
+    // Nothing listens on the port of a dropped listener.
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    client.set_server_address(&format!("http://127.0.0.1:{port}"));
+
+    dirpin_client::sync::sync(&client.settings, &client.database, false)
+        .await
+        .unwrap();
+    assert_eq!(Settings::last_sync().unwrap(), OffsetDateTime::UNIX_EPOCH);
+
+    // The next sync with the server back uploads the queued entry.
+    client.set_server_address(&server_address);
+    dirpin_client::sync::sync(&client.settings, &client.database, false)
+        .await
+        .unwrap();
+    assert_ne!(Settings::last_sync().unwrap(), OffsetDateTime::UNIX_EPOCH);
+
+    let remote = AuthClient::new(&server_address, &register_session.session).unwrap();
+    let response = remote.sync(OffsetDateTime::UNIX_EPOCH).await.unwrap();
+    assert_eq!(response.updated.len(), 1);
+    assert_eq!(response.updated[0].kind, "entry");
+}
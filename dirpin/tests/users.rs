@@ -1,8 +1,20 @@
 mod helpers;
-use dirpin_client::api_client::AuthClient;
+use dirpin_client::api_client::{ApiError, AuthClient, ClientOptions};
+use dirpin_client::encryption;
+use dirpin_client::link::LinkToken;
+use dirpin_common::api::{
+    ChangePasswordRequest, CreateLinkRequest, DeleteAccountRequest, RegisterRequest,
+    RevokeSessionsRequest,
+};
+use dirpin_server::database::DbError;
+use dirpin_server::models::{NewEntry, NewInviteCode, RenewSession, VerificationKind};
+use dirpin_server::settings::MailTransport;
 use fake::faker::internet::en::{FreeEmail, Password, Username};
 use fake::Fake;
-use helpers::spawn_sync_app;
+use helpers::{spawn_sync_app, spawn_sync_app_with, TestServer};
+use std::net::SocketAddr;
+use time::OffsetDateTime;
+use tokio::net::TcpListener;
 
 #[tokio::test]
 async fn registration() {
@@ -15,29 +27,815 @@ async fn registration() {
     let host_id = helpers::build_host_id().to_string();
 
     let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+    let status = client.status().await.unwrap();
+
+    assert_eq!(status.username, username);
+    assert_eq!(status.version, helpers::VERSION);
+
+    let login_session = dirpin_client::api_client::login(
         &server_address,
         &username,
-        &email,
         &password,
         &host_id,
+        &ClientOptions::default(),
     )
     .await
     .unwrap();
 
-    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+    let client = AuthClient::new(&server_address, &login_session.session).unwrap();
     let status = client.status().await.unwrap();
 
     assert_eq!(status.username, username);
     assert_eq!(status.version, helpers::VERSION);
+    assert_eq!(login_session.key_salt, None);
+}
+
+#[tokio::test]
+async fn login_returns_key_salt() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
 
-    let login_session =
-        dirpin_client::api_client::login(&server_address, &username, &password, &host_id)
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+    let salt = dirpin_client::encryption::generate_key_salt();
+
+    dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: Some(salt.to_string()),
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    let other_host_id = helpers::build_host_id().to_string();
+    let login_session = dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &other_host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(login_session.key_salt, Some(salt));
+}
+
+#[tokio::test]
+async fn link_new_host() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    let (key, _) = encryption::generate_encoded_key().unwrap();
+    let (secret, _) = encryption::generate_encoded_key().unwrap();
+    let res = client
+        .create_link(&CreateLinkRequest {
+            data: encryption::wrap_key(&key, &secret).unwrap(),
+        })
+        .await
+        .unwrap();
+    let token = LinkToken {
+        code: res.code,
+        secret,
+    }
+    .to_string();
+
+    let new_host_id = helpers::build_host_id().to_string();
+    let token = token.parse::<LinkToken>().unwrap();
+    let (session, linked_key) = dirpin_client::link::claim_link(
+        &server_address,
+        &token,
+        &new_host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(linked_key, key);
+
+    let status = AuthClient::new(&server_address, &session)
+        .unwrap()
+        .status()
+        .await
+        .unwrap();
+    assert_eq!(status.username, username);
+
+    // The token works only once
+    let err = dirpin_client::link::claim_link(
+        &server_address,
+        &token,
+        &new_host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Other { status, .. }) if *status == 404
+    ));
+}
+
+#[tokio::test]
+async fn list_renew_and_revoke_sessions() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username: String = Username().fake();
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_ids = (0..3)
+        .map(|_| helpers::build_host_id().to_string())
+        .collect::<Vec<_>>();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_ids[0].to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let mut tokens = vec![register_session.session];
+    for host_id in &host_ids[1..] {
+        let res = dirpin_client::api_client::login(
+            &server_address,
+            &username,
+            &password,
+            host_id,
+            &ClientOptions::default(),
+        )
+        .await
+        .unwrap();
+        tokens.push(res.session);
+    }
+    let clients = tokens
+        .iter()
+        .map(|x| AuthClient::new(&server_address, x).unwrap())
+        .collect::<Vec<_>>();
+
+    let sessions = clients[0].sessions().await.unwrap().sessions;
+    assert_eq!(sessions.len(), 3);
+    let current = sessions.iter().find(|x| x.current).unwrap();
+    assert_eq!(current.host_id, host_ids[0]);
+    assert!(current.last_used_at.is_some());
+
+    // A session close to its expiry is extended when it is used
+    let soon = OffsetDateTime::now_utc() + time::Duration::days(1);
+    set_sessions_expiry(&server, soon).await;
+    let sessions = clients[0].sessions().await.unwrap().sessions;
+    let current = sessions.iter().find(|x| x.current).unwrap();
+    assert!(current.expires_at > soon + time::Duration::weeks(4));
+    assert!(sessions
+        .iter()
+        .filter(|x| !x.current)
+        .all(|x| x.expires_at <= soon));
+
+    let res = clients[0]
+        .revoke_sessions(&RevokeSessionsRequest {
+            host_id: Some(host_ids[1].clone()),
+            all_others: false,
+        })
+        .await
+        .unwrap();
+    assert_eq!(res.revoked, 1);
+    let err = clients[1].status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
+
+    let res = clients[2]
+        .revoke_sessions(&RevokeSessionsRequest {
+            host_id: None,
+            all_others: true,
+        })
+        .await
+        .unwrap();
+    assert_eq!(res.revoked, 1);
+    let sessions = clients[2].sessions().await.unwrap().sessions;
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].host_id, host_ids[2]);
+
+    // Logging out ends the session on the server
+    clients[2].logout().await.unwrap();
+    let err = clients[2].sessions().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
+}
+
+/// Move the expiry of all the sessions on the server.
+async fn set_sessions_expiry(server: &TestServer, expires_at: OffsetDateTime) {
+    for session in server.database.list_sessions().await.unwrap() {
+        server
+            .database
+            .renew_session(RenewSession {
+                id: session.id,
+                token: session.token,
+                expires_at,
+            })
             .await
             .unwrap();
+    }
+}
 
-    let client = AuthClient::new(&server_address, &login_session.session).unwrap();
-    let status = client.status().await.unwrap();
+fn new_entry(user_id: u32, client_id: &str, deleted_at: Option<OffsetDateTime>) -> NewEntry {
+    NewEntry {
+        client_id: client_id.into(),
+        user_id: user_id.into(),
+        version: 1,
+        data: "data".into(),
+        kind: "entry".into(),
+        updated_at: OffsetDateTime::UNIX_EPOCH,
+        deleted_at,
+        key_id: None,
+    }
+}
+
+/// The code in the first line of the last mail to the address.
+fn mailed_token(server: &TestServer, to: &str) -> String {
+    let mail = server
+        .mail
+        .mails()
+        .into_iter()
+        .rfind(|x| x.to == to)
+        .unwrap();
+    let line = mail.body.lines().next().unwrap();
+    line.split_whitespace().last().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn verify_email_and_reset_password() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username = Username().fake::<String>().replace('.', "_");
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    assert!(client.verify("wrong").await.is_err());
+    client.verify(&mailed_token(&server, &email)).await.unwrap();
+    let user = server.database.get_user(&username).await.unwrap();
+    assert!(user.verified_at.is_some());
+
+    // The unknown users get the same answer and no mail
+    let sent = server.mail.mails().len();
+    dirpin_client::api_client::send_password_reset(
+        &server_address,
+        "nobody",
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(server.mail.mails().len(), sent);
+
+    dirpin_client::api_client::send_password_reset(
+        &server_address,
+        &username,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let token = mailed_token(&server, &email);
+    let new_password: String = Password(3..24).fake();
+    assert!(dirpin_client::api_client::reset_password(
+        &server_address,
+        &username,
+        "wrong",
+        &new_password,
+        &ClientOptions::default()
+    )
+    .await
+    .is_err());
+    dirpin_client::api_client::reset_password(
+        &server_address,
+        &username,
+        &token,
+        &new_password,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    // The code works only once and the hosts are logged out
+    assert!(dirpin_client::api_client::reset_password(
+        &server_address,
+        &username,
+        &token,
+        &new_password,
+        &ClientOptions::default()
+    )
+    .await
+    .is_err());
+    let err = client.status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
 
+    assert!(dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &host_id,
+        &ClientOptions::default()
+    )
+    .await
+    .is_err());
+    dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &new_password,
+        &host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+async fn required_verification_blocks_unverified_accounts() {
+    let server = spawn_sync_app_with(|x| x.require_verification = true)
+        .await
+        .unwrap();
+    let server_address = server.address();
+
+    let username = Username().fake::<String>().replace('.', "_");
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+
+    let register_session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    let client = AuthClient::new(&server_address, &register_session.session).unwrap();
+
+    let err = client.status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Other { status, .. }) if *status == reqwest::StatusCode::FORBIDDEN
+    ));
+
+    // A lost code can be replaced
+    let first = mailed_token(&server, &email);
+    client.send_verification().await.unwrap();
+    let second = mailed_token(&server, &email);
+    assert!(client.verify(&first).await.is_err());
+    client.verify(&second).await.unwrap();
+
+    let status = client.status().await.unwrap();
     assert_eq!(status.username, username);
-    assert_eq!(status.version, helpers::VERSION);
+}
+
+#[tokio::test]
+async fn password_reset_answers_the_same_when_the_mail_fails() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut server = TestServer::build("127.0.0.1", port).await.unwrap();
+    // The mails can't be appended to a directory.
+    let dir = tempfile::TempDir::new().unwrap();
+    server.settings.mail_transport = MailTransport::File;
+    server.settings.mail_file = dir.path().to_str().unwrap().into();
+    let router = dirpin_server::make_router(&server.settings, server.database.clone())
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+    });
+    let server_address = server.address();
+
+    let username = Username().fake::<String>().replace('.', "_");
+    dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: FreeEmail().fake(),
+            password: Password(3..24).fake(),
+            host_id: helpers::build_host_id().to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    for username in [username.as_str(), "nobody"] {
+        dirpin_client::api_client::send_password_reset(
+            &server_address,
+            username,
+            &ClientOptions::default(),
+        )
+        .await
+        .unwrap();
+    }
+}
+
+#[tokio::test]
+async fn change_password_and_delete_account() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username = Username().fake::<String>().replace('.', "_");
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+    let other_host_id = helpers::build_host_id().to_string();
+
+    let session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap()
+    .session;
+    let other_session = dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &other_host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap()
+    .session;
+    let client = AuthClient::new(&server_address, &session).unwrap();
+    let other_client = AuthClient::new(&server_address, &other_session).unwrap();
+
+    let new_password: String = Password(3..24).fake();
+    assert!(client
+        .change_password(&ChangePasswordRequest {
+            current_password: "wrong".into(),
+            new_password: new_password.clone(),
+        })
+        .await
+        .is_err());
+    let revoked = client
+        .change_password(&ChangePasswordRequest {
+            current_password: password.clone(),
+            new_password: new_password.clone(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(revoked, 1);
+    client.status().await.unwrap();
+    assert!(other_client.status().await.is_err());
+    assert!(dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &host_id,
+        &ClientOptions::default()
+    )
+    .await
+    .is_err());
+
+    let user = server.database.get_user(&username).await.unwrap();
+    server
+        .database
+        .save_entries(&[new_entry(user.id, "entry-1", None)])
+        .await
+        .unwrap();
+
+    assert!(client
+        .delete_account(&DeleteAccountRequest {
+            password: password.clone(),
+        })
+        .await
+        .is_err());
+    client
+        .delete_account(&DeleteAccountRequest {
+            password: new_password.clone(),
+        })
+        .await
+        .unwrap();
+
+    let err = client.status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
+    assert!(matches!(
+        server.database.get_user(&username).await,
+        Err(DbError::NotFound)
+    ));
+    assert!(server
+        .database
+        .count_records(Some(user.id))
+        .await
+        .unwrap()
+        .is_empty());
+    assert!(server.database.list_sessions().await.unwrap().is_empty());
+    // The verification mailed at the registration went with the user.
+    assert!(!server
+        .database
+        .take_verification_token(
+            user.id,
+            VerificationKind::Verify,
+            &mailed_token(&server, &email)
+        )
+        .await
+        .unwrap());
+}
+
+#[tokio::test]
+async fn closed_registration_needs_an_invite() {
+    let server = spawn_sync_app_with(|x| x.open_registration = false)
+        .await
+        .unwrap();
+    let server_address = server.address();
+
+    let expired = OffsetDateTime::now_utc() - time::Duration::hours(1);
+    for (code, expires_at) in [("valid", None), ("expired", Some(expired))] {
+        server
+            .database
+            .add_invite_code(NewInviteCode {
+                code: code.into(),
+                expires_at,
+            })
+            .await
+            .unwrap();
+    }
+
+    let register = |username: String, invite: Option<&'static str>| {
+        let server_address = server_address.clone();
+        async move {
+            let password: String = Password(3..24).fake();
+            let email: String = FreeEmail().fake();
+            let host_id = helpers::build_host_id().to_string();
+            dirpin_client::api_client::register(
+                &server_address,
+                &RegisterRequest {
+                    username: username.to_string(),
+                    email: email.to_string(),
+                    password: password.to_string(),
+                    host_id: host_id.to_string(),
+                    key_salt: None,
+                    invite: invite.map(Into::into),
+                },
+                &ClientOptions::default(),
+            )
+            .await
+        }
+    };
+
+    let username = Username().fake::<String>().replace('.', "_");
+    assert!(register(username.clone(), None).await.is_err());
+    assert!(register(username.clone(), Some("expired")).await.is_err());
+    assert!(register(username.clone(), Some("unknown")).await.is_err());
+    register(username.clone(), Some("valid")).await.unwrap();
+
+    // Every code registers one user
+    let other = Username().fake::<String>().replace('.', "_");
+    assert!(register(other, Some("valid")).await.is_err());
+
+    let invites = server.database.list_invite_codes().await.unwrap();
+    let used = invites.iter().find(|x| x.code == "valid").unwrap();
+    assert_eq!(used.used_by.as_deref(), Some(username.as_str()));
+    assert!(!server.database.remove_invite_code("valid").await.unwrap());
+    assert!(server.database.remove_invite_code("expired").await.unwrap());
+}
+
+#[tokio::test]
+async fn auth_requests_are_rate_limited() {
+    let server = spawn_sync_app_with(|x| x.auth_rate_limit = 2)
+        .await
+        .unwrap();
+    let server_address = server.address();
+    let host_id = helpers::build_host_id().to_string();
+
+    for _ in 0..2 {
+        let err = dirpin_client::api_client::login(
+            &server_address,
+            "nobody",
+            "wrong",
+            &host_id,
+            &ClientOptions::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(!err.to_string().contains("429"));
+    }
+    let err = dirpin_client::api_client::login(
+        &server_address,
+        "nobody",
+        "wrong",
+        &host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Other { status, .. }) if *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    ));
+
+    // Guessing the link codes and mailing the reset codes share the limit
+    let err = dirpin_client::api_client::claim_link(
+        &server_address,
+        "unknown",
+        &host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Other { status, .. }) if *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    ));
+    let err = dirpin_client::api_client::send_password_reset(
+        &server_address,
+        "nobody",
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Other { status, .. }) if *status == reqwest::StatusCode::TOO_MANY_REQUESTS
+    ));
+
+    // The other endpoints are not limited
+    dirpin_client::api_client::health_check(&server_address, &ClientOptions::default())
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn admin_disables_users_and_reads_stats() {
+    let server = spawn_sync_app().await.unwrap();
+    let server_address = server.address();
+
+    let username = Username().fake::<String>().replace('.', "_");
+    let password: String = Password(3..24).fake();
+    let email: String = FreeEmail().fake();
+    let host_id = helpers::build_host_id().to_string();
+
+    let session = dirpin_client::api_client::register(
+        &server_address,
+        &RegisterRequest {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: password.to_string(),
+            host_id: host_id.to_string(),
+            key_salt: None,
+            invite: None,
+        },
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap()
+    .session;
+    let client = AuthClient::new(&server_address, &session).unwrap();
+
+    let user = server.database.get_user(&username).await.unwrap();
+    server
+        .database
+        .save_entries(&[
+            new_entry(user.id, "entry-1", None),
+            new_entry(user.id, "entry-2", Some(OffsetDateTime::now_utc())),
+        ])
+        .await
+        .unwrap();
+
+    let stats = server.database.stats().await.unwrap();
+    assert_eq!(stats.users, 1);
+    assert_eq!(stats.sessions, 1);
+    assert_eq!(stats.records.len(), 1);
+    assert_eq!((stats.records[0].live, stats.records[0].deleted), (1, 1));
+    assert!(stats.db_size > 0);
+
+    server
+        .database
+        .set_user_disabled(user.id, true)
+        .await
+        .unwrap();
+    assert_eq!(server.database.stats().await.unwrap().disabled_users, 1);
+    let err = client.status().await.unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<ApiError>(),
+        Some(ApiError::Unauthorized(_))
+    ));
+    assert!(dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &host_id,
+        &ClientOptions::default()
+    )
+    .await
+    .is_err());
+
+    server
+        .database
+        .set_user_disabled(user.id, false)
+        .await
+        .unwrap();
+    dirpin_client::api_client::login(
+        &server_address,
+        &username,
+        &password,
+        &host_id,
+        &ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+
+    set_sessions_expiry(&server, OffsetDateTime::UNIX_EPOCH).await;
+    assert_eq!(server.database.list_sessions().await.unwrap().len(), 1);
+    assert_eq!(server.database.purge_expired_sessions().await.unwrap(), 1);
+    assert!(server.database.list_sessions().await.unwrap().is_empty());
+
+    server.database.vacuum().await.unwrap();
 }